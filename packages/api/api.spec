@@ -21,6 +21,7 @@ Source6: migration-tmpfiles.conf
 Source7: settings-applier.service
 Source8: data-store-version
 Source9: migrator.service
+Source10: kernel-modules-modprobe-conf
 BuildRequires: gcc-%{_cross_target}
 BuildRequires: %{_cross_os}glibc-devel
 BuildRequires: %{_cross_os}systemd-devel
@@ -70,18 +71,45 @@ Summary: Dynamic setting generator for kubernetes
 %description -n %{_cross_os}pluto
 %{summary}.
 
+%package -n %{_cross_os}regiondog
+Summary: Dynamic setting generator for the AWS region
+%description -n %{_cross_os}regiondog
+%{summary}.
+
 %package -n %{_cross_os}thar-be-settings
 Summary: Applies changed settings to a Thar system
 Requires: %{_cross_os}apiserver = %{version}-%{release}
 %description -n %{_cross_os}thar-be-settings
 %{summary}.
 
+%package -n %{_cross_os}thar-be-updates
+Summary: Runs update commands for the Thar API server
+Requires: %{_cross_os}apiserver = %{version}-%{release}
+Requires: %{_cross_os}signpost
+Requires: %{_cross_os}updog
+%description -n %{_cross_os}thar-be-updates
+%{summary}.
+
 %package -n %{_cross_os}servicedog
 Summary: Manipulates systemd units based on setting changes
 Requires: %{_cross_os}apiserver = %{version}-%{release}
 %description -n %{_cross_os}servicedog
 %{summary}.
 
+%package -n %{_cross_os}corndog
+Summary: Applies kernel settings
+Requires: %{_cross_os}apiserver = %{version}-%{release}
+Requires: %{_cross_os}kmod
+%description -n %{_cross_os}corndog
+%{summary}.
+
+%package -n %{_cross_os}host-containers-manager
+Summary: Manages host containers based on setting changes
+Requires: %{_cross_os}apiserver = %{version}-%{release}
+Requires: %{_cross_os}host-containers
+%description -n %{_cross_os}host-containers-manager
+%{summary}.
+
 %package -n %{_cross_os}storewolf
 Summary: Data store creator
 Requires: %{_cross_os}apiserver = %{version}-%{release}
@@ -107,9 +135,9 @@ Summary: Commits settings from user data, defaults, and generators at boot
 
 for p in \
   apiclient \
-  moondog netdog sundog pluto bork \
-  thar-be-settings servicedog storewolf settings-committer \
-  migration/migrator ;
+  moondog netdog sundog pluto regiondog bork \
+  thar-be-settings thar-be-updates servicedog corndog host-containers storewolf \
+  settings-committer migration/migrator ;
 do
   %cargo_build --path %{workspace_dir}/${p}
 done
@@ -125,10 +153,10 @@ done
 %install
 install -d %{buildroot}%{_cross_bindir}
 for p in \
-  apiclient apiserver \
-  moondog netdog sundog pluto bork \
-  thar-be-settings servicedog storewolf settings-committer \
-  migrator ;
+  apiclient apiserver datastore-convert datastore-fsck \
+  moondog netdog sundog pluto regiondog bork \
+  thar-be-settings thar-be-updates servicedog corndog host-containers storewolf \
+  settings-committer migrator ;
 do
   install -p -m 0755 bin/${p} %{buildroot}%{_cross_bindir}
 done
@@ -141,6 +169,9 @@ install -p -m 0644 \
 install -d %{buildroot}%{_cross_datadir}/thar
 install -p -m 0644 %{S:8} %{buildroot}%{_cross_datadir}/thar
 
+install -d %{buildroot}%{_cross_templatedir}
+install -p -m 0644 %{S:10} %{buildroot}%{_cross_templatedir}
+
 install -d %{buildroot}%{migration_dir}
 for m in bin/migrate_* ; do
   [ -f "${m}" ] || continue
@@ -152,6 +183,8 @@ install -p -m 0644 %{S:6} %{buildroot}%{_cross_tmpfilesdir}/migration.conf
 
 %files -n %{_cross_os}apiserver
 %{_cross_bindir}/apiserver
+%{_cross_bindir}/datastore-convert
+%{_cross_bindir}/datastore-fsck
 %{_cross_unitdir}/apiserver.service
 %{_cross_unitdir}/migrator.service
 %{_cross_datadir}/thar/data-store-version
@@ -173,6 +206,9 @@ install -p -m 0644 %{S:6} %{buildroot}%{_cross_tmpfilesdir}/migration.conf
 %files -n %{_cross_os}pluto
 %{_cross_bindir}/pluto
 
+%files -n %{_cross_os}regiondog
+%{_cross_bindir}/regiondog
+
 %files -n %{_cross_os}bork
 %{_cross_bindir}/bork
 
@@ -180,9 +216,19 @@ install -p -m 0644 %{S:6} %{buildroot}%{_cross_tmpfilesdir}/migration.conf
 %{_cross_bindir}/thar-be-settings
 %{_cross_unitdir}/settings-applier.service
 
+%files -n %{_cross_os}thar-be-updates
+%{_cross_bindir}/thar-be-updates
+
 %files -n %{_cross_os}servicedog
 %{_cross_bindir}/servicedog
 
+%files -n %{_cross_os}corndog
+%{_cross_bindir}/corndog
+%{_cross_templatedir}/kernel-modules-modprobe-conf
+
+%files -n %{_cross_os}host-containers-manager
+%{_cross_bindir}/host-containers
+
 %files -n %{_cross_os}storewolf
 %{_cross_bindir}/storewolf
 %{_cross_unitdir}/storewolf.service
@@ -15,8 +15,9 @@ License: ASL 2.0
 URL: https://%{goimport}
 Source0: https://%{goimport}/archive/v%{gover}/%{gorepo}-%{gover}.tar.gz
 Source1: containerd.service
-Source2: containerd-config.toml
+Source2: containerd-config-toml
 Source3: containerd-tmpfiles.conf
+Source4: containerd-registry-ca-bundle
 BuildRequires: git
 BuildRequires: gcc-%{_cross_target}
 BuildRequires: %{_cross_os}glibc-devel
@@ -52,8 +53,9 @@ install -p -m 0755 ctr %{buildroot}%{_cross_bindir}
 install -d %{buildroot}%{_cross_unitdir}
 install -p -m 0644 %{S:1} %{buildroot}%{_cross_unitdir}/containerd.service
 
-install -d %{buildroot}%{_cross_factorydir}%{_cross_sysconfdir}/containerd
-install -p -m 0644 %{S:2} %{buildroot}%{_cross_factorydir}%{_cross_sysconfdir}/containerd/config.toml
+install -d %{buildroot}%{_cross_templatedir}
+install -p -m 0644 %{S:2} %{buildroot}%{_cross_templatedir}/containerd-config-toml
+install -p -m 0644 %{S:4} %{buildroot}%{_cross_templatedir}/containerd-registry-ca-bundle
 
 install -d %{buildroot}%{_cross_tmpfilesdir}
 install -p -m 0644 %{S:3} %{buildroot}%{_cross_tmpfilesdir}/containerd.conf
@@ -63,8 +65,9 @@ install -p -m 0644 %{S:3} %{buildroot}%{_cross_tmpfilesdir}/containerd.conf
 %{_cross_bindir}/containerd-shim
 %{_cross_bindir}/ctr
 %{_cross_unitdir}/containerd.service
-%dir %{_cross_factorydir}%{_cross_sysconfdir}/containerd
-%{_cross_factorydir}%{_cross_sysconfdir}/containerd/config.toml
+%dir %{_cross_templatedir}
+%{_cross_templatedir}/containerd-config-toml
+%{_cross_templatedir}/containerd-registry-ca-bundle
 %{_cross_tmpfilesdir}/containerd.conf
 
 %changelog
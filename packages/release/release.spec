@@ -12,17 +12,17 @@ Source99: release-tmpfiles.conf
 
 # FIXME What should own system-level file templates?
 Source200: hostname.template
-Source201: host-containers-systemd-unit-admin.template
-Source202: host-containers-systemd-unit-control.template
+Source203: proxy-env.template
 
 Source1000: eth0.xml
 Source1002: configured.target
 Source1003: host-containerd.service
-Source1004: host-containerd-config.toml
+Source1004: host-containerd-config-toml
 Source1005: local.mount
 Source1006: prepare-local.service
 Source1007: var.mount
 Source1008: opt.mount
+Source1009: host-containers@.service
 
 BuildArch: noarch
 Requires: %{_cross_os}apiclient
@@ -30,6 +30,7 @@ Requires: %{_cross_os}apiserver
 Requires: %{_cross_os}bash
 Requires: %{_cross_os}ca-certificates
 Requires: %{_cross_os}chrony
+Requires: %{_cross_os}containerd
 Requires: %{_cross_os}coreutils
 Requires: %{_cross_os}dbus-broker
 Requires: %{_cross_os}filesystem
@@ -43,17 +44,21 @@ Requires: %{_cross_os}netdog
 Requires: %{_cross_os}signpost
 Requires: %{_cross_os}sundog
 Requires: %{_cross_os}pluto
+Requires: %{_cross_os}regiondog
 Requires: %{_cross_os}storewolf
 Requires: %{_cross_os}servicedog
+Requires: %{_cross_os}corndog
 Requires: %{_cross_os}settings-committer
 Requires: %{_cross_os}systemd
 Requires: %{_cross_os}thar-be-settings
+Requires: %{_cross_os}thar-be-updates
 Requires: %{_cross_os}migration
 Requires: %{_cross_os}updog
 Requires: %{_cross_os}util-linux
 Requires: %{_cross_os}preinit
 Requires: %{_cross_os}wicked
 Requires: %{_cross_os}host-containers
+Requires: %{_cross_os}host-containers-manager
 
 %description
 %{summary}.
@@ -73,9 +78,6 @@ install -p -m 0644 %{S:10} %{S:11} %{buildroot}%{_cross_factorydir}%{_cross_sysc
 install -d %{buildroot}%{_cross_factorydir}%{_cross_sysconfdir}/wicked/ifconfig
 install -p -m 0644 %{S:1000} %{buildroot}%{_cross_factorydir}%{_cross_sysconfdir}/wicked/ifconfig
 
-install -d %{buildroot}%{_cross_factorydir}%{_cross_sysconfdir}/host-containerd
-install -p -m 0644 %{S:1004} %{buildroot}%{_cross_factorydir}%{_cross_sysconfdir}/host-containerd/config.toml
-
 install -d %{buildroot}%{_cross_tmpfilesdir}
 install -p -m 0644 %{S:99} %{buildroot}%{_cross_tmpfilesdir}/release.conf
 
@@ -87,19 +89,19 @@ VERSION_ID=%{version}
 EOF
 
 install -d %{buildroot}%{_cross_unitdir}
-install -p -m 0644 %{S:1002} %{S:1003} %{S:1005} %{S:1006} %{S:1007} %{S:1008} %{buildroot}%{_cross_unitdir}
+install -p -m 0644 %{S:1002} %{S:1003} %{S:1005} %{S:1006} %{S:1007} %{S:1008} \
+  %{S:1009} %{buildroot}%{_cross_unitdir}
 
 install -d %{buildroot}%{_cross_templatedir}
 install -p -m 0644 %{S:200} %{buildroot}%{_cross_templatedir}/hostname
-install -p -m 0644 %{S:201} %{buildroot}%{_cross_templatedir}/host-containers-systemd-unit-admin
-install -p -m 0644 %{S:202} %{buildroot}%{_cross_templatedir}/host-containers-systemd-unit-control
+install -p -m 0644 %{S:203} %{buildroot}%{_cross_templatedir}/proxy-env
+install -p -m 0644 %{S:1004} %{buildroot}%{_cross_templatedir}/host-containerd-config-toml
 
 %files
 %{_cross_bindir}/login
 %{_cross_factorydir}%{_cross_sysconfdir}/hosts
 %{_cross_factorydir}%{_cross_sysconfdir}/nsswitch.conf
 %{_cross_factorydir}%{_cross_sysconfdir}/wicked/ifconfig/eth0.xml
-%{_cross_factorydir}%{_cross_sysconfdir}/host-containerd/config.toml
 %{_cross_tmpfilesdir}/release.conf
 %{_cross_libdir}/os-release
 %{_cross_unitdir}/configured.target
@@ -108,9 +110,10 @@ install -p -m 0644 %{S:202} %{buildroot}%{_cross_templatedir}/host-containers-sy
 %{_cross_unitdir}/prepare-local.service
 %{_cross_unitdir}/var.mount
 %{_cross_unitdir}/opt.mount
+%{_cross_unitdir}/host-containers@.service
 %dir %{_cross_templatedir}
 %{_cross_templatedir}/hostname
-%{_cross_templatedir}/host-containers-systemd-unit-admin
-%{_cross_templatedir}/host-containers-systemd-unit-control
+%{_cross_templatedir}/proxy-env
+%{_cross_templatedir}/host-containerd-config-toml
 
 %changelog
@@ -25,14 +25,14 @@ Getting settings:
 
 ```
 apiclient -m GET -u /settings
-apiclient -m GET -u /settings/pending
+apiclient -m GET -u /tx
 ```
 
 Changing settings:
 
 ```
 apiclient -X PATCH -u /settings -d '{"timezone": "OldLosAngeles"}'
-apiclient -m POST -u /settings/commit_and_apply
+apiclient -m POST -u /tx/commit_and_apply
 ```
 
 ## apiclient library
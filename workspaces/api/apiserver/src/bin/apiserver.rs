@@ -1,5 +1,4 @@
 #[macro_use]
-
 extern crate log;
 
 use snafu::{ensure, ResultExt};
@@ -7,6 +6,7 @@ use std::env;
 use std::path::Path;
 use std::process;
 
+use apiserver::datastore::Backend;
 use apiserver::serve;
 
 const DEFAULT_BIND_PATH: &str = "/run/api.sock";
@@ -34,7 +34,9 @@ struct Args {
     verbosity: usize,
     color: stderrlog::ColorChoice,
     datastore_path: String,
+    datastore_backend: Backend,
     socket_path: String,
+    policy_path: Option<String>,
 }
 
 /// Informs the user about proper usage of the program and exits.
@@ -43,11 +45,17 @@ fn usage() -> ! {
     eprintln!(
         r"Usage: {}
             --datastore-path PATH
+            [ --datastore-backend filesystem|sqlite ]
             [ --socket-path PATH ]
+            [ --auth-policy PATH ]
             [ --no-color ]
             [ --verbose --verbose ... ]
-    Socket path defaults to {}",
-        program_name, DEFAULT_BIND_PATH
+    Socket path defaults to {}
+    Datastore backend defaults to {}
+    If no authorization policy is given, all clients may make any request",
+        program_name,
+        DEFAULT_BIND_PATH,
+        Backend::default()
     );
     process::exit(2);
 }
@@ -61,7 +69,9 @@ fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
 /// Parses user arguments into an Args structure.
 fn parse_args(args: env::Args) -> Args {
     let mut datastore_path = None;
+    let mut datastore_backend = None;
     let mut socket_path = None;
+    let mut policy_path = None;
     let mut verbosity = 0;
     let mut color = stderrlog::ColorChoice::Auto;
 
@@ -79,6 +89,14 @@ fn parse_args(args: env::Args) -> Args {
                 )
             }
 
+            "--datastore-backend" => {
+                let backend = iter.next().unwrap_or_else(|| {
+                    usage_msg("Did not give argument to --datastore-backend")
+                });
+                datastore_backend =
+                    Some(backend.parse().unwrap_or_else(|e| usage_msg(format!("{}", e))))
+            }
+
             "--socket-path" => {
                 socket_path = Some(
                     iter.next()
@@ -86,6 +104,13 @@ fn parse_args(args: env::Args) -> Args {
                 )
             }
 
+            "--auth-policy" => {
+                policy_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --auth-policy")),
+                )
+            }
+
             _ => usage(),
         }
     }
@@ -94,7 +119,9 @@ fn parse_args(args: env::Args) -> Args {
         verbosity,
         color,
         datastore_path: datastore_path.unwrap_or_else(|| usage()),
+        datastore_backend: datastore_backend.unwrap_or_default(),
         socket_path: socket_path.unwrap_or_else(|| DEFAULT_BIND_PATH.to_string()),
+        policy_path,
     }
 }
 
@@ -126,9 +153,16 @@ fn main() -> Result<()> {
         _ => "",
     };
     info!(
-        "Starting server at {} with {} thread{} and datastore at {}",
-        &args.socket_path, threads, threads_suffix, &args.datastore_path,
+        "Starting server at {} with {} thread{} and {} datastore at {}",
+        &args.socket_path, threads, threads_suffix, args.datastore_backend, &args.datastore_path,
     );
 
-    serve(&args.socket_path, &args.datastore_path, threads).context(error::Server)
+    serve(
+        &args.socket_path,
+        &args.datastore_path,
+        args.datastore_backend,
+        args.policy_path.as_ref().map(Path::new),
+        threads,
+    )
+    .context(error::Server)
 }
\ No newline at end of file
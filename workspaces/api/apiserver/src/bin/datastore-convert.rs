@@ -0,0 +1,179 @@
+/*!
+# Background
+
+datastore-convert copies a data store from the filesystem layout, where each key is a file under
+`live` and `pending`, into a single SQLite database file, so an existing host can switch the API
+server to `--datastore-backend sqlite`.
+
+Run it while the API server is stopped.  The database is written as DATABASE_FILE inside the
+destination directory, which can be the same directory as the source; the source files are left
+alone so you can switch back.
+*/
+
+#[macro_use]
+extern crate log;
+
+use snafu::{ensure, ResultExt};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use apiserver::datastore::backend::copy_data_store;
+use apiserver::datastore::sqlite::DATABASE_FILE;
+use apiserver::datastore::{FilesystemDataStore, SqliteDataStore};
+
+type Result<T> = std::result::Result<T, error::Error>;
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Logger setup error: {}", source))]
+        Logger { source: log::SetLoggerError },
+
+        #[snafu(display("Source datastore '{}' does not exist", path.display()))]
+        NonexistentDatastore { path: PathBuf },
+
+        #[snafu(display("Destination database '{}' already exists", path.display()))]
+        DatabaseExists { path: PathBuf },
+
+        #[snafu(display("Unable to create destination directory '{}': {}", path.display(), source))]
+        CreateDirectory {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Unable to open database '{}': {}", path.display(), source))]
+        OpenDatabase {
+            path: PathBuf,
+            source: apiserver::datastore::Error,
+        },
+
+        #[snafu(display("Unable to copy data store: {}", source))]
+        Copy { source: apiserver::datastore::Error },
+    }
+}
+
+struct Args {
+    from: PathBuf,
+    to: PathBuf,
+    verbosity: usize,
+}
+
+/// Informs the user about proper usage of the program and exits.
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            --from PATH
+            --to PATH
+            [ --verbose --verbose ... ]
+    Reads the filesystem datastore at --from and writes {} in directory --to",
+        program_name, DATABASE_FILE
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parses user arguments into an Args structure.
+fn parse_args(args: env::Args) -> Args {
+    let mut from = None;
+    let mut to = None;
+    let mut verbosity = 2;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "-v" | "--verbose" => verbosity += 1,
+
+            "--from" => {
+                from = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --from")),
+                )
+            }
+
+            "--to" => {
+                to = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --to")),
+                )
+            }
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        from: from.unwrap_or_else(|| usage()).into(),
+        to: to.unwrap_or_else(|| usage()).into(),
+        verbosity,
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    ensure!(
+        args.from.exists(),
+        error::NonexistentDatastore { path: &args.from }
+    );
+
+    // Refuse to mix converted data into an existing database.
+    let database_path = args.to.join(DATABASE_FILE);
+    ensure!(
+        !database_path.exists(),
+        error::DatabaseExists {
+            path: &database_path
+        }
+    );
+    fs::create_dir_all(&args.to).context(error::CreateDirectory { path: &args.to })?;
+
+    let from = FilesystemDataStore::new(&args.from);
+    let mut to = SqliteDataStore::open(&database_path).context(error::OpenDatabase {
+        path: &database_path,
+    })?;
+
+    info!(
+        "Converting {} to {}",
+        args.from.display(),
+        database_path.display()
+    );
+    if let Err(e) = copy_data_store(&from, &mut to) {
+        // Don't leave a partial database behind for the API server to pick up.
+        drop(to);
+        let _ = fs::remove_file(&database_path);
+        return Err(e).context(error::Copy);
+    }
+    info!("Conversion complete");
+    Ok(())
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    let args = parse_args(env::args());
+
+    if let Err(e) = stderrlog::new()
+        .module(module_path!())
+        .verbosity(args.verbosity)
+        .init()
+        .context(error::Logger)
+    {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
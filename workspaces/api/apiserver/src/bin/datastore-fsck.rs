@@ -0,0 +1,177 @@
+/*!
+# Background
+
+datastore-fsck checks a filesystem data store for problems that would otherwise only show up when
+a request touches a bad key, like files that aren't valid keys, or values that can't be
+deserialized.  It also checks the chain of version links that points to the data store, and
+warns about metadata for data keys that don't exist.
+
+Give it the same `--datastore-path` as the API server, i.e. the `current` link, or the data store
+directory itself to skip checking the links.  With `--quarantine`, files with problems are moved
+into a `quarantine` directory next to `live` and `pending`, keeping their relative paths, so they
+can be examined or restored by hand.  Run it while the API server is stopped.
+
+It exits 0 if no problems (other than warnings) remain, and 3 if any do.
+*/
+
+#[macro_use]
+extern crate log;
+
+use snafu::ResultExt;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use apiserver::datastore::fsck::{self, Problem};
+
+type Result<T> = std::result::Result<T, error::Error>;
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Logger setup error: {}", source))]
+        Logger { source: log::SetLoggerError },
+
+        #[snafu(display("Unable to check version links: {}", source))]
+        CheckLinks { source: apiserver::datastore::Error },
+
+        #[snafu(display("Unable to check data store: {}", source))]
+        CheckDatastore { source: apiserver::datastore::Error },
+
+        #[snafu(display("Unable to quarantine files: {}", source))]
+        Quarantine { source: apiserver::datastore::Error },
+    }
+}
+
+/// Exit code for when problems remain in the data store.
+const PROBLEMS_REMAIN: i32 = 3;
+
+struct Args {
+    datastore_path: PathBuf,
+    quarantine: bool,
+    verbosity: usize,
+}
+
+/// Informs the user about proper usage of the program and exits.
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            --datastore-path PATH
+            [ --quarantine ]
+            [ --verbose --verbose ... ]",
+        program_name
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parses user arguments into an Args structure.
+fn parse_args(args: env::Args) -> Args {
+    let mut datastore_path = None;
+    let mut quarantine = false;
+    let mut verbosity = 2;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "-v" | "--verbose" => verbosity += 1,
+
+            "--quarantine" => quarantine = true,
+
+            "--datastore-path" => {
+                datastore_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --datastore-path")),
+                )
+            }
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        datastore_path: datastore_path.unwrap_or_else(|| usage()).into(),
+        quarantine,
+        verbosity,
+    }
+}
+
+/// Checks the data store and quarantines problems if requested.  Returns the problems that
+/// remain.
+fn run(args: &Args) -> Result<Vec<Problem>> {
+    // A data store directory can be given directly, skipping the link checks.
+    let is_link = fs::symlink_metadata(&args.datastore_path)
+        .map(|metadata| metadata.file_type().is_symlink())
+        .unwrap_or(false);
+    let (mut problems, resolved) = if is_link {
+        fsck::check_links(&args.datastore_path).context(error::CheckLinks)?
+    } else {
+        (Vec::new(), None)
+    };
+
+    // If the links are broken, we still check whatever the given path leads to.
+    let datastore_path = resolved.unwrap_or_else(|| args.datastore_path.clone());
+    info!("Checking data store at {}", datastore_path.display());
+    let found = fsck::check_datastore(&datastore_path).context(error::CheckDatastore)?;
+
+    if args.quarantine {
+        let moved = fsck::quarantine(&datastore_path, &found).context(error::Quarantine)?;
+        for path in &moved {
+            info!("Quarantined {}", path.display());
+        }
+        problems.extend(found.into_iter().filter(|p| !p.kind.quarantinable()));
+    } else {
+        problems.extend(found);
+    }
+
+    Ok(problems)
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    let args = parse_args(env::args());
+
+    if let Err(e) = stderrlog::new()
+        .module(module_path!())
+        .verbosity(args.verbosity)
+        .init()
+        .context(error::Logger)
+    {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+
+    let problems = match run(&args) {
+        Ok(problems) => problems,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    for problem in &problems {
+        if problem.kind.is_warning() {
+            warn!("{}", problem);
+        } else {
+            error!("{}", problem);
+        }
+    }
+    if problems.iter().any(|p| !p.kind.is_warning()) {
+        process::exit(PROBLEMS_REMAIN);
+    }
+    if problems.is_empty() {
+        info!("No problems found");
+    }
+}
@@ -0,0 +1,267 @@
+//! Programs that let configuration choose the DataStore implementation, like the API server and
+//! migration-helper, use the types here to open and hold whichever one was chosen.
+//!
+//! Both backends live in a data store directory, so the versioned directory layout (and the
+//! `current` symlink pointing into it) works the same either way.  SqliteDataStore keeps its
+//! single file, DATABASE_FILE, inside that directory.
+
+use snafu::OptionExt;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::sqlite::DATABASE_FILE;
+use super::{error, Committed, DataStore, FilesystemDataStore, Key, Result, SqliteDataStore};
+
+/// The DataStore implementations that can be chosen by configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Filesystem,
+    Sqlite,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Filesystem
+    }
+}
+
+impl FromStr for Backend {
+    type Err = error::Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "filesystem" => Ok(Backend::Filesystem),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => error::UnknownBackend { name: input }.fail(),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Backend::Filesystem => "filesystem",
+            Backend::Sqlite => "sqlite",
+        }
+        .fmt(f)
+    }
+}
+
+/// A data store using whichever backend was chosen.
+#[derive(Debug)]
+pub enum AnyDataStore {
+    Filesystem(FilesystemDataStore),
+    Sqlite(SqliteDataStore),
+}
+
+impl AnyDataStore {
+    /// Opens the data store in the given directory with the given backend.
+    pub fn open<P: AsRef<Path>>(backend: Backend, path: P) -> Result<AnyDataStore> {
+        let path = path.as_ref();
+        match backend {
+            Backend::Filesystem => Ok(AnyDataStore::Filesystem(FilesystemDataStore::new(path))),
+            Backend::Sqlite => Ok(AnyDataStore::Sqlite(SqliteDataStore::open(
+                path.join(DATABASE_FILE),
+            )?)),
+        }
+    }
+
+    /// Finishes or rolls back a commit that was interrupted; see FilesystemDataStore::recover.
+    /// SQLite does this itself when the database is opened.
+    pub fn recover(&mut self) -> Result<()> {
+        match self {
+            AnyDataStore::Filesystem(ds) => ds.recover(),
+            AnyDataStore::Sqlite(_) => Ok(()),
+        }
+    }
+}
+
+/// Calls the same method on whichever backend we hold.
+macro_rules! delegate {
+    ($self:ident, $ds:ident => $call:expr) => {
+        match $self {
+            AnyDataStore::Filesystem($ds) => $call,
+            AnyDataStore::Sqlite($ds) => $call,
+        }
+    };
+}
+
+impl DataStore for AnyDataStore {
+    fn key_populated(&self, key: &Key, committed: &Committed) -> Result<bool> {
+        delegate!(self, ds => ds.key_populated(key, committed))
+    }
+
+    fn list_populated_keys<S: AsRef<str>>(
+        &self,
+        prefix: S,
+        committed: &Committed,
+    ) -> Result<HashSet<Key>> {
+        delegate!(self, ds => ds.list_populated_keys(prefix, committed))
+    }
+
+    fn list_populated_metadata<S1, S2>(
+        &self,
+        prefix: S1,
+        metadata_key_name: &Option<S2>,
+        committed: &Committed,
+    ) -> Result<HashMap<Key, HashSet<Key>>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        delegate!(self, ds => ds.list_populated_metadata(prefix, metadata_key_name, committed))
+    }
+
+    fn get_key(&self, key: &Key, committed: &Committed) -> Result<Option<String>> {
+        delegate!(self, ds => ds.get_key(key, committed))
+    }
+
+    fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()> {
+        delegate!(self, ds => ds.set_key(key, value, committed))
+    }
+
+    fn set_keys<S1, S2>(&mut self, pairs: &HashMap<S1, S2>, committed: &Committed) -> Result<()>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        delegate!(self, ds => ds.set_keys(pairs, committed))
+    }
+
+    fn get_metadata_raw(
+        &self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<Option<String>> {
+        delegate!(self, ds => ds.get_metadata_raw(metadata_key, data_key, committed))
+    }
+
+    fn set_metadata<S: AsRef<str>>(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        value: S,
+        committed: &Committed,
+    ) -> Result<()> {
+        delegate!(self, ds => ds.set_metadata(metadata_key, data_key, value, committed))
+    }
+
+    fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        delegate!(self, ds => ds.commit_transaction(transaction))
+    }
+
+    fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        delegate!(self, ds => ds.delete_transaction(transaction))
+    }
+
+    fn list_transactions(&self) -> Result<HashSet<String>> {
+        delegate!(self, ds => ds.list_transactions())
+    }
+}
+
+/// Copies all live data, pending transactions, and metadata from one data store to another, for
+/// example to convert between backends.  Metadata is copied as set, without inheritance, for live
+/// and for each pending transaction.
+pub fn copy_data_store<S, D>(from: &S, to: &mut D) -> Result<()>
+where
+    S: DataStore,
+    D: DataStore,
+{
+    let live = from.get_prefix("", &Committed::Live)?;
+    debug!("Copying {} live keys", live.len());
+    to.set_keys(&live, &Committed::Live)?;
+    copy_metadata(from, to, &Committed::Live)?;
+
+    for tx in from.list_transactions()? {
+        let pending = Committed::Pending { tx };
+        let data = from.get_prefix("", &pending)?;
+        debug!("Copying {} pending keys from {:?}", data.len(), pending);
+        to.set_keys(&data, &pending)?;
+        copy_metadata(from, to, &pending)?;
+    }
+
+    Ok(())
+}
+
+/// Copies the metadata for the given committed state from one data store to another.
+fn copy_metadata<S, D>(from: &S, to: &mut D, committed: &Committed) -> Result<()>
+where
+    S: DataStore,
+    D: DataStore,
+{
+    let metadata = from.list_populated_metadata("", &None as &Option<&str>, committed)?;
+    for (data_key, metadata_keys) in metadata {
+        for metadata_key in metadata_keys {
+            let value = from
+                .get_metadata_raw(&metadata_key, &data_key, committed)?
+                .context(error::ListedMetaNotPresent {
+                    meta_key: metadata_key.as_ref(),
+                    data_key: data_key.as_ref(),
+                })?;
+            to.set_metadata(&metadata_key, &data_key, value, committed)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::datastore::{Key, KeyType};
+    use tempfile::TempDir;
+
+    #[test]
+    fn copy_between_backends() {
+        let dir = TempDir::new().unwrap();
+        let mut from = AnyDataStore::open(Backend::Filesystem, dir.path().join("fs")).unwrap();
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        let mk = Key::new(KeyType::Meta, "affected-services").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        from.set_key(&k1, "1", &Committed::Live).unwrap();
+        from.set_key(&k2, "2", &pending).unwrap();
+        from.set_metadata(&mk, &k1, "[\"a\"]", &Committed::Live)
+            .unwrap();
+        from.set_metadata(&mk, &k2, "[\"b\"]", &pending).unwrap();
+
+        let sqlite_dir = dir.path().join("sqlite");
+        std::fs::create_dir(&sqlite_dir).unwrap();
+        let mut to = AnyDataStore::open(Backend::Sqlite, &sqlite_dir).unwrap();
+        copy_data_store(&from, &mut to).unwrap();
+
+        assert_eq!(to.get_key(&k1, &Committed::Live).unwrap(), Some("1".into()));
+        assert_eq!(to.get_key(&k2, &pending).unwrap(), Some("2".into()));
+        assert_eq!(to.get_key(&k2, &Committed::Live).unwrap(), None);
+        assert_eq!(
+            to.get_metadata_raw(&mk, &k1, &Committed::Live).unwrap(),
+            Some("[\"a\"]".into())
+        );
+        assert_eq!(
+            to.get_metadata_raw(&mk, &k2, &pending).unwrap(),
+            Some("[\"b\"]".into())
+        );
+        assert_eq!(
+            to.get_metadata_raw(&mk, &k2, &Committed::Live).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_backend() {
+        assert_eq!("sqlite".parse::<Backend>().unwrap(), Backend::Sqlite);
+        assert_eq!(
+            "filesystem".parse::<Backend>().unwrap(),
+            Backend::Filesystem
+        );
+        "lmdb".parse::<Backend>().unwrap_err();
+    }
+}
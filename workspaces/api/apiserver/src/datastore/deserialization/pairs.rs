@@ -1,12 +1,15 @@
-use serde::de::{value::MapDeserializer, IntoDeserializer, Visitor};
+use serde::de::{value::MapDeserializer, Error as _, IntoDeserializer, Visitor};
 use serde::{forward_to_deserialize_any, Deserialize};
 use snafu::ResultExt;
 use std::borrow::Borrow;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::hash::Hash;
 
 use super::{error, Error, Result};
-use crate::datastore::{deserializer_for_scalar, ScalarDeserializer, KEY_SEPARATOR};
+use crate::datastore::key::{split_first_segment, split_index, unquote_segment};
+use crate::datastore::{
+    deserializer_for_scalar, merge_list_elements, ScalarDeserializer, Value, KEY_SEPARATOR,
+};
 
 pub fn from_map<'de, S1, S2, T, BH>(map: &'de HashMap<S1, S2, BH>) -> Result<T>
 where
@@ -61,11 +64,12 @@ where
 
 /// ValueDeserializer is what interfaces with serde's MapDeserializer, which expects to receive a
 /// key name and a deserializer for it on each iteration, i.e. for each field.  Based on whether
-/// the key name has a dot, we know if we need to recurse again or just deserialize a final value,
-/// which we represent as the two arms of the enum.
+/// the key name has a dot, we know if we need to recurse again or just deserialize a final value.
+/// Lists can also have elements set individually, which we combine before deserializing.
 enum ValueDeserializer<'de, S1, S2, BH> {
     Scalar(ScalarDeserializer<'de>),
     Compound(CompoundDeserializer<'de, S1, S2, BH>),
+    List(ListDeserializer<'de>),
 }
 
 impl<'de, S1, S2, BH> serde::de::Deserializer<'de> for ValueDeserializer<'de, S1, S2, BH>
@@ -92,6 +96,7 @@ where
             ValueDeserializer::Compound(compound_deserializer) => {
                 compound_deserializer.deserialize_map(visitor)
             }
+            ValueDeserializer::List(list_deserializer) => list_deserializer.deserialize(visitor),
         }
     }
 
@@ -111,6 +116,7 @@ where
             ValueDeserializer::Compound(compound_deserializer) => {
                 compound_deserializer.deserialize_option(visitor)
             }
+            ValueDeserializer::List(_) => visitor.visit_some(self),
         }
     }
 
@@ -134,6 +140,34 @@ where
     }
 }
 
+/// ListDeserializer handles a list whose elements were set individually, like "a[0]" and "a[1]",
+/// possibly along with the whole list "a", whose elements they replace or append to.
+struct ListDeserializer<'de> {
+    /// The key of the whole list, for error messages.
+    key: String,
+    /// The serialized whole list, if set.
+    list: Option<&'de str>,
+    /// The serialized elements that were set individually, by index.
+    elements: BTreeMap<usize, &'de str>,
+}
+
+impl<'de> ListDeserializer<'de> {
+    fn deserialize<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        trace!(
+            "Merging {} list elements into '{}'",
+            self.elements.len(),
+            self.key
+        );
+        let list =
+            merge_list_elements(&self.key, self.list, &self.elements).map_err(Error::custom)?;
+        serde::Deserializer::deserialize_any(Value::Array(list), visitor)
+            .context(error::DeserializeScalar)
+    }
+}
+
 /// CompoundDeserializer is our main structure that drives serde's MapDeserializer and stores the
 /// state we need to understand the recursive structure of the output.
 struct CompoundDeserializer<'de, S1, S2, BH> {
@@ -217,7 +251,10 @@ where
         // give it an iterator that yields (key, deserializer) pairs.  The nested deserializers
         // have the appropriate 'path' and a subset of 'keys' so they can do their job.
         visitor.visit_map(MapDeserializer::new(self.keys.iter().filter_map(|key| {
-            let struct_name = key.split('.').next().unwrap();
+            // Segments can be quoted, so we can't just split on dots.  The name we give serde is
+            // unquoted, but paths keep the quotes so they match the input keys.
+            let (segment, rest) = split_first_segment(key);
+            let (struct_name, index) = split_index(segment);
             trace!("Visiting key '{}', struct name '{}'", key, struct_name);
 
             // If we have a path, add a separator, otherwise start with an empty string.
@@ -230,7 +267,12 @@ where
             let new_path = old_path + struct_name;
             trace!("New path: {}", &new_path);
 
-            if key.contains('.') {
+            // Elements are "name[N]"; this finds their indexes among our keys.
+            let element_prefix = struct_name.to_owned() + "[";
+            let is_list = index.is_some()
+                || (rest.is_none() && self.keys.iter().any(|k| k.starts_with(&element_prefix)));
+
+            if rest.is_some() {
                 if structs_done.contains(&struct_name) {
                     // We've handled this structure with a recursive call, so we're done.
                     trace!("Already handled struct '{}', skipping", struct_name);
@@ -256,7 +298,7 @@ where
                         keys
                     );
                     Some((
-                        struct_name.to_owned(),
+                        unquote_segment(struct_name).to_owned(),
                         ValueDeserializer::Compound(CompoundDeserializer::new(
                             self.map,
                             keys,
@@ -264,6 +306,32 @@ where
                         )),
                     ))
                 }
+            } else if is_list {
+                // We see the list once for each element, and maybe for the whole list.
+                if structs_done.contains(&struct_name) {
+                    trace!("Already handled list '{}', skipping", struct_name);
+                    return None;
+                }
+                structs_done.insert(struct_name);
+
+                let mut elements = BTreeMap::new();
+                for element_key in self.keys.iter() {
+                    if let (name, Some(i)) = split_index(element_key) {
+                        if name == struct_name {
+                            let element_path = format!("{}[{}]", new_path, i);
+                            elements.insert(i, self.map.get(&element_path)?.as_ref());
+                        }
+                    }
+                }
+                trace!("Key '{}' is a list with elements {:?}", new_path, elements);
+                Some((
+                    unquote_segment(struct_name).to_owned(),
+                    ValueDeserializer::List(ListDeserializer {
+                        list: self.map.get(&new_path).map(|val| val.as_ref()),
+                        key: new_path,
+                        elements,
+                    }),
+                ))
             } else {
                 // No dot, so we have a scalar; hand the data to a scalar deserializer.
                 trace!(
@@ -273,7 +341,7 @@ where
                 );
                 let val = self.map.get(&new_path)?;
                 Some((
-                    key.to_owned(),
+                    unquote_segment(key).to_owned(),
                     ValueDeserializer::Scalar(deserializer_for_scalar(val.as_ref())),
                 ))
             }
@@ -415,6 +483,54 @@ mod test {
         );
     }
 
+    #[test]
+    fn quoted_map_keys() {
+        let map = &hashmap! {
+            r#"x."app.io/role""#.to_string() => "\"worker\"".to_string(),
+            r#"x."a.b".c"#.to_string() => "\"nested\"".to_string(),
+        };
+        let x: HashMap<String, serde_json::Value> =
+            from_map_with_prefix(Some("x".to_string()), map).unwrap();
+        assert_eq!(
+            x,
+            hashmap! {
+                "app.io/role".to_string() => serde_json::json!("worker"),
+                "a.b".to_string() => serde_json::json!({"c": "nested"}),
+            }
+        );
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct D {
+        list: Vec<u8>,
+        other: Option<Vec<u8>>,
+    }
+
+    #[test]
+    fn list_elements() {
+        // Elements replace or append to the whole list
+        let d: D = from_map(&hashmap! {
+            "d.list".to_string() => "[1, 2]".to_string(),
+            "d.list[1]".to_string() => "20".to_string(),
+            "d.list[2]".to_string() => "30".to_string(),
+            "d.other[0]".to_string() => "4".to_string(),
+        })
+        .unwrap();
+        assert_eq!(
+            d,
+            D {
+                list: vec![1, 20, 30],
+                other: Some(vec![4]),
+            }
+        );
+
+        // Elements past the end of the list
+        let bad: Result<D, Error> = from_map(&hashmap! {
+            "d.list[1]".to_string() => "1".to_string(),
+        });
+        bad.unwrap_err();
+    }
+
     #[derive(Debug, Deserialize, PartialEq)]
     struct Bad {
         id: u64,
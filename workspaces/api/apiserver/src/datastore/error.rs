@@ -2,7 +2,7 @@ use snafu::Snafu;
 use std::io;
 use std::path::PathBuf;
 
-use super::{serialization, ScalarError};
+use super::{deserialization, serialization, ScalarError};
 
 /// Possible errors from datastore operations.
 #[derive(Debug, Snafu)]
@@ -17,6 +17,15 @@ pub enum Error {
     #[snafu(display("Error serializing scalar {}: {} ", given, source))]
     SerializeScalar { given: String, source: ScalarError },
 
+    #[snafu(display("Error deserializing {}: {} ", given, source))]
+    Deserialization {
+        given: String,
+        source: deserialization::Error,
+    },
+
+    #[snafu(display("Error deserializing scalar {}: {} ", given, source))]
+    DeserializeScalar { given: String, source: ScalarError },
+
     #[snafu(display("Key would traverse outside data store: {}", name))]
     PathTraversal { name: String },
 
@@ -60,6 +69,40 @@ pub enum Error {
 
     #[snafu(display("Key name beyond maximum length {}: {}", name, max))]
     KeyTooLong { name: String, max: usize },
+
+    #[snafu(display("Key path would be beyond maximum length {}: {}", max, name))]
+    KeyPathTooLong { name: String, max: usize },
+
+    #[snafu(display(
+        "Transaction name '{}' has invalid format, should match regex: {}",
+        name,
+        pattern
+    ))]
+    InvalidTransaction { name: String, pattern: regex::Regex },
+
+    #[snafu(display("List element '{}' is past the end of its list of length {}", key, len))]
+    ListIndex { key: String, len: usize },
+
+    #[snafu(display("Can't set elements of '{}', which is not a list: {}", key, source))]
+    NotAList { key: String, source: ScalarError },
+
+    #[snafu(display("Database error during {}: {}", op, source))]
+    Database {
+        op: String,
+        source: rusqlite::Error,
+    },
+
+    #[snafu(display("Another thread poisoned the database lock by panicking"))]
+    DatabaseLock,
+
+    #[snafu(display("Unable to parse data store fixture: {}", source))]
+    FixtureParse { source: toml::de::Error },
+
+    #[snafu(display("Unknown data store backend '{}'", name))]
+    UnknownBackend { name: String },
+
+    #[snafu(display("Snapshot has its own pending transaction '{}', needed for import", tx))]
+    SnapshotTransaction { tx: String },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
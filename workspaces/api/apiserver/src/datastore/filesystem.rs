@@ -1,88 +1,402 @@
+use sha2::{Digest, Sha256};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io;
-use std::path::{self, Path, PathBuf};
+use std::path::{Path, PathBuf};
 use walkdir::{DirEntry, WalkDir};
 
 use super::key::{Key, KeyType, KEY_SEPARATOR};
-use super::{error, Commited, DataStore, Result};
+use super::{
+    commits_key, error, fold_list_elements, valid_transaction, Committed, DataStore, Result,
+    COMMIT_PREFIX,
+};
 
 const METADATA_KEY_PREFIX: char = '.';
 
+// Key segments can be longer than a filename can be, so longer names are replaced by a hash,
+// starting with a character that key segments can't start with.  A sidecar file next to it, with
+// the suffix added, holds the real name.
+const MAX_FILENAME_LENGTH: usize = 255;
+pub(super) const LONG_NAME_PREFIX: char = '#';
+pub(super) const LONG_NAME_SUFFIX: &str = "#name";
+
+// Hashing keeps each filename short, but a key with many segments can still make a path longer
+// than PATH_MAX, including its terminating NUL.  During a commit, live keys are copied under
+// COMMIT_STAGING_DIR and moved to STAGED_OLD_LIVE, which makes their paths up to this much longer
+// than under LIVE_DIR, so we leave room for that.
+const MAX_PATH_LENGTH: usize = 4096 - 1;
+const COMMIT_PATH_GROWTH: usize = 16;
+
+// Names of the live and pending trees under the base path.
+pub(super) const LIVE_DIR: &str = "live";
+pub(super) const PENDING_DIR: &str = "pending";
+
+// Names used under the base path while committing; see commit_transaction for how they're used.
+const COMMIT_STAGING_DIR: &str = "commit.tmp";
+const COMMIT_STAGED_DIR: &str = "commit";
+// Names used inside the commit staging directory.
+const STAGED_LIVE: &str = "live";
+const STAGED_OLD_LIVE: &str = "live.old";
+const STAGED_TRANSACTION: &str = "transaction";
+
 #[derive(Debug)]
-pub struct FileSystemDataStore {
+pub struct FilesystemDataStore {
+    base_path: PathBuf,
     live_path: PathBuf,
-    pending_path: PathBuf,
+    pending_base_path: PathBuf,
 }
 
-impl FileSystemDataStore {
-    pub fn new<P: AsRef<Path>>(base_path: P) -> FileSystemDataStore {
-        FileSystemDataStore {
-            live_path: base_path.as_ref().join("live"),
-            pending_path: base_path.as_ref().join("pending"),
+impl FilesystemDataStore {
+    pub fn new<P: AsRef<Path>>(base_path: P) -> FilesystemDataStore {
+        FilesystemDataStore {
+            base_path: base_path.as_ref().to_path_buf(),
+            live_path: base_path.as_ref().join(LIVE_DIR),
+            pending_base_path: base_path.as_ref().join(PENDING_DIR),
         }
     }
 
-    /// Returns the appropriate filesystem path for pending or live data.
-    fn base_path(&self, committed: Commited) -> &PathBuf {
+    /// Finishes or rolls back a commit that was interrupted, for example by a crash or power
+    /// loss.  This should be called before using the data store after startup; it's a no-op if
+    /// there was no interrupted commit.
+    ///
+    /// If the new live tree wasn't fully staged, the staging directory is removed and live and
+    /// pending are left as they were.  If it was fully staged, the commit is rolled forward.
+    pub fn recover(&mut self) -> Result<()> {
+        let staging_path = self.base_path.join(COMMIT_STAGING_DIR);
+        if staging_path.exists() {
+            warn!(
+                "Rolling back incomplete commit staged at {}",
+                staging_path.display()
+            );
+            remove_dir_if_exists(&staging_path)?;
+            sync_dir(&self.base_path)?;
+        }
+
+        let staged_path = self.base_path.join(COMMIT_STAGED_DIR);
+        if staged_path.exists() {
+            warn!(
+                "Finishing interrupted commit staged at {}",
+                staged_path.display()
+            );
+            self.finish_commit()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the appropriate filesystem path for pending or live data.  Each pending
+    /// transaction gets its own directory under the pending base path.
+    fn base_path(&self, committed: &Committed) -> Result<PathBuf> {
         match committed {
-            Commited::Pending => &self.pending_path,
-            Commited::Live => &self.live_path,
+            Committed::Pending { tx } => {
+                // Transaction names become directory names, so hold them to the same standard
+                // as a key segment; this keeps them from traversing out of the pending area.
+                Ok(self.pending_base_path.join(valid_transaction(tx)?))
+            }
+            Committed::Live => Ok(self.live_path.clone()),
         }
     }
 
-    /// Returns the appropriate path on the filesystem for the given data key.
-    fn data_path(&self, key: &key, commited: Commited) -> Result<PathBuf> {
-        let base_path = self.base_path(commited);
+    /// Returns the appropriate file on the filesystem for the given data key.
+    fn data_path(&self, key: &Key, committed: &Committed) -> Result<KeyFile> {
+        let base_path = self.base_path(committed)?;
+        data_path_under(&base_path, key)
+    }
+
+    /// Returns the appropriate file on the filesystem for the given metadata key.
+    fn metadata_path(
+        &self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<KeyFile> {
+        let base_path = self.base_path(committed)?;
+        metadata_path_under(&base_path, metadata_key, data_key)
+    }
+
+    /// Builds a complete copy of the live tree with the given pending data and metadata applied,
+    /// and marks it as staged.  Live and pending aren't touched, so a failure here leaves the data
+    /// store as it was before the commit.
+    fn stage_commit(
+        &self,
+        transaction: &str,
+        pending_data: &HashMap<Key, String>,
+        pending_metadata: &HashMap<Key, HashMap<Key, String>>,
+    ) -> Result<()> {
+        let staging_path = self.base_path.join(COMMIT_STAGING_DIR);
+        let staged_path = self.base_path.join(COMMIT_STAGED_DIR);
 
-        // turn dot-separated key into slash-separated path suffix
-        let path_suffix = key.replace(KEY_SEPARATOR, &path::MAIN_SEPARATOR.to_string());
+        // Clear out anything left from an earlier failed attempt.
+        remove_dir_if_exists(&staging_path)?;
 
-        // Make path from base + prefix
+        // The first commit into a new data store has no live tree to copy.
+        let new_live_path = staging_path.join(STAGED_LIVE);
+        if self.live_path.exists() {
+            debug!("Copying live keys to {}", staging_path.display());
+            copy_tree(&self.live_path, &new_live_path)?;
+        } else {
+            fs::create_dir_all(&new_live_path).context(error::Io {
+                path: &new_live_path,
+            })?;
+        }
+
+        debug!("Writing pending keys to {}", new_live_path.display());
+        for (key, value) in pending_data {
+            data_path_under(&new_live_path, key)?.write(value)?;
+        }
+        for (data_key, metadata) in pending_metadata {
+            for (metadata_key, value) in metadata {
+                metadata_path_under(&new_live_path, metadata_key, data_key)?.write(value)?;
+            }
+        }
+
+        // Record the transaction so recovery can remove it if we're interrupted after this.
+        let transaction_path = staging_path.join(STAGED_TRANSACTION);
+        fs::write(&transaction_path, transaction).context(error::Io {
+            path: &transaction_path,
+        })?;
+
+        // Everything has to be on disk before we mark the commit as staged, or we could roll
+        // forward to a partial tree after a power loss.
+        sync_tree(&staging_path)?;
+        fs::rename(&staging_path, &staged_path).context(error::Io { path: &staged_path })?;
+        sync_dir(&self.base_path)
+    }
+
+    /// Swaps a staged live tree into place and removes the transaction that was committed.  Each
+    /// step checks whether it was already done, so this can be used to roll forward a commit that
+    /// was interrupted at any point after staging.
+    fn finish_commit(&self) -> Result<()> {
+        let staged_path = self.base_path.join(COMMIT_STAGED_DIR);
+        let new_live_path = staged_path.join(STAGED_LIVE);
+        let old_live_path = staged_path.join(STAGED_OLD_LIVE);
+
+        // A directory can't be renamed over a non-empty directory, so we move the old live tree
+        // aside first.  If we're interrupted in between, live is missing, but the staged tree is
+        // complete and recovery moves it into place.
+        if new_live_path.exists() {
+            if self.live_path.exists() {
+                fs::rename(&self.live_path, &old_live_path).context(error::Io {
+                    path: &self.live_path,
+                })?;
+            }
+            fs::rename(&new_live_path, &self.live_path).context(error::Io {
+                path: &new_live_path,
+            })?;
+            sync_dir(&self.base_path)?;
+        }
+
+        // The transaction is only missing if we'd already started removing the staged directory,
+        // which we do after removing pending.
+        let transaction_path = staged_path.join(STAGED_TRANSACTION);
+        match fs::read_to_string(&transaction_path) {
+            Ok(transaction) => {
+                debug!("Removing committed transaction '{}'", transaction);
+                let pending = Committed::Pending { tx: transaction };
+                remove_dir_if_exists(&self.base_path(&pending)?)?;
+            }
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e).context(error::Io {
+                        path: &transaction_path,
+                    });
+                }
+            }
+        }
+
+        remove_dir_if_exists(&staged_path)?;
+        sync_dir(&self.base_path)
+    }
+}
+
+/// Returns the file on the filesystem for the given data key under the given base path.  Each
+/// key segment is a path component; list elements like "a[2]" sit next to their list.
+pub(super) fn data_path_under(base_path: &Path, key: &Key) -> Result<KeyFile> {
+    let names = key.segments().into_iter().map(encode_segment).collect();
+    KeyFile::new(base_path, key, names)
+}
+
+/// Returns the file on the filesystem for the given metadata key under the given base path, which
+/// sits next to the data key's file with the metadata key as a suffix.
+pub(super) fn metadata_path_under(
+    base_path: &Path,
+    metadata_key: &Key,
+    data_key: &Key,
+) -> Result<KeyFile> {
+    let mut names: Vec<String> = data_key.segments().into_iter().map(encode_segment).collect();
+    let basename = names.last_mut().context(error::Internal {
+        msg: format!("Data key with no segments: {}", data_key),
+    })?;
+    basename.push(METADATA_KEY_PREFIX);
+    basename.push_str(metadata_key);
+
+    KeyFile::new(base_path, data_key, names)
+}
+
+/// KeyFile is the location of a key's file under a base path.  Path components that would be too
+/// long for a filename are hashed, and we keep their real names to write to sidecar files when
+/// we write the key, so they can be found again when listing keys; see KeyPath::new.
+pub(super) struct KeyFile {
+    pub(super) path: PathBuf,
+    /// The sidecar file and real name for each hashed path component.
+    long_names: Vec<(PathBuf, String)>,
+}
+
+impl KeyFile {
+    fn new(base_path: &Path, key: &Key, names: Vec<String>) -> Result<KeyFile> {
         // FIXME: canonicalize requires that the full path exists.  We know our Key is checked
         // for acceptable characters, so join should be safe enough, but come back to this.
-        // let path = fs::canonicalize(self.base_path.join(path_suffix))?;
-        let path = base_path.join(path_suffix);
+        let mut path = base_path.to_path_buf();
+        let mut long_names = Vec::new();
+        for name in names {
+            if name.len() > MAX_FILENAME_LENGTH {
+                let hashed = format!(
+                    "{}{}",
+                    LONG_NAME_PREFIX,
+                    hex::encode(Sha256::digest(name.as_bytes()))
+                );
+                long_names.push((path.join(hashed.clone() + LONG_NAME_SUFFIX), name));
+                path.push(hashed);
+            } else {
+                path.push(name);
+            }
+        }
 
         ensure!(
-            path != *base_path && path.starts_with(base_path),
+            path != base_path && path.starts_with(&base_path),
             error::PathTraversal { name: key.as_ref() }
         );
-        Ok(path)
+
+        // A sidecar file can be longer than the key's own path, if it's for the last component.
+        let longest = long_names
+            .iter()
+            .map(|(sidecar, _)| sidecar.as_os_str().len())
+            .chain(Some(path.as_os_str().len()))
+            .max()
+            .unwrap_or(0);
+        let max = MAX_PATH_LENGTH - COMMIT_PATH_GROWTH;
+        ensure!(
+            longest <= max,
+            error::KeyPathTooLong {
+                name: key.as_ref(),
+                max,
+            }
+        );
+
+        Ok(KeyFile { path, long_names })
     }
 
-    fn metadata_path(
-        &self,
-        metadata_key: &Key,
-        data_key: &Key,
-        commited: Commited,
-    ) -> Result<PathBuf> {
-        let data_path = self.data_path(data_key, commited)?;
-        let data_path_str = data_path.to_str().expect("key paths must be UTF-8");
-
-        let segments: Vec<&str> = data_path_str.rsplit(2, path:MAIN_SEPARATOR)
-        let (basename, dirname) = match segment.len() {
-            2 => (segments[0], segments[1]),
-            _ => panic!("Grave error with path generation; invalid base path?"),
-        };
+    /// Writes the key's file, and sidecar files for any hashed path components.
+    fn write<S: AsRef<str>>(&self, data: S) -> Result<()> {
+        for (sidecar_path, name) in &self.long_names {
+            write_file_mkdir(sidecar_path, name)?;
+        }
+        write_file_mkdir(&self.path, data)
+    }
+}
+
+/// Quoted key segments can contain characters that we use in paths: path separators, and the
+/// dot that starts a metadata key in a filename.  We percent-encode those, and the percent sign
+/// itself; bare segments don't have any of them, so they're unchanged.
+fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for c in segment.chars() {
+        match c {
+            '%' => encoded.push_str("%25"),
+            '.' => encoded.push_str("%2E"),
+            '/' => encoded.push_str("%2F"),
+            _ => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+/// Reverses encode_segment.  Returns None if the input has an escape encode_segment wouldn't
+/// have made.
+fn decode_segments(encoded: &str) -> Option<String> {
+    let mut decoded = String::with_capacity(encoded.len());
+    let mut rest = encoded;
+    while let Some(start) = rest.find('%') {
+        decoded.push_str(&rest[..start]);
+        let escape = rest.get(start..start + 3)?;
+        decoded.push(match escape {
+            "%25" => '%',
+            "%2E" => '.',
+            "%2F" => '/',
+            _ => return None,
+        });
+        rest = &rest[start + 3..];
+    }
+    decoded.push_str(rest);
+    Some(decoded)
+}
+
+/// Helper for removing a directory tree that may not exist.
+fn remove_dir_if_exists(path: &Path) -> Result<()> {
+    if let Err(e) = fs::remove_dir_all(path) {
+        if e.kind() != io::ErrorKind::NotFound {
+            return Err(e).context(error::Io { path });
+        }
+    }
+    Ok(())
+}
+
+/// Helper for flushing a directory's entries to disk, so renames and removals in it are durable.
+fn sync_dir(path: &Path) -> Result<()> {
+    fs::File::open(path)
+        .and_then(|dir| dir.sync_all())
+        .context(error::Io { path })
+}
+
+/// Helper for flushing every file and directory in a tree to disk.
+fn sync_tree(path: &Path) -> Result<()> {
+    for entry in WalkDir::new(path).follow_links(false) {
+        let entry = entry.context(error::ListKeys)?;
+        fs::File::open(entry.path())
+            .and_then(|f| f.sync_all())
+            .context(error::Io { path: entry.path() })?;
+    }
+    Ok(())
+}
+
+/// Helper for copying a tree of data store files and directories to a new location.
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    let walker = WalkDir::new(from)
+        .follow_links(false) // shouldn't be links...
+        .same_file_system(true); // shouldn't be filesystems to cross...
+
+    for entry in walker {
+        let entry = entry.context(error::ListKeys)?;
+        let relative = entry.path().strip_prefix(from).context(error::Path)?;
+        let target = to.join(relative);
 
-        let filename = basename.to_owned() + &METADATA_KEY_PRFIX.to_string() + metadata_key;
-        Ok(Path::new(dirname).join(filename))
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            fs::create_dir_all(&target).context(error::Io { path: &target })?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), &target).context(error::Io { path: &target })?;
+        } else {
+            return error::Corruption {
+                msg: "Unexpected file type in data store",
+                path: entry.path(),
+            }
+            .fail();
+        }
     }
+    Ok(())
 }
 
 /// Helper for reading a key from the filesystem.  Returns Ok(None) if the file doesn't exist
 /// rather than erroring.
-
-fn read_file_for_key(key: &key, path: &Path) -> Result<Option<String>> {
+fn read_file_for_key(key: &Key, path: &Path) -> Result<Option<String>> {
     match fs::read_to_string(path) {
-        Ok(s) => Ok(Some(s)).
-        Err(s) => {
+        Ok(s) => Ok(Some(s)),
+        Err(e) => {
             if e.kind() == io::ErrorKind::NotFound {
                 return Ok(None);
             }
-            
+
             Err(e).context(error::KeyRead { key: key.as_ref() })
         }
     }
@@ -90,20 +404,22 @@ fn read_file_for_key(key: &key, path: &Path) -> Result<Option<String>> {
 
 /// Helper for writing a file that makes the directory tree beforehand, so we can handle
 /// arbitrarily dotted keys without needing to create fixed structure first.
-fn write_file_mkdir<S: AsRef<str>>(path: PathBuf, data: S) -> Result<()> {
+fn write_file_mkdir<S: AsRef<str>>(path: &Path, data: S) -> Result<()> {
     let dirname = path.parent().with_context(|| error::Internal {
         msg: format!(
             "Given path to write without proper prefix: {}",
             path.display()
         ),
     })?;
-    fs::create_dir_all(dirname).context(error::Io {path: dirname })?;
-    fs::write(&path, data.os_ref().as_bytes()).context(error::Io {path: &Path})
+    fs::create_dir_all(dirname).context(error::Io { path: dirname })?;
+    fs::write(path, data.as_ref().as_bytes()).context(error::Io { path })
 }
 
 /// KeyPath represents the filesystem path to a data or metadata key, relative to the base path of
 /// the live or pending data store.  For example, the data key "settings.a.b" would be
 /// "settings/a/b" and the metadata key "meta1" for "settings.a.b" would be "settings/a/b.meta1".
+/// Quoted segments are encoded with encode_segment, so the data key `settings."a.b"` would be
+/// `settings/"a%2Eb"`, and long names are hashed as described in KeyFile.
 ///
 /// It allows access to the data_key and (if it's a metadata key) the metadata_key based on the
 /// path.
@@ -114,29 +430,52 @@ fn write_file_mkdir<S: AsRef<str>>(path: PathBuf, data: S) -> Result<()> {
 // Note: this may be useful in other parts of the FilesystemDataStore code too.  It may also be
 // useful enough to use its ideas to extend the Key type directly, instead.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct KeyPath {
-    data_key: Key,
-    metadata_key: Option<Key>,
+pub(super) struct KeyPath {
+    pub(super) data_key: Key,
+    pub(super) metadata_key: Option<Key>,
 }
 
 impl KeyPath {
-    fn new(path: &Path) -> Result<KeyPath> {
-        let path_str = path.to_str().context(error::Corruption {
-            msg: "Non-UTF-8 path",
-            path,
-        })?;
+    /// Makes a KeyPath from a path relative to the given base path.  Hashed path components
+    /// are replaced with their real names from the sidecar files under the base path.
+    pub(super) fn new(base_path: &Path, path: &Path) -> Result<KeyPath> {
+        let mut names = Vec::new();
+        let mut dir = base_path.to_path_buf();
+        for component in path.iter() {
+            let component = component.to_str().context(error::Corruption {
+                msg: "Non-UTF-8 path",
+                path,
+            })?;
+            if component.starts_with(LONG_NAME_PREFIX) {
+                let sidecar_path = dir.join(component.to_owned() + LONG_NAME_SUFFIX);
+                let name = fs::read_to_string(&sidecar_path).context(error::Io {
+                    path: &sidecar_path,
+                })?;
+                names.push(name);
+            } else {
+                names.push(component.to_string());
+            }
+            dir.push(component);
+        }
+        let path_str = names.join("/");
 
         let mut segments = path_str.splitn(2, '.');
 
-        let data_key_raw = segment.next().context(error::Internal {
+        let data_key_raw = segments.next().context(error::Internal {
             msg: "KeyPath given empty path",
         })?;
 
-        let data_key_str = data_key_raw.replace("/", KEY_SEPARATOR);
+        // Replace separators before decoding, so encoded separators inside quoted segments stay.
+        let data_key_str = decode_segments(&data_key_raw.replace("/", KEY_SEPARATOR)).context(
+            error::Corruption {
+                msg: "Invalid escape in key path",
+                path,
+            },
+        )?;
         let data_key = Key::new(KeyType::Data, data_key_str)?;
 
         let metadata_key = match segments.next() {
-            Some(meta_key_str) => Some(Key::new(KeyType::Metadata, meta_key_str)?),
+            Some(meta_key_str) => Some(Key::new(KeyType::Meta, meta_key_str)?),
             None => None,
         };
 
@@ -168,9 +507,15 @@ fn key_path_for_entry<P: AsRef<Path>>(
     }
 
     let path = entry.path();
-    let key_path_raw = path.strip_prefix(strip_path_prefix).context(error::Path)?;
+    if entry.file_name().to_string_lossy().ends_with(LONG_NAME_SUFFIX) {
+        trace!("Skipping sidecar file: {}", path.display());
+        return Ok(None);
+    }
+
+    let base_path = strip_path_prefix.as_ref();
+    let key_path_raw = path.strip_prefix(base_path).context(error::Path)?;
     // If KeyPath doesn't think this is an OK key, we'll return Ok(None), otherwise the KeyPath
-    Ok(KeyPath::new(key_path_raw).ok())
+    Ok(KeyPath::new(base_path, key_path_raw).ok())
 }
 
 /// Helper to walk through the filesystem to find populated keys of the given type, starting with
@@ -184,10 +529,10 @@ fn find_populated_key_paths<S: AsRef<str>>(
     datastore: &FilesystemDataStore,
     key_type: KeyType,
     prefix: S,
-    committed: Committed,
+    committed: &Committed,
 ) -> Result<HashSet<KeyPath>> {
     // Find the base path for our search, and confirm it exists.
-    let base = datastore.base_path(committed);
+    let base = datastore.base_path(committed)?;
     if !base.exists() {
         match committed {
             // No live keys; something must be wrong because we create a default datastore.
@@ -199,7 +544,7 @@ fn find_populated_key_paths<S: AsRef<str>>(
                 .fail()
             }
             // No pending keys, OK, return empty set.
-            Committed::Pending => {
+            Committed::Pending { .. } => {
                 trace!(
                     "Returning empty list because pending path doesn't exist: {}",
                     base.display()
@@ -210,7 +555,7 @@ fn find_populated_key_paths<S: AsRef<str>>(
     }
 
     // Walk through the filesystem.
-    let walker = WalkDir::new(base)
+    let walker = WalkDir::new(&base)
         .follow_links(false) // shouldn't be links...
         .same_file_system(true); // shouldn't be filesystems to cross...
 
@@ -246,43 +591,44 @@ fn find_populated_key_paths<S: AsRef<str>>(
 }
 
 impl DataStore for FilesystemDataStore {
-    fn key_populate(&self, key: &key, commited: Commited) -> Result<bool> {
-        let path = self.data_path(key, commited)?;
+    fn key_populated(&self, key: &Key, committed: &Committed) -> Result<bool> {
+        let key_file = self.data_path(key, committed)?;
 
-        Ok(path.exists())
+        Ok(key_file.path.exists())
     }
 
     fn list_populated_keys<S: AsRef<str>>(
         &self,
         prefix: S,
-        commited: Commited,
-    ) -> Result<HashSet<key>> {
-        let key_paths = find_populated_key_paths(self, KeyType::Data, prefix, commited)?;
+        committed: &Committed,
+    ) -> Result<HashSet<Key>> {
+        let key_paths = find_populated_key_paths(self, KeyType::Data, prefix, committed)?;
         let keys = key_paths.into_iter().map(|kp| kp.data_key).collect();
 
         Ok(keys)
     }
-}
 
- /// Finds all metadata keys that are currently populated in the datastore whose data keys
+    /// Finds all metadata keys that are currently populated in the datastore whose data keys
     /// start with the given prefix.  If you specify metadata_key_name, only metadata keys with
     /// that name will be returned.
     ///
     /// Returns a mapping of the data keys to the set of populated metadata keys for each.
     ///
     /// Note: The data keys do not need to be populated themselves; sometimes metadata is used
-    /// to help generate the data, for example.  (Committed status is then irrelevant, too.)
+    /// to help generate the data, for example.  Pending metadata is only returned for its own
+    /// transaction, though, like pending data.
     fn list_populated_metadata<S1, S2>(
         &self,
         prefix: S1,
         metadata_key_name: &Option<S2>,
+        committed: &Committed,
     ) -> Result<HashMap<Key, HashSet<Key>>>
     where
         S1: AsRef<str>,
         S2: AsRef<str>,
     {
         // Find metadata key paths on disk
-        let key_paths = find_populated_key_paths(self, KeyType::Meta, prefix, Committed::Live)?;
+        let key_paths = find_populated_key_paths(self, KeyType::Meta, prefix, committed)?;
 
         // For each file on disk, check the user's conditions, and add it to our output
         let mut result = HashMap::new();
@@ -307,19 +653,28 @@ impl DataStore for FilesystemDataStore {
         Ok(result)
     }
 
-    fn get_key(&self, key: &Key, committed: Committed) -> Result<Option<String>> {
-        let path = self.data_path(key, committed)?;
-        read_file_for_key(&key, &path)
+    fn get_key(&self, key: &Key, committed: &Committed) -> Result<Option<String>> {
+        let key_file = self.data_path(key, committed)?;
+        read_file_for_key(&key, &key_file.path)
     }
 
-    fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: Committed) -> Result<()> {
-        let path = self.data_path(key, committed)?;
-        write_file_mkdir(path, value)
+    fn set_key<S: AsRef<str>>(
+        &mut self,
+        key: &Key,
+        value: S,
+        committed: &Committed,
+    ) -> Result<()> {
+        self.data_path(key, committed)?.write(value)
     }
 
-    fn get_metadata_raw(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>> {
-        let path = self.metadata_path(metadata_key, data_key, Committed::Live)?;
-        read_file_for_key(&metadata_key, &path)
+    fn get_metadata_raw(
+        &self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<Option<String>> {
+        let key_file = self.metadata_path(metadata_key, data_key, committed)?;
+        read_file_for_key(&metadata_key, &key_file.path)
     }
 
     fn set_metadata<S: AsRef<str>>(
@@ -327,57 +682,134 @@ impl DataStore for FilesystemDataStore {
         metadata_key: &Key,
         data_key: &Key,
         value: S,
+        committed: &Committed,
     ) -> Result<()> {
-        let path = self.metadata_path(metadata_key, data_key, Committed::Live)?;
-        write_file_mkdir(path, value)
+        self.metadata_path(metadata_key, data_key, committed)?.write(value)
     }
 
-    /// We commit by copying pending keys to live, then removing pending.  Something smarter (lock,
-    /// atomic flip, etc.) will be required to make the server concurrent.
-    fn commit(&mut self) -> Result<HashSet<Key>> {
-        // Get data for changed keys
-        let pending_data = self.get_prefix("settings.", Committed::Pending)?;
+    /// We commit by building a copy of live with the pending keys and metadata applied, then swapping it in
+    /// with renames and removing pending.  The copy is marked as staged with a single rename once
+    /// it's complete, so an interrupted commit can always be rolled back (if it wasn't staged) or
+    /// rolled forward (if it was); see recover.
+    fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        let transaction = transaction.into();
+        let pending = Committed::Pending {
+            tx: transaction.clone(),
+        };
 
-        // Nothing to do if no keys are present in pending
-        if pending_data.is_empty() {
-            return Ok(Default::default())
+        // Get data for changed keys, after folding any list elements into their lists
+        fold_list_elements(self, &transaction)?;
+        let mut pending_data = self.get_prefix(COMMIT_PREFIX, &pending)?;
+        pending_data.retain(|key, _| commits_key(key));
+
+        // Get metadata set in the transaction, which goes live along with the data
+        let mut pending_metadata = HashMap::new();
+        let populated = self.list_populated_metadata("", &None as &Option<&str>, &pending)?;
+        for (data_key, metadata_keys) in populated {
+            let mut metadata = HashMap::new();
+            for metadata_key in metadata_keys {
+                if let Some(value) = self.get_metadata_raw(&metadata_key, &data_key, &pending)? {
+                    metadata.insert(metadata_key, value);
+                }
+            }
+            pending_metadata.insert(data_key, metadata);
         }
 
-        // Turn String keys of pending data into Key keys, for return
-        let try_pending_keys: Result<HashSet<Key>> = pending_data
-            .keys()
-            .map(|s| Key::new(KeyType::Data, s))
-            .collect();
-        let pending_keys = try_pending_keys?;
-
-        // Apply changes to live
-        debug!("Writing pending keys to live");
-        self.set_keys(&pending_data, Committed::Live)?;
-
-        // Remove pending
-        debug!("Removing old pending keys");
-        fs::remove_dir_all(&self.pending_path).context(error::Io {
-            path: &self.pending_path,
-        })?;
+        // Nothing to make live if no keys are present in pending, but the transaction is still
+        // finished, like any other commit
+        if pending_data.is_empty() && pending_metadata.is_empty() {
+            remove_dir_if_exists(&self.base_path(&pending)?)?;
+            return Ok(Default::default());
+        }
+
+        // Save Keys for return value
+        let pending_keys: HashSet<Key> = pending_data.keys().cloned().collect();
+
+        // Build the new live tree, then swap it in and remove pending
+        self.stage_commit(&transaction, &pending_data, &pending_metadata)?;
+        self.finish_commit()?;
 
         Ok(pending_keys)
     }
+
+    fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        let pending = Committed::Pending {
+            tx: transaction.into(),
+        };
+
+        // Get changed keys so we can return the list
+        let pending_keys = self.list_populated_keys("", &pending)?;
+
+        // Pull out the transaction directory; if it doesn't exist there's nothing to delete
+        remove_dir_if_exists(&self.base_path(&pending)?)?;
+
+        Ok(pending_keys)
+    }
+
+    /// We store pending transactions in subdirectories of the pending base path, so we list
+    /// directory names to find them.
+    fn list_transactions(&self) -> Result<HashSet<String>> {
+        // If the pending base path doesn't exist, nothing has been staged.
+        let entries = match fs::read_dir(&self.pending_base_path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                if e.kind() == io::ErrorKind::NotFound {
+                    return Ok(HashSet::new());
+                }
+                return Err(e).context(error::Io {
+                    path: &self.pending_base_path,
+                });
+            }
+        };
+
+        let mut transactions = HashSet::new();
+        for entry in entries {
+            let entry = entry.context(error::Io {
+                path: &self.pending_base_path,
+            })?;
+            let file_type = entry.file_type().context(error::Io { path: entry.path() })?;
+            if !file_type.is_dir() {
+                trace!("Skipping non-directory in pending: {}", entry.path().display());
+                continue;
+            }
+
+            let name = entry.file_name();
+            let name = name.to_str().context(error::Corruption {
+                msg: "Non-UTF-8 transaction directory",
+                path: entry.path(),
+            })?;
+            transactions.insert(name.to_string());
+        }
+
+        Ok(transactions)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{Committed, FilesystemDataStore, Key, KeyType };
-    
+    use super::*;
+    use maplit::{hashmap, hashset};
+    use tempfile::TempDir;
+
     #[test]
     fn data_path() {
         let f = FilesystemDataStore::new("/base");
         let key = Key::new(KeyType::Data, "a.b.c").unwrap();
 
-        let pending = f.data_path(&key, Committed::Pending).unwrap();
-        assert_eq!(pending.into_os_string(), "/base/pending/a/b/c");
+        let tx = Committed::Pending {
+            tx: "testtx".into(),
+        };
+        let pending = f.data_path(&key, &tx).unwrap();
+        assert_eq!(pending.path.into_os_string(), "/base/pending/testtx/a/b/c");
 
-        let live = f.data_path(&key, Committed::Live).unwrap();
-        assert_eq!(live.into_os_string(), "/base/live/a/b/c");
+        let live = f.data_path(&key, &Committed::Live).unwrap();
+        assert_eq!(live.path.into_os_string(), "/base/live/a/b/c");
     }
 
     #[test]
@@ -386,14 +818,281 @@ mod test {
         let data_key = Key::new(KeyType::Data, "a.b.c").unwrap();
         let md_key = Key::new(KeyType::Meta, "my-metadata").unwrap();
 
-        let pending = f
-            .metadata_path(&md_key, &data_key, Committed::Pending)
-            .unwrap();
-        assert_eq!(pending.into_os_string(), "/base/pending/a/b/c.my-metadata");
+        let tx = Committed::Pending {
+            tx: "testtx".into(),
+        };
+        let pending = f.metadata_path(&md_key, &data_key, &tx).unwrap();
+        assert_eq!(
+            pending.path.into_os_string(),
+            "/base/pending/testtx/a/b/c.my-metadata"
+        );
 
         let live = f
-            .metadata_path(&md_key, &data_key, Committed::Live)
+            .metadata_path(&md_key, &data_key, &Committed::Live)
             .unwrap();
-        assert_eq!(live.into_os_string(), "/base/live/a/b/c.my-metadata");
+        assert_eq!(live.path.into_os_string(), "/base/live/a/b/c.my-metadata");
+    }
+
+    #[test]
+    fn quoted_and_indexed_paths() {
+        let f = FilesystemDataStore::new("/base");
+        let key = Key::new(KeyType::Data, r#"a."app.io/role%".c[2]"#).unwrap();
+        let live = f.data_path(&key, &Committed::Live).unwrap();
+        assert_eq!(
+            live.path.into_os_string(),
+            r#"/base/live/a/"app%2Eio%2Frole%25"/c[2]"#
+        );
+
+        let md_key = Key::new(KeyType::Meta, "my-metadata").unwrap();
+        let key_path = KeyPath::new(
+            Path::new("/base/live"),
+            Path::new(r#"a/"app%2Eio%2Frole%25"/c[2].my-metadata"#),
+        )
+        .unwrap();
+        assert_eq!(key_path.data_key, key);
+        assert_eq!(key_path.metadata_key, Some(md_key));
+
+        assert!(KeyPath::new(Path::new("/base/live"), Path::new(r#"a/"b%2"#)).is_err());
+    }
+
+    #[test]
+    fn quoted_keys_listed() {
+        let dir = TempDir::new().unwrap();
+        let mut f = FilesystemDataStore::new(dir.path());
+        let key = Key::new(KeyType::Data, r#"settings.labels."app.io/role""#).unwrap();
+        f.set_key(&key, "\"x\"", &Committed::Live).unwrap();
+        assert_eq!(
+            f.list_populated_keys("settings.labels", &Committed::Live)
+                .unwrap(),
+            hashset!(key)
+        );
+    }
+
+    #[test]
+    fn long_paths_rejected() {
+        let f = FilesystemDataStore::new("/base");
+        let max = MAX_PATH_LENGTH - COMMIT_PATH_GROWTH;
+
+        // Short segments can't be hashed, so the path is about as long as the key.
+        let fits = "/base/live/".len() + 1;
+        let key_name = format!("a{}", ".a".repeat((max - fits) / 2));
+        let key = Key::new(KeyType::Data, &key_name).unwrap();
+        assert!(f.data_path(&key, &Committed::Live).unwrap().path.as_os_str().len() <= max);
+
+        let key = Key::new(KeyType::Data, format!("{}.a", key_name)).unwrap();
+        assert!(f.data_path(&key, &Committed::Live).is_err());
+        let md_key = Key::new(KeyType::Meta, "my-metadata").unwrap();
+        let data_key = Key::new(KeyType::Data, &key_name).unwrap();
+        assert!(f.metadata_path(&md_key, &data_key, &Committed::Live).is_err());
+    }
+
+    #[test]
+    fn long_names_hashed() {
+        let dir = TempDir::new().unwrap();
+        let mut f = FilesystemDataStore::new(dir.path());
+        let long = "a".repeat(MAX_FILENAME_LENGTH + 1);
+        let key = Key::new(KeyType::Data, format!("settings.{}.{}", long, long)).unwrap();
+        let md_key = Key::new(KeyType::Meta, "my-metadata").unwrap();
+        f.set_key(&key, "\"x\"", &Committed::Live).unwrap();
+        f.set_metadata(&md_key, &key, "\"m\"", &Committed::Live).unwrap();
+
+        // Both the directory and the file for the key are hashed
+        let key_file = f.data_path(&key, &Committed::Live).unwrap();
+        assert_eq!(key_file.long_names.len(), 2);
+        assert!(key_file.path.exists());
+        for component in key_file.path.strip_prefix(dir.path()).unwrap() {
+            assert!(component.len() <= MAX_FILENAME_LENGTH);
+        }
+
+        assert_eq!(
+            f.get_key(&key, &Committed::Live).unwrap(),
+            Some("\"x\"".to_string())
+        );
+        assert_eq!(
+            f.list_populated_keys("settings", &Committed::Live).unwrap(),
+            hashset!(key.clone())
+        );
+        assert_eq!(
+            f.list_populated_metadata("settings", &None as &Option<&str>, &Committed::Live)
+                .unwrap(),
+            hashmap!(key.clone() => hashset!(md_key.clone()))
+        );
+        assert_eq!(
+            f.get_metadata_raw(&md_key, &key, &Committed::Live).unwrap(),
+            Some("\"m\"".to_string())
+        );
+
+        // Committing copies the sidecar files along with the keys
+        let pending = Committed::Pending { tx: "tx".into() };
+        f.set_key(&key, "\"y\"", &pending).unwrap();
+        f.commit_transaction("tx").unwrap();
+        assert_eq!(
+            f.list_populated_keys("settings", &Committed::Live).unwrap(),
+            hashset!(key)
+        );
+    }
+
+    #[test]
+    fn transaction_traversal() {
+        let f = FilesystemDataStore::new("/base");
+        let key = Key::new(KeyType::Data, "a.b.c").unwrap();
+
+        for tx in &["..", "a/b", ""] {
+            let committed = Committed::Pending { tx: tx.to_string() };
+            assert!(f.data_path(&key, &committed).is_err());
+        }
+    }
+
+    // Helpers for the commit tests: a data store with one live key and a pending change to it.
+
+    fn setting() -> Key {
+        Key::new(KeyType::Data, "settings.a.b").unwrap()
+    }
+
+    fn pending() -> Committed {
+        Committed::Pending {
+            tx: "testtx".into(),
+        }
+    }
+
+    fn commit_test_datastore() -> (TempDir, FilesystemDataStore) {
+        let dir = TempDir::new().unwrap();
+        let mut f = FilesystemDataStore::new(dir.path());
+        f.set_key(&setting(), "\"old\"", &Committed::Live).unwrap();
+        f.set_metadata(
+            &Key::new(KeyType::Meta, "my-meta").unwrap(),
+            &setting(),
+            "\"meta\"",
+            &Committed::Live,
+        )
+        .unwrap();
+        f.set_key(&setting(), "\"new\"", &pending()).unwrap();
+        (dir, f)
+    }
+
+    fn pending_data(f: &FilesystemDataStore) -> HashMap<Key, String> {
+        f.get_prefix("settings.", &pending()).unwrap()
+    }
+
+    /// Checks that the commit went through completely and left nothing behind.
+    fn assert_committed(dir: &TempDir, f: &FilesystemDataStore) {
+        assert_eq!(
+            f.get_key(&setting(), &Committed::Live).unwrap(),
+            Some("\"new\"".to_string())
+        );
+        assert!(!f.key_populated(&setting(), &pending()).unwrap());
+        assert!(f.list_transactions().unwrap().is_empty());
+        // Metadata is carried over to the new live tree.
+        assert_eq!(
+            f.get_metadata_raw(
+                &Key::new(KeyType::Meta, "my-meta").unwrap(),
+                &setting(),
+                &Committed::Live
+            )
+            .unwrap(),
+            Some("\"meta\"".to_string())
+        );
+        assert!(!dir.path().join(COMMIT_STAGING_DIR).exists());
+        assert!(!dir.path().join(COMMIT_STAGED_DIR).exists());
+    }
+
+    /// Checks that the data store looks like the commit never started.
+    fn assert_not_committed(dir: &TempDir, f: &FilesystemDataStore) {
+        assert_eq!(
+            f.get_key(&setting(), &Committed::Live).unwrap(),
+            Some("\"old\"".to_string())
+        );
+        assert_eq!(
+            f.get_key(&setting(), &pending()).unwrap(),
+            Some("\"new\"".to_string())
+        );
+        assert!(!dir.path().join(COMMIT_STAGING_DIR).exists());
+        assert!(!dir.path().join(COMMIT_STAGED_DIR).exists());
+    }
+
+    #[test]
+    fn commit() {
+        let (dir, mut f) = commit_test_datastore();
+        let changed = f.commit_transaction("testtx").unwrap();
+        assert_eq!(changed, hashset!(setting()));
+        assert_committed(&dir, &f);
+    }
+
+    #[test]
+    fn recover_nothing() {
+        let (dir, mut f) = commit_test_datastore();
+        f.recover().unwrap();
+        assert_not_committed(&dir, &f);
+    }
+
+    #[test]
+    fn interrupted_while_staging() {
+        let (dir, _) = commit_test_datastore();
+
+        // Simulate a failure partway through copying live: only some of the new tree exists.
+        let partial = dir
+            .path()
+            .join(COMMIT_STAGING_DIR)
+            .join(STAGED_LIVE)
+            .join("settings");
+        fs::create_dir_all(&partial).unwrap();
+        fs::write(partial.join("a"), "garbage").unwrap();
+
+        // Recovery should roll back, and we can commit again afterward.
+        let mut f = FilesystemDataStore::new(dir.path());
+        f.recover().unwrap();
+        assert_not_committed(&dir, &f);
+
+        f.commit_transaction("testtx").unwrap();
+        assert_committed(&dir, &f);
+    }
+
+    #[test]
+    fn interrupted_after_staging() {
+        let (dir, f) = commit_test_datastore();
+
+        // Simulate a failure after the new tree is staged, but before it's swapped in.
+        f.stage_commit("testtx", &pending_data(&f), &HashMap::new()).unwrap();
+        assert_eq!(
+            f.get_key(&setting(), &Committed::Live).unwrap(),
+            Some("\"old\"".to_string())
+        );
+
+        // Recovery should roll forward.
+        let mut f = FilesystemDataStore::new(dir.path());
+        f.recover().unwrap();
+        assert_committed(&dir, &f);
+    }
+
+    #[test]
+    fn interrupted_during_swap() {
+        let (dir, f) = commit_test_datastore();
+
+        // Simulate a failure after the old live tree is moved aside, leaving no live tree.
+        f.stage_commit("testtx", &pending_data(&f), &HashMap::new()).unwrap();
+        let staged = dir.path().join(COMMIT_STAGED_DIR);
+        fs::rename(dir.path().join("live"), staged.join(STAGED_OLD_LIVE)).unwrap();
+        assert!(f.get_key(&setting(), &Committed::Live).unwrap().is_none());
+
+        // Recovery should roll forward.
+        let mut f = FilesystemDataStore::new(dir.path());
+        f.recover().unwrap();
+        assert_committed(&dir, &f);
+    }
+
+    #[test]
+    fn interrupted_during_cleanup() {
+        let (dir, f) = commit_test_datastore();
+
+        // Simulate a failure after the new live tree is in place, but before pending is removed.
+        f.stage_commit("testtx", &pending_data(&f), &HashMap::new()).unwrap();
+        let staged = dir.path().join(COMMIT_STAGED_DIR);
+        fs::rename(dir.path().join("live"), staged.join(STAGED_OLD_LIVE)).unwrap();
+        fs::rename(staged.join(STAGED_LIVE), dir.path().join("live")).unwrap();
+
+        // Recovery should remove the committed transaction and the old tree.
+        let mut f = FilesystemDataStore::new(dir.path());
+        f.recover().unwrap();
+        assert_committed(&dir, &f);
     }
 }
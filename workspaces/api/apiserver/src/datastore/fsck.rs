@@ -0,0 +1,534 @@
+//! Integrity checks for a FilesystemDataStore, for finding problems up front instead of when a
+//! request happens to touch a bad key.
+//!
+//! We walk the live tree and each pending transaction, and check that every file is a valid key
+//! in the place we'd look for it, with a valid serialized value.  We also check the chain of
+//! version links that points to the data store, and look for metadata about data keys that don't
+//! exist.
+//!
+//! Problems that would make data store operations fail can be quarantined, which moves the bad
+//! files out of the data store into a directory next to live and pending, so they can be examined
+//! or restored by hand.
+
+use data_store_version::Version;
+use snafu::ResultExt;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use super::filesystem::{
+    data_path_under, metadata_path_under, KeyPath, LIVE_DIR, LONG_NAME_PREFIX, LONG_NAME_SUFFIX,
+    PENDING_DIR,
+};
+use super::{deserialize_scalar, error, valid_transaction, Key, Result, ScalarError, Value};
+
+/// The name of the directory, next to live and pending, where quarantined files are moved.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// A problem found in a data store, and the file or link where we found it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub path: PathBuf,
+    pub kind: ProblemKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProblemKind {
+    /// A link in the chain of version links is missing or doesn't point where it should.
+    BrokenLink(String),
+    /// The live tree doesn't exist.
+    MissingLive,
+    /// An entry in the pending directory isn't a directory with a valid transaction name.
+    InvalidTransaction,
+    /// A file that can't be a key, like a symlink, or a sidecar file for a name that isn't used.
+    UnexpectedFile(String),
+    /// The path can't be turned into a valid key.
+    InvalidKey(String),
+    /// The path is a valid key, but it's not the path we'd use for that key, so the key can be
+    /// listed but not read.
+    MisplacedKey { expected: PathBuf },
+    /// The file doesn't contain a valid serialized scalar.
+    InvalidValue(String),
+    /// Metadata for a data key that isn't populated, and isn't the parent of a populated key.
+    OrphanedMetadata { data_key: Key, metadata_key: Key },
+}
+
+impl ProblemKind {
+    /// Returns whether the problem is only suspicious, rather than something that makes data
+    /// store operations fail.  Metadata can legitimately describe data that doesn't exist yet,
+    /// for example a setting that's generated at boot.
+    pub fn is_warning(&self) -> bool {
+        match self {
+            ProblemKind::OrphanedMetadata { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Returns whether the problem is with a file we can move out of the data store.
+    pub fn quarantinable(&self) -> bool {
+        match self {
+            ProblemKind::InvalidTransaction
+            | ProblemKind::UnexpectedFile(_)
+            | ProblemKind::InvalidKey(_)
+            | ProblemKind::MisplacedKey { .. }
+            | ProblemKind::InvalidValue(_) => true,
+            ProblemKind::BrokenLink(_)
+            | ProblemKind::MissingLive
+            | ProblemKind::OrphanedMetadata { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path.display();
+        match &self.kind {
+            ProblemKind::BrokenLink(msg) => write!(f, "Broken version link {}: {}", path, msg),
+            ProblemKind::MissingLive => write!(f, "Live data missing at {}", path),
+            ProblemKind::InvalidTransaction => write!(f, "Invalid pending transaction {}", path),
+            ProblemKind::UnexpectedFile(msg) => write!(f, "Unexpected file {}: {}", path, msg),
+            ProblemKind::InvalidKey(msg) => write!(f, "Invalid key at {}: {}", path, msg),
+            ProblemKind::MisplacedKey { expected } => {
+                write!(f, "Key at {} should be at {}", path, expected.display())
+            }
+            ProblemKind::InvalidValue(msg) => write!(f, "Invalid value in {}: {}", path, msg),
+            ProblemKind::OrphanedMetadata {
+                data_key,
+                metadata_key,
+            } => write!(
+                f,
+                "Metadata '{}' at {} is for unpopulated key '{}'",
+                metadata_key, path, data_key
+            ),
+        }
+    }
+}
+
+/// Checks the chain of version links starting at the given link, usually named "current", which
+/// should look like current -> v1 -> v1.5 -> v1.5_0123456789abcdef, all in the same directory.
+/// Returns any problems, and the data store directory if the chain is intact.
+pub fn check_links<P: AsRef<Path>>(current: P) -> Result<(Vec<Problem>, Option<PathBuf>)> {
+    let current = current.as_ref();
+    let base_path = current.parent().unwrap_or_else(|| Path::new(""));
+
+    // current -> v1
+    let major_name = match read_link_name(current)? {
+        Ok(name) => name,
+        Err(msg) => return broken(current, msg),
+    };
+    let major = match major_name.trim_start_matches('v').parse::<u32>() {
+        Ok(major) if format!("v{}", major) == major_name => major,
+        _ => return broken(current, format!("'{}' isn't a major version", major_name)),
+    };
+
+    // v1 -> v1.5
+    let major_path = base_path.join(&major_name);
+    let minor_name = match read_link_name(&major_path)? {
+        Ok(name) => name,
+        Err(msg) => return broken(&major_path, msg),
+    };
+    let version = match minor_name.parse::<Version>() {
+        Ok(version) if version.major == major && version.to_string() == minor_name => version,
+        _ => {
+            let msg = format!(
+                "'{}' isn't a version with major version {}",
+                minor_name, major
+            );
+            return broken(&major_path, msg);
+        }
+    };
+
+    // v1.5 -> v1.5_0123456789abcdef
+    let minor_path = base_path.join(&minor_name);
+    let data_store_name = match read_link_name(&minor_path)? {
+        Ok(name) => name,
+        Err(msg) => return broken(&minor_path, msg),
+    };
+    let id_prefix = format!("{}_", version);
+    if !data_store_name.starts_with(&id_prefix) || data_store_name.len() == id_prefix.len() {
+        let msg = format!("'{}' isn't a data store for {}", data_store_name, version);
+        return broken(&minor_path, msg);
+    }
+
+    let data_store_path = base_path.join(&data_store_name);
+    if !data_store_path.is_dir() {
+        let msg = format!("'{}' isn't a directory", data_store_name);
+        return broken(&minor_path, msg);
+    }
+
+    Ok((Vec::new(), Some(data_store_path)))
+}
+
+/// Helper for returning a single broken link from check_links.
+fn broken(path: &Path, msg: String) -> Result<(Vec<Problem>, Option<PathBuf>)> {
+    let problem = Problem {
+        path: path.to_path_buf(),
+        kind: ProblemKind::BrokenLink(msg),
+    };
+    Ok((vec![problem], None))
+}
+
+/// Reads the target of a version link, which should be the name of another entry in the same
+/// directory.  The inner Result describes a broken link; the outer one is for unexpected errors.
+fn read_link_name(path: &Path) -> Result<std::result::Result<String, String>> {
+    let target = match fs::read_link(path) {
+        Ok(target) => target,
+        Err(e) => {
+            return match e.kind() {
+                io::ErrorKind::NotFound => Ok(Err("missing".to_string())),
+                io::ErrorKind::InvalidInput => Ok(Err("not a symlink".to_string())),
+                _ => Err(e).context(error::Io { path }),
+            }
+        }
+    };
+
+    let name = target.to_str().filter(|name| !name.contains('/'));
+    match name {
+        Some(name) => Ok(Ok(name.to_string())),
+        None => Ok(Err(format!(
+            "target '{}' isn't a name in the same directory",
+            target.display()
+        ))),
+    }
+}
+
+/// The keys found in the live tree, or in a pending transaction.
+#[derive(Debug, Default)]
+struct Tree {
+    data_keys: HashSet<Key>,
+    // The path of each metadata file, with its data key and metadata key.
+    metadata: Vec<(PathBuf, Key, Key)>,
+}
+
+/// Checks every key in the data store at the given directory, which contains live and pending.
+/// Returns the problems found; Err means we weren't able to finish checking.
+pub fn check_datastore<P: AsRef<Path>>(datastore_path: P) -> Result<Vec<Problem>> {
+    let datastore_path = datastore_path.as_ref();
+    let mut problems = Vec::new();
+
+    let live_path = datastore_path.join(LIVE_DIR);
+    let live = if live_path.is_dir() {
+        check_tree(&live_path, &mut problems)?
+    } else {
+        problems.push(Problem {
+            path: live_path,
+            kind: ProblemKind::MissingLive,
+        });
+        Tree::default()
+    };
+    check_orphans(&live, None, &mut problems);
+
+    let pending_path = datastore_path.join(PENDING_DIR);
+    let entries = match fs::read_dir(&pending_path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            if e.kind() == io::ErrorKind::NotFound {
+                return Ok(problems);
+            }
+            return Err(e).context(error::Io {
+                path: &pending_path,
+            });
+        }
+    };
+    for entry in entries {
+        let entry = entry.context(error::Io {
+            path: &pending_path,
+        })?;
+        let path = entry.path();
+        let valid_name = entry
+            .file_name()
+            .to_str()
+            .map_or(false, |name| valid_transaction(name).is_ok());
+        if !valid_name || !path.is_dir() {
+            problems.push(Problem {
+                path,
+                kind: ProblemKind::InvalidTransaction,
+            });
+            continue;
+        }
+
+        let transaction = check_tree(&path, &mut problems)?;
+        check_orphans(&transaction, Some(&live), &mut problems);
+    }
+
+    Ok(problems)
+}
+
+/// Checks each file under the given live or pending path, and returns the valid keys we found.
+fn check_tree(base_path: &Path, problems: &mut Vec<Problem>) -> Result<Tree> {
+    trace!("Checking keys under {}", base_path.display());
+    let mut tree = Tree::default();
+
+    let walker = WalkDir::new(base_path).follow_links(false).min_depth(1);
+    for entry in walker {
+        let entry = entry.context(error::ListKeys)?;
+        let path = entry.path();
+        let mut problem = |kind| {
+            problems.push(Problem {
+                path: path.to_path_buf(),
+                kind,
+            })
+        };
+
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            continue;
+        } else if !file_type.is_file() {
+            problem(ProblemKind::UnexpectedFile(
+                "not a regular file".to_string(),
+            ));
+            continue;
+        }
+
+        // Sidecar files hold the real names of hashed path components; see KeyFile.
+        let file_name = entry.file_name().to_string_lossy();
+        if file_name.ends_with(LONG_NAME_SUFFIX) {
+            let hashed = &file_name[..file_name.len() - LONG_NAME_SUFFIX.len()];
+            if !path.with_file_name(hashed).exists() {
+                problem(ProblemKind::UnexpectedFile(format!(
+                    "sidecar for missing '{}'",
+                    hashed
+                )));
+            }
+            continue;
+        }
+
+        let relative = path.strip_prefix(base_path).context(error::Path)?;
+        let key_path = match KeyPath::new(base_path, relative) {
+            Ok(key_path) => key_path,
+            Err(e) => {
+                problem(ProblemKind::InvalidKey(e.to_string()));
+                continue;
+            }
+        };
+
+        // Make sure we'd find the file again when looking up the key.
+        let expected = match &key_path.metadata_key {
+            Some(metadata_key) => metadata_path_under(base_path, metadata_key, &key_path.data_key),
+            None => data_path_under(base_path, &key_path.data_key),
+        };
+        match expected {
+            Ok(key_file) => {
+                if key_file.path != path {
+                    problem(ProblemKind::MisplacedKey {
+                        expected: key_file.path,
+                    });
+                    continue;
+                }
+            }
+            Err(e) => {
+                problem(ProblemKind::InvalidKey(e.to_string()));
+                continue;
+            }
+        }
+
+        let raw = match fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(e) => {
+                if e.kind() == io::ErrorKind::InvalidData {
+                    problem(ProblemKind::InvalidValue("not UTF-8".to_string()));
+                    continue;
+                }
+                return Err(e).context(error::Io { path });
+            }
+        };
+        if let Err(e) = deserialize_scalar::<Value, ScalarError>(&raw) {
+            problem(ProblemKind::InvalidValue(e.to_string()));
+            continue;
+        }
+
+        match key_path.metadata_key {
+            Some(metadata_key) => {
+                tree.metadata
+                    .push((path.to_path_buf(), key_path.data_key, metadata_key))
+            }
+            None => {
+                tree.data_keys.insert(key_path.data_key);
+            }
+        }
+    }
+
+    Ok(tree)
+}
+
+/// Reports metadata in the given tree whose data key isn't populated, and isn't a parent of a
+/// populated key that would inherit the metadata.  Pending metadata can also describe live keys.
+fn check_orphans(tree: &Tree, live: Option<&Tree>, problems: &mut Vec<Problem>) {
+    let mut data_keys = tree.data_keys.iter().collect::<Vec<_>>();
+    if let Some(live) = live {
+        data_keys.extend(&live.data_keys);
+    }
+
+    for (path, data_key, metadata_key) in &tree.metadata {
+        let used = data_keys
+            .iter()
+            .any(|key| key.ancestors().contains(&data_key.as_ref()));
+        if !used {
+            problems.push(Problem {
+                path: path.clone(),
+                kind: ProblemKind::OrphanedMetadata {
+                    data_key: data_key.clone(),
+                    metadata_key: metadata_key.clone(),
+                },
+            });
+        }
+    }
+}
+
+/// Moves the files with quarantinable problems out of the data store at the given directory, into
+/// the quarantine directory next to live and pending, keeping their relative paths.  Returns the
+/// new path of each file moved.
+pub fn quarantine<P: AsRef<Path>>(datastore_path: P, problems: &[Problem]) -> Result<Vec<PathBuf>> {
+    let datastore_path = datastore_path.as_ref();
+    let quarantine_path = datastore_path.join(QUARANTINE_DIR);
+
+    let mut moved = Vec::new();
+    for problem in problems.iter().filter(|p| p.kind.quarantinable()) {
+        // A file can have more than one problem, or be inside a transaction we already moved.
+        if !problem.path.exists() {
+            continue;
+        }
+        let relative = problem
+            .path
+            .strip_prefix(datastore_path)
+            .context(error::Path)?;
+        let target = quarantine_path.join(relative);
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).context(error::Io { path: parent })?;
+        }
+        debug!("Moving {} to {}", problem.path.display(), target.display());
+        fs::rename(&problem.path, &target).context(error::Io { path: &target })?;
+        moved.push(target.clone());
+
+        // Take the real name of a hashed file along with it.
+        let file_name = problem.path.file_name().unwrap_or_default();
+        if file_name.to_string_lossy().starts_with(LONG_NAME_PREFIX) {
+            let mut sidecar_name = file_name.to_os_string();
+            sidecar_name.push(LONG_NAME_SUFFIX);
+            let sidecar_path = problem.path.with_file_name(&sidecar_name);
+            if sidecar_path.exists() {
+                let target = target.with_file_name(&sidecar_name);
+                fs::rename(&sidecar_path, &target).context(error::Io { path: &target })?;
+                moved.push(target);
+            }
+        }
+    }
+
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::datastore::{Committed, DataStore, FilesystemDataStore, KeyType};
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    fn datastore() -> (TempDir, FilesystemDataStore) {
+        let dir = TempDir::new().unwrap();
+        let mut f = FilesystemDataStore::new(dir.path());
+        let hostname = Key::new(KeyType::Data, "settings.hostname").unwrap();
+        let affected = Key::new(KeyType::Meta, "affected-services").unwrap();
+        f.set_key(&hostname, "\"abc\"", &Committed::Live).unwrap();
+        f.set_metadata(&affected, &hostname, "[\"hostname\"]", &Committed::Live)
+            .unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        f.set_key(&hostname, "\"def\"", &pending).unwrap();
+        (dir, f)
+    }
+
+    #[test]
+    fn clean() {
+        let (dir, _f) = datastore();
+        assert_eq!(check_datastore(dir.path()).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn problems_found_and_quarantined() {
+        let (dir, mut f) = datastore();
+        let live = dir.path().join(LIVE_DIR);
+        fs::write(live.join("settings/timezone"), "not json").unwrap();
+        fs::write(live.join("settings/bad%2e"), "1").unwrap();
+        fs::write(dir.path().join("pending/bad name"), "").unwrap();
+        let generated = Key::new(KeyType::Data, "settings.generated").unwrap();
+        let generator = Key::new(KeyType::Meta, "setting-generator").unwrap();
+        f.set_metadata(&generator, &generated, "\"gen\"", &Committed::Live)
+            .unwrap();
+
+        // A hashed name for a segment that's short enough not to need one
+        let hashed = live.join("#0123");
+        fs::write(hashed.with_file_name("#0123#name"), "short").unwrap();
+        fs::write(&hashed, "1").unwrap();
+
+        let problems = check_datastore(dir.path()).unwrap();
+        let kinds: Vec<_> = problems.iter().map(|p| &p.kind).collect();
+        assert_eq!(problems.len(), 5, "{:?}", problems);
+        assert!(kinds.contains(&&ProblemKind::InvalidTransaction));
+        assert!(kinds.contains(&&ProblemKind::MisplacedKey {
+            expected: live.join("short")
+        }));
+        assert!(kinds.contains(&&ProblemKind::OrphanedMetadata {
+            data_key: generated,
+            metadata_key: generator,
+        }));
+        assert!(problems
+            .iter()
+            .any(|p| p.path == live.join("settings/timezone") && matches_invalid_value(&p.kind)));
+        assert!(problems
+            .iter()
+            .any(|p| p.path == live.join("settings/bad%2e")));
+
+        // Everything but the orphaned metadata is moved aside, and the rest of the data store
+        // still works
+        let moved = quarantine(dir.path(), &problems).unwrap();
+        assert_eq!(moved.len(), 5);
+        assert!(dir
+            .path()
+            .join(QUARANTINE_DIR)
+            .join("live/settings/timezone")
+            .exists());
+        let remaining = check_datastore(dir.path()).unwrap();
+        assert!(remaining.iter().all(|p| p.kind.is_warning()));
+        f.get_prefix("settings", &Committed::Live).unwrap();
+    }
+
+    fn matches_invalid_value(kind: &ProblemKind) -> bool {
+        match kind {
+            ProblemKind::InvalidValue(_) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn links() {
+        let dir = TempDir::new().unwrap();
+        let base = dir.path();
+        fs::create_dir(base.join("v1.5_0123456789abcdef")).unwrap();
+        symlink("v1.5_0123456789abcdef", base.join("v1.5")).unwrap();
+        symlink("v1.5", base.join("v1")).unwrap();
+        symlink("v1", base.join("current")).unwrap();
+
+        let (problems, path) = check_links(base.join("current")).unwrap();
+        assert!(problems.is_empty());
+        assert_eq!(path, Some(base.join("v1.5_0123456789abcdef")));
+
+        // The minor version link has to match the major version
+        fs::remove_file(base.join("v1")).unwrap();
+        symlink("v2.5", base.join("v1")).unwrap();
+        let (problems, path) = check_links(base.join("current")).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].path, base.join("v1"));
+        assert_eq!(path, None);
+
+        // And the data store has to exist
+        fs::remove_file(base.join("v1")).unwrap();
+        symlink("v1.5", base.join("v1")).unwrap();
+        fs::remove_dir(base.join("v1.5_0123456789abcdef")).unwrap();
+        let (problems, _) = check_links(base.join("current")).unwrap();
+        assert_eq!(problems[0].path, base.join("v1.5"));
+    }
+}
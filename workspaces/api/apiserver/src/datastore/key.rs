@@ -15,9 +15,19 @@ pub const KEY_SEPARATOR: &str = ".";
 /// The character set was chosen to match TOML for ease of serialization.
 pub const KEY_SEGMENT_STR: &str = "[a-zA-Z0-9_-]+";
 
-/// Maximum key name length matches the maximum filename length of 255; if we need to have longer
-/// keys (up to 4096) we could make prefixes not count against this limit.
-const MAX_KEY_NAME_LENGTH: usize = 255;
+/// String that can be used in a regex to validate quoted segments of data key names.  Quoting
+/// allows map keys with characters like "." and "/", for example Kubernetes labels, without
+/// implying nesting.  Quotes and backslashes aren't allowed inside, so there's no escaping.
+pub const QUOTED_SEGMENT_STR: &str = r#""[^"\\\x00-\x1f]+""#;
+
+/// String that can be used in a regex to validate the index that can follow the final segment of
+/// a data key name, pointing to one element of a list, e.g. "settings.ntp.time-servers[2]".
+pub const KEY_INDEX_STR: &str = r"\[(0|[1-9][0-9]*)\]";
+
+/// Maximum key name length matches the maximum path length, PATH_MAX.  FilesystemDataStore
+/// hashes any segment that's too long for a filename, and separately checks that the full path
+/// for a key, under its base path, fits.
+const MAX_KEY_NAME_LENGTH: usize = 4096;
 
 lazy_static! {
     /// Pattern to validate a single key name segment, e.g. between separators.
@@ -25,10 +35,21 @@ lazy_static! {
         &format!(r"^{segment}$", segment=KEY_SEGMENT_STR)
     ).unwrap();
 
+    /// Pattern to validate a quoted key name segment.
+    pub(crate) static ref QUOTED_SEGMENT: Regex = Regex::new(
+        &format!(r"^{quoted}$", quoted=QUOTED_SEGMENT_STR)
+    ).unwrap();
+
     /// Pattern to validate a user-specified data key.
-    // Optional dot-separated prefix segments, with at least one final segment.
+    // Optional dot-separated prefix segments, with at least one final segment, which can have an
+    // index.  Any segment can be bare or quoted.
     pub(crate) static ref DATA_KEY: Regex = Regex::new(
-        &format!(r"^(?P<prefix>({segment}\.)*)(?P<final>{segment})$", segment=KEY_SEGMENT_STR)
+        &format!(
+            r"^(?P<prefix>(({segment}|{quoted})\.)*)(?P<final>({segment}|{quoted})({index})?)$",
+            segment=KEY_SEGMENT_STR,
+            quoted=QUOTED_SEGMENT_STR,
+            index=KEY_INDEX_STR,
+        )
     ).unwrap();
 
     /// Pattern to validate a user-specified metadata key.
@@ -46,6 +67,10 @@ pub enum KeyType {
 /// A Key is a pointer into the datastore with a convenient name.  Their names are simply dotted
 /// strings ("a.b.c") with the dots implying hierarchy, so "a.b.c" and "a.b.d" are probably
 /// related.
+///
+/// Data key segments can be quoted to include dots without implying hierarchy, like
+/// `a."b.c"`, and the final segment can have an index pointing to one element of a list, like
+/// `a.b[2]`.
 // Note: it's important that Key only has the name String, or that it otherwise hashes the same as
 // the name String, so that deserializing with from_map behaves the same whether we have a map
 // whose keys are Strings or Keys containing those Strings.  If we wanted to store KeyType in the
@@ -84,6 +109,102 @@ impl Key {
         let copy = name.to_string();
         Ok(Key { name: copy })
     }
+
+    /// Returns the segments of the key name, as written, e.g. `a`, `"b.c"`, and `d[2]` for
+    /// `a."b.c".d[2]`.
+    pub fn segments(&self) -> Vec<&str> {
+        let mut segments = Vec::new();
+        let mut rest = Some(self.name.as_str());
+        while let Some(name) = rest {
+            let (segment, remainder) = split_first_segment(name);
+            segments.push(segment);
+            rest = remainder;
+        }
+        segments
+    }
+
+    /// Returns the names of this key and its ancestors, from the root down, e.g. `a`, `a.b`,
+    /// `a.b.c`, and `a.b.c[2]` for `a.b.c[2]`.  A list element's parent is the list.
+    pub fn ancestors(&self) -> Vec<&str> {
+        let mut ancestors = Vec::new();
+        let mut end = 0;
+        for segment in self.segments() {
+            if end > 0 {
+                end += KEY_SEPARATOR.len();
+            }
+            if let (name, Some(_)) = split_index(segment) {
+                ancestors.push(&self.name[..end + name.len()]);
+            }
+            end += segment.len();
+            ancestors.push(&self.name[..end]);
+        }
+        ancestors
+    }
+
+    /// If this key points to an element of a list, like `a.b[2]`, returns the key of the list and
+    /// the index of the element.
+    pub fn list_element(&self) -> Option<(Key, usize)> {
+        match split_index(&self.name) {
+            (list, Some(index)) => Some((
+                Key {
+                    name: list.to_string(),
+                },
+                index,
+            )),
+            (_, None) => None,
+        }
+    }
+}
+
+/// Splits a key name into its first segment, as written, and the rest of the name after the
+/// separator, if there is more.  Separators inside quoted segments don't count.
+pub(crate) fn split_first_segment(name: &str) -> (&str, Option<&str>) {
+    let mut quoted = false;
+    for (i, c) in name.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '.' if !quoted => return (&name[..i], Some(&name[i + 1..])),
+            _ => {}
+        }
+    }
+    (name, None)
+}
+
+/// Splits a list index, if any, from the end of a key segment or name, e.g. `b[2]` into `b` and
+/// `Some(2)`.
+pub(crate) fn split_index(segment: &str) -> (&str, Option<usize>) {
+    // Quoted segments can contain brackets, but an index always comes after the closing quote.
+    if segment.ends_with(']') {
+        if let Some(start) = segment.rfind('[') {
+            if let Ok(index) = segment[start + 1..segment.len() - 1].parse() {
+                return (&segment[..start], Some(index));
+            }
+        }
+    }
+    (segment, None)
+}
+
+/// Returns the given map key as a data key segment, quoting it if it isn't a valid bare segment,
+/// or None if it can't be represented.
+pub(crate) fn quote_segment(name: &str) -> Option<String> {
+    if KEY_SEGMENT.is_match(name) {
+        return Some(name.to_string());
+    }
+    let quoted = format!("\"{}\"", name);
+    if QUOTED_SEGMENT.is_match(&quoted) {
+        Some(quoted)
+    } else {
+        None
+    }
+}
+
+/// Returns the name inside a key segment, removing quotes if it's quoted.
+pub(crate) fn unquote_segment(segment: &str) -> &str {
+    if segment.len() >= 2 && segment.starts_with('"') && segment.ends_with('"') {
+        &segment[1..segment.len() - 1]
+    } else {
+        segment
+    }
 }
 
 // These trait implementations let you treat a Key like a string most of the time.
@@ -132,7 +253,9 @@ impl Serialize for Key {
 
 #[cfg(test)]
 mod test {
-    use super::{Key, KeyType, DATA_KEY, KEY_SEGMENT, MAX_KEY_NAME_LENGTH, METADATA_KEY};
+    use super::{
+        quote_segment, Key, KeyType, DATA_KEY, KEY_SEGMENT, MAX_KEY_NAME_LENGTH, METADATA_KEY,
+    };
 
     // Helper macro for testing conditions that apply to both data and metadata keys
     macro_rules! data_and_meta {
@@ -187,6 +310,53 @@ mod test {
         data_and_meta!(|t| assert!(Key::new(t, "a.").is_err()));
     }
 
+    #[test]
+    fn quoted_data_key_ok() {
+        assert!(Key::new(KeyType::Data, r#"a."app.io/role".c"#).is_ok());
+        assert!(Key::new(KeyType::Meta, r#""app.io/role""#).is_err());
+        assert!(Key::new(KeyType::Data, r#"a."b"c""#).is_err());
+        assert!(Key::new(KeyType::Data, r#"a."""#).is_err());
+        assert!(Key::new(KeyType::Data, r#"a."b"#).is_err());
+    }
+
+    #[test]
+    fn indexed_data_key_ok() {
+        assert!(Key::new(KeyType::Data, "a.b[0]").is_ok());
+        assert!(Key::new(KeyType::Data, r#"a."b.c"[12]"#).is_ok());
+        assert!(Key::new(KeyType::Meta, "a[0]").is_err());
+        // Only the final segment can have an index, and only one
+        assert!(Key::new(KeyType::Data, "a[0].b").is_err());
+        assert!(Key::new(KeyType::Data, "a[0][1]").is_err());
+        assert!(Key::new(KeyType::Data, "a[01]").is_err());
+        assert!(Key::new(KeyType::Data, "a[-1]").is_err());
+    }
+
+    #[test]
+    fn key_segments() {
+        let key = Key::new(KeyType::Data, r#"a."b.c[1]".d[2]"#).unwrap();
+        assert_eq!(key.segments(), vec!["a", r#""b.c[1]""#, "d[2]"]);
+        assert_eq!(
+            key.ancestors(),
+            vec![
+                "a",
+                r#"a."b.c[1]""#,
+                r#"a."b.c[1]".d"#,
+                r#"a."b.c[1]".d[2]"#
+            ]
+        );
+        let (list, index) = key.list_element().unwrap();
+        assert_eq!((list.as_ref(), index), (r#"a."b.c[1]".d"#, 2));
+        assert!(list.list_element().is_none());
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(quote_segment("a-b").unwrap(), "a-b");
+        assert_eq!(quote_segment("app.io/role").unwrap(), r#""app.io/role""#);
+        assert!(quote_segment(r#"a"b"#).is_none());
+        assert!(quote_segment("").is_none());
+    }
+
     #[test]
     fn segment_regex() {
         assert!(KEY_SEGMENT.is_match("abcd123_-"));
@@ -205,6 +375,7 @@ mod test {
     fn data_regex() {
         assert!(DATA_KEY.is_match("abcd123_-"));
         assert!(DATA_KEY.is_match("abcd.123"));
+        assert!(DATA_KEY.is_match(r#"abcd."1.2/3"[4]"#));
         assert!(!DATA_KEY.is_match("!"));
     }
 }
\ No newline at end of file
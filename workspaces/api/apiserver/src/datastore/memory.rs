@@ -0,0 +1,448 @@
+//! In-memory datastore, for testing code that uses a data store, like migrations and the server
+//! controller.  It's built with the "memory-datastore" feature, and is always available to this
+//! crate's tests.
+//!
+//! Mimics the decisions made for FilesystemDataStore: transaction names must be valid key
+//! segments, and committing a transaction moves its settings and metadata to live and drops the
+//! transaction.
+//!
+//! Tests can start from a fixture, a TOML file with live data, pending transactions, and live
+//! metadata in the format of storewolf's defaults.toml:
+//!
+//! ```toml
+//! [live.settings]
+//! hostname = "example"
+//!
+//! [pending.my-tx.settings]
+//! timezone = "America/Los_Angeles"
+//!
+//! [[metadata]]
+//! key = "settings.hostname"
+//! md = "affected-services"
+//! val = ["hostname"]
+//! ```
+
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use super::serialization::to_pairs;
+use super::{
+    commits_key, error, fold_list_elements, serialize_scalar, valid_transaction, Committed,
+    DataStore, Key, KeyType, Result, ScalarError,
+};
+
+/// The contents of a fixture file; see the module docs.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Fixture {
+    #[serde(default)]
+    live: toml::value::Table,
+    #[serde(default)]
+    pending: HashMap<String, toml::value::Table>,
+    #[serde(default)]
+    metadata: Vec<FixtureMetadata>,
+}
+
+/// A metadata value for a data key, as in storewolf's defaults.toml.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixtureMetadata {
+    key: String,
+    md: String,
+    val: toml::Value,
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryDataStore {
+    // Uncommitted (pending) data and metadata, keyed by transaction name.
+    pending: HashMap<String, Dataset>,
+    // Committed (live) data and metadata.
+    live: Dataset,
+}
+
+/// The data and metadata for live, or for one pending transaction.
+#[derive(Debug, Default)]
+struct Dataset {
+    data: HashMap<Key, String>,
+    // Map of data keys to their metadata, which in turn is a mapping of metadata keys to
+    // arbitrary (string/serialized) values.
+    metadata: HashMap<Key, HashMap<Key, String>>,
+}
+
+impl MemoryDataStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Builds a data store from the fixture file at the given path; see the module docs.
+    pub fn from_fixture_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let fixture_str = fs::read_to_string(path).context(error::Io { path })?;
+        Self::from_fixture_str(&fixture_str)
+    }
+
+    /// Builds a data store from a fixture given as a TOML string; see the module docs.
+    pub fn from_fixture_str(fixture_str: &str) -> Result<Self> {
+        let fixture: Fixture = toml::from_str(fixture_str).context(error::FixtureParse)?;
+        let mut datastore = Self::new();
+
+        let live = to_pairs(&fixture.live).context(error::Serialization {
+            given: "fixture live data",
+        })?;
+        datastore.set_keys(&live, &Committed::Live)?;
+
+        for (tx, data) in fixture.pending {
+            let pending = to_pairs(&data).context(error::Serialization {
+                given: format!("fixture transaction '{}'", tx),
+            })?;
+            datastore.set_keys(&pending, &Committed::Pending { tx })?;
+        }
+
+        for FixtureMetadata { key, md, val } in fixture.metadata {
+            let data_key = Key::new(KeyType::Data, &key)?;
+            let metadata_key = Key::new(KeyType::Meta, &md)?;
+            let value =
+                serialize_scalar::<_, ScalarError>(&val).context(error::SerializeScalar {
+                    given: format!("fixture metadata '{}' for '{}'", md, key),
+                })?;
+            datastore.set_metadata(&metadata_key, &data_key, value, &Committed::Live)?;
+        }
+
+        Ok(datastore)
+    }
+
+    /// Returns the data for the given committed state, if any exists.
+    fn dataset(&self, committed: &Committed) -> Result<Option<&Dataset>> {
+        match committed {
+            Committed::Live => Ok(Some(&self.live)),
+            Committed::Pending { tx } => Ok(self.pending.get(valid_transaction(tx)?)),
+        }
+    }
+
+    /// Returns the data for the given committed state, creating the transaction if needed.
+    fn dataset_mut(&mut self, committed: &Committed) -> Result<&mut Dataset> {
+        match committed {
+            Committed::Live => Ok(&mut self.live),
+            Committed::Pending { tx } => Ok(self
+                .pending
+                .entry(valid_transaction(tx)?.to_string())
+                .or_default()),
+        }
+    }
+}
+
+impl DataStore for MemoryDataStore {
+    fn list_populated_keys<S: AsRef<str>>(
+        &self,
+        prefix: S,
+        committed: &Committed,
+    ) -> Result<HashSet<Key>> {
+        let dataset = match self.dataset(committed)? {
+            Some(dataset) => dataset,
+            None => return Ok(HashSet::new()),
+        };
+        Ok(dataset
+            .data
+            .keys()
+            .filter(|key| key.starts_with(prefix.as_ref()))
+            .cloned()
+            .collect())
+    }
+
+    fn list_populated_metadata<S1, S2>(
+        &self,
+        prefix: S1,
+        metadata_key_name: &Option<S2>,
+        committed: &Committed,
+    ) -> Result<HashMap<Key, HashSet<Key>>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let dataset = match self.dataset(committed)? {
+            Some(dataset) => dataset,
+            None => return Ok(HashMap::new()),
+        };
+
+        let mut result = HashMap::new();
+        for (data_key, meta_map) in dataset.metadata.iter() {
+            // Confirm data key matches requested prefix.
+            if !data_key.starts_with(prefix.as_ref()) {
+                continue;
+            }
+
+            let mut meta_for_data = HashSet::new();
+            for meta_key in meta_map.keys() {
+                // Confirm metadata key matches requested name, if any.
+                if let Some(name) = metadata_key_name {
+                    if name.as_ref() != meta_key.as_ref() {
+                        continue;
+                    }
+                }
+                meta_for_data.insert(meta_key.clone());
+            }
+            // Only add an entry for the data key if we found metadata.
+            if !meta_for_data.is_empty() {
+                result.insert(data_key.clone(), meta_for_data);
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn get_key(&self, key: &Key, committed: &Committed) -> Result<Option<String>> {
+        Ok(self
+            .dataset(committed)?
+            .and_then(|dataset| dataset.data.get(key))
+            .cloned())
+    }
+
+    fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()> {
+        self.dataset_mut(committed)?
+            .data
+            .insert(key.clone(), value.as_ref().to_owned());
+        Ok(())
+    }
+
+    fn key_populated(&self, key: &Key, committed: &Committed) -> Result<bool> {
+        Ok(self
+            .dataset(committed)?
+            .map(|dataset| dataset.data.contains_key(key))
+            .unwrap_or(false))
+    }
+
+    fn get_metadata_raw(
+        &self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<Option<String>> {
+        let metadata_for_data = self
+            .dataset(committed)?
+            .and_then(|dataset| dataset.metadata.get(data_key));
+        // If we have a metadata entry for this data key, then we can try fetching the requested
+        // metadata key, otherwise we'll return early with Ok(None).
+        let result = metadata_for_data.and_then(|m| m.get(metadata_key));
+        Ok(result.cloned())
+    }
+
+    fn set_metadata<S: AsRef<str>>(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        value: S,
+        committed: &Committed,
+    ) -> Result<()> {
+        // If we don't already have a metadata entry for this data key, insert one.
+        let metadata_for_data = self
+            .dataset_mut(committed)?
+            .metadata
+            .entry(data_key.clone())
+            .or_insert_with(HashMap::new);
+
+        metadata_for_data.insert(metadata_key.clone(), value.as_ref().to_owned());
+        Ok(())
+    }
+
+    fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        // Remove the transaction so it's no longer pending, and apply its settings and metadata
+        // to live.
+        let tx = valid_transaction(transaction.as_ref())?;
+        fold_list_elements(self, tx)?;
+        let pending = self.pending.remove(tx).unwrap_or_default();
+        let committed: HashMap<Key, String> = pending
+            .data
+            .into_iter()
+            .filter(|(key, _)| commits_key(key))
+            .collect();
+        let pending_keys = committed.keys().cloned().collect();
+        self.live.data.extend(committed);
+        for (data_key, metadata) in pending.metadata {
+            self.live
+                .metadata
+                .entry(data_key)
+                .or_default()
+                .extend(metadata);
+        }
+        Ok(pending_keys)
+    }
+
+    fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        let tx = valid_transaction(transaction.as_ref())?;
+        let pending = self.pending.remove(tx).unwrap_or_default();
+        Ok(pending.data.keys().cloned().collect())
+    }
+
+    fn list_transactions(&self) -> Result<HashSet<String>> {
+        Ok(self.pending.keys().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Committed, DataStore, Key, KeyType};
+    use super::MemoryDataStore;
+    use maplit::hashset;
+
+    #[test]
+    fn get_set() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "memtest").unwrap();
+        let v = "memvalue";
+        m.set_key(&k, v, &Committed::Live).unwrap();
+        assert_eq!(
+            m.get_key(&k, &Committed::Live).unwrap(),
+            Some(v.to_string())
+        );
+
+        let mdkey = Key::new(KeyType::Meta, "testmd").unwrap();
+        let md = "mdval";
+        m.set_metadata(&mdkey, &k, md, &Committed::Live).unwrap();
+        assert_eq!(
+            m.get_metadata_raw(&mdkey, &k, &Committed::Live).unwrap(),
+            Some(md.to_string())
+        );
+    }
+
+    #[test]
+    fn populated() {
+        let mut m = MemoryDataStore::new();
+        let k1 = Key::new(KeyType::Data, "memtest1").unwrap();
+        let k2 = Key::new(KeyType::Data, "memtest2").unwrap();
+        let v = "memvalue";
+        m.set_key(&k1, v, &Committed::Live).unwrap();
+        let tx = Committed::Pending {
+            tx: "testtx".into(),
+        };
+        m.set_key(&k2, v, &tx).unwrap();
+
+        assert!(m.key_populated(&k1, &Committed::Live).unwrap());
+        assert!(m.key_populated(&k2, &tx).unwrap());
+        assert!(!m.key_populated(&k1, &tx).unwrap());
+        assert!(!m.key_populated(&k2, &Committed::Live).unwrap());
+    }
+
+    #[test]
+    fn commit() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
+        let v = "memvalue";
+        let tx = Committed::Pending {
+            tx: "testtx".into(),
+        };
+        m.set_key(&k, v, &tx).unwrap();
+
+        assert!(m.key_populated(&k, &tx).unwrap());
+        assert!(!m.key_populated(&k, &Committed::Live).unwrap());
+        m.commit_transaction("testtx").unwrap();
+        assert!(!m.key_populated(&k, &tx).unwrap());
+        assert!(m.key_populated(&k, &Committed::Live).unwrap());
+    }
+
+    #[test]
+    fn commit_only_settings() {
+        let mut m = MemoryDataStore::new();
+        let setting = Key::new(KeyType::Data, "settings.a").unwrap();
+        let other = Key::new(KeyType::Data, "services.a").unwrap();
+        let tx = Committed::Pending {
+            tx: "testtx".into(),
+        };
+        m.set_key(&setting, "1", &tx).unwrap();
+        m.set_key(&other, "2", &tx).unwrap();
+
+        assert_eq!(m.commit_transaction("testtx").unwrap(), hashset!(setting));
+        assert!(!m.key_populated(&other, &Committed::Live).unwrap());
+        assert!(m.list_transactions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn commit_list_elements() {
+        let mut m = MemoryDataStore::new();
+        let list = Key::new(KeyType::Data, "settings.list").unwrap();
+        let tx = Committed::Pending {
+            tx: "testtx".into(),
+        };
+        m.set_key(&list, "[\"a\",\"b\"]", &Committed::Live).unwrap();
+        for (name, value) in &[("settings.list[1]", "\"x\""), ("settings.list[2]", "\"y\"")] {
+            let element = Key::new(KeyType::Data, name).unwrap();
+            m.set_key(&element, value, &tx).unwrap();
+        }
+
+        assert_eq!(
+            m.commit_transaction("testtx").unwrap(),
+            hashset!(list.clone())
+        );
+        assert_eq!(
+            m.get_key(&list, &Committed::Live).unwrap(),
+            Some("[\"a\",\"x\",\"y\"]".to_string())
+        );
+        assert_eq!(
+            m.list_populated_keys("", &Committed::Live).unwrap(),
+            hashset!(list.clone())
+        );
+
+        // Elements can't leave a gap
+        let element = Key::new(KeyType::Data, "settings.list[4]").unwrap();
+        m.set_key(&element, "\"z\"", &tx).unwrap();
+        m.commit_transaction("testtx").unwrap_err();
+    }
+
+    #[test]
+    fn invalid_transaction() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let tx = Committed::Pending { tx: "../x".into() };
+        m.set_key(&k, "1", &tx).unwrap_err();
+        m.key_populated(&k, &tx).unwrap_err();
+        m.commit_transaction("../x").unwrap_err();
+    }
+
+    #[test]
+    fn fixture() {
+        let m = MemoryDataStore::from_fixture_str(
+            r#"
+            [live.settings]
+            hostname = "example"
+
+            [pending.tx1.settings]
+            timezone = "tz"
+
+            [[metadata]]
+            key = "settings.hostname"
+            md = "affected-services"
+            val = ["hostname"]
+            "#,
+        )
+        .unwrap();
+
+        let hostname = Key::new(KeyType::Data, "settings.hostname").unwrap();
+        let timezone = Key::new(KeyType::Data, "settings.timezone").unwrap();
+        let pending = Committed::Pending { tx: "tx1".into() };
+        assert_eq!(
+            m.get_key(&hostname, &Committed::Live).unwrap(),
+            Some("\"example\"".to_string())
+        );
+        assert_eq!(
+            m.get_key(&timezone, &pending).unwrap(),
+            Some("\"tz\"".to_string())
+        );
+        assert_eq!(m.get_key(&timezone, &Committed::Live).unwrap(), None);
+        let affected_services = Key::new(KeyType::Meta, "affected-services").unwrap();
+        assert_eq!(
+            m.get_metadata_raw(&affected_services, &hostname, &Committed::Live)
+                .unwrap(),
+            Some("[\"hostname\"]".to_string())
+        );
+
+        MemoryDataStore::from_fixture_str("[surprise]").unwrap_err();
+    }
+}
@@ -1,84 +1,206 @@
-
 //! The datastore module contains the DataStore trait, which describes a key/value storage system
 //! with metadata and simple transactions.
 //!
 //! There's also a common error type and some methods that implementations of DataStore should
 //! generally share, like scalar serialization.
 //!
+//! FilesystemDataStore keeps each key in its own file, and SqliteDataStore keeps everything in a
+//! single SQLite file; see the backend module for choosing between them at runtime.
+//!
 //! We represent scalars -- the actual values stored under a datastore key -- using JSON, just to
 //! have a convenient human-readable form.  (TOML doesn't allow raw scalars.  The JSON spec
 //! doesn't seem to either, but this works, and the format is so simple for scalars that it could
 //! be easily swapped out if needed.)
 
+pub mod backend;
 pub mod deserialization;
 pub mod error;
+pub mod filesystem;
+pub mod fsck;
 pub mod key;
-#[cfg(test)]
-pub(crate) mod memory;
+#[cfg(any(test, feature = "memory-datastore"))]
+pub mod memory;
 pub mod serialization;
+pub mod snapshot;
+pub mod sqlite;
 
+pub use backend::{AnyDataStore, Backend};
 pub use error::{Error, Result};
-pub use filesystem::FileSystemDataStore;
+pub use filesystem::FilesystemDataStore;
 pub use key::{Key, KeyType, KEY_SEPARATOR};
+#[cfg(any(test, feature = "memory-datastore"))]
+pub use memory::MemoryDataStore;
+pub use snapshot::Snapshot;
+pub use sqlite::SqliteDataStore;
 
 use serde::{Deserialize, Serialize};
-use snafu::OptionExt;
-use std::collections::{HashMap, HashSet};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use key::KEY_SEGMENT;
 
 /// Committed represents whether we want to look at pending (uncommitted) or live (committed) data
-/// in the datastore.
-#[derive(Debug, Copy, Clone)]
+/// in the datastore.  Pending data is grouped into named transactions, so that separate callers
+/// can stage changes without committing each other's work.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Committed {
-    Pending,
     Live,
+    Pending {
+        // If the change is pending, we need to know which transaction it belongs to.
+        tx: String,
+    },
+}
+
+/// Only keys under this prefix are committed from a pending transaction to live; anything else
+/// staged in the transaction is dropped with it.
+pub(crate) const COMMIT_PREFIX: &str = "settings.";
+
+/// Returns whether the given pending key is committed to live.  List elements, like
+/// "settings.ntp.time-servers[2]", aren't committed themselves; fold_list_elements merges them
+/// into their list first.
+pub(crate) fn commits_key(key: &Key) -> bool {
+    key.starts_with(COMMIT_PREFIX) && key.list_element().is_none()
+}
+
+/// Merges list elements staged in a transaction, like "settings.ntp.time-servers[2]", into their
+/// list, so a single element can be changed without restating the list.  The list comes from the
+/// transaction if it sets the whole list too, otherwise from live data.
+///
+/// Implementations of commit_transaction should call this first, or do the same as part of
+/// their own atomic commit, and then only commit keys accepted by commits_key.
+pub(crate) fn fold_list_elements<D: DataStore>(datastore: &mut D, transaction: &str) -> Result<()> {
+    let pending = Committed::Pending {
+        tx: transaction.to_string(),
+    };
+    let data = datastore.get_prefix(COMMIT_PREFIX, &pending)?;
+
+    // Group elements by list.
+    let mut lists: HashMap<Key, BTreeMap<usize, &str>> = HashMap::new();
+    for (key, value) in &data {
+        if let Some((list, index)) = key.list_element() {
+            lists.entry(list).or_default().insert(index, value);
+        }
+    }
+
+    for (list_key, elements) in lists {
+        let existing = match data.get(&list_key) {
+            Some(value) => Some(value.clone()),
+            None => datastore.get_key(&list_key, &Committed::Live)?,
+        };
+        let list =
+            merge_list_elements(&list_key, existing.as_ref().map(String::as_str), &elements)?;
+        let value = serialize_scalar::<_, ScalarError>(&list).context(error::SerializeScalar {
+            given: list_key.as_ref(),
+        })?;
+        datastore.set_key(&list_key, value, &pending)?;
+    }
+    Ok(())
+}
+
+/// Applies serialized list elements, by index, to the given serialized list, or to an empty list
+/// if there isn't one.  Each element replaces an existing element or is appended; indexes past
+/// the end are an error.
+pub(crate) fn merge_list_elements(
+    list_key: &str,
+    list: Option<&str>,
+    elements: &BTreeMap<usize, &str>,
+) -> Result<Vec<Value>> {
+    let mut list: Vec<Value> = match list {
+        Some(value) => deserialize_scalar::<_, ScalarError>(value)
+            .context(error::NotAList { key: list_key })?,
+        None => Vec::new(),
+    };
+
+    // BTreeMap iterates in index order, so appends happen in order.
+    for (&index, value) in elements {
+        let value = deserialize_scalar::<_, ScalarError>(value)
+            .context(error::DeserializeScalar { given: *value })?;
+        ensure!(
+            index <= list.len(),
+            error::ListIndex {
+                key: format!("{}[{}]", list_key, index),
+                len: list.len(),
+            }
+        );
+        if index == list.len() {
+            list.push(value);
+        } else {
+            list[index] = value;
+        }
+    }
+    Ok(list)
+}
+
+/// Checks that a transaction name is a valid key segment.  FilesystemDataStore uses transaction
+/// names as directory names, and the other backends hold them to the same standard so that all
+/// backends accept the same names.
+pub(crate) fn valid_transaction(tx: &str) -> Result<&str> {
+    ensure!(
+        KEY_SEGMENT.is_match(tx),
+        error::InvalidTransaction {
+            name: tx,
+            pattern: KEY_SEGMENT.clone(),
+        }
+    );
+    Ok(tx)
 }
 
 pub trait DataStore {
     /// returns whether a key is present in the datastore
-    fn key_populated(&self, key: &Key, committed: Committed) -> Result<bool>;
+    fn key_populated(&self, key: &Key, committed: &Committed) -> Result<bool>;
+    /// Returns a list of the populated data keys in the datastore whose names start with the
+    /// given prefix.
     fn list_populated_keys<S: AsRef<str>>(
         &self,
         prefix: S,
-        committed: Committed,
-    ) -> Result<HashSet<key>>;
+        committed: &Committed,
+    ) -> Result<HashSet<Key>>;
 
     /// Finds all metadata keys that are currently populated in the datastore whose data keys
     /// start with the given prefix.  If you specify metadata_key_name, only metadata keys with
     /// that name will be returned.
     ///
     /// Returns a mapping of the data keys to the set of populated metadata keys for each.
-    
     fn list_populated_metadata<S1, S2>(
         &self,
         prefix: S1,
-        metadata_key_name: &option<S2>
-    ) -> Result<HashMap<key, HashSet<key>>>
+        metadata_key_name: &Option<S2>,
+        committed: &Committed,
+    ) -> Result<HashMap<Key, HashSet<Key>>>
     where
-        s1: AsRef<str>,
-        s2: AsRef<str>;
+        S1: AsRef<str>,
+        S2: AsRef<str>;
 
     /// Retrieve the value for a single data key from the datastore.
-    fn get_key(&self, key: &Key, committed: Committed) -> Result<Option<String>>;
+    fn get_key(&self, key: &Key, committed: &Committed) -> Result<Option<String>>;
     /// Set the value of a single data key in the datastore.
-    fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: Committed) -> Result<()>;
+    fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed)
+        -> Result<()>;
 
-     /// Retrieve the value for a single metadata key from the datastore.  Values will inherit from
+    /// Retrieve the value for a single metadata key from the datastore.  Values will inherit from
     /// earlier in the tree, if more specific values are not found later.
-    fn get_metadata(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>> {
+    ///
+    /// Like data, metadata can be pending in a transaction until it's committed.  When looking
+    /// at a transaction, each key's pending metadata is used if it has any, and its live metadata
+    /// otherwise, since that's what the key will have once the transaction is committed.
+    fn get_metadata(
+        &self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<Option<String>> {
         let mut result = Ok(None);
-        let mut current_path = String::with_capacity(data_key.len());
-
-        for component in data_key.split(KEY_SEPARATOR) {
-            if !current_path.is_empty() {
-                current_path.push(KEY_SEPARATOR);
-            }
-            current_path.push_str(component);
 
-            let data_key = Key::new(KeyType::Data, &current_path).unwrap_or_else(|_| {
-                unreachable!("Prefix of key failed to make key: {}", current_path)
+        for ancestor in data_key.ancestors() {
+            let data_key = Key::new(KeyType::Data, ancestor).unwrap_or_else(|_| {
+                unreachable!("Prefix of key failed to make key: {}", ancestor)
             });
-            
-            if let Some(md) = self.get_metadata_raw(metadata_key, &data_key)? {
+
+            let mut md = self.get_metadata_raw(metadata_key, &data_key, committed)?;
+            if md.is_none() && *committed != Committed::Live {
+                md = self.get_metadata_raw(metadata_key, &data_key, &Committed::Live)?;
+            }
+            if let Some(md) = md {
                 result = Ok(Some(md));
             }
         }
@@ -87,23 +209,42 @@ pub trait DataStore {
 
     /// Retrieve the value for a single metadata key from the datastore, without taking into
     /// account inheritance of metadata from earlier in the tree.
-    fn get_metadata_raw(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>>;
+    fn get_metadata_raw(
+        &self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<Option<String>>;
     /// Set the value of a single metadata key in the datastore.
     fn set_metadata<S: AsRef<str>>(
         &mut self,
         metadata_key: &Key,
         data_key: &Key,
         value: S,
+        committed: &Committed,
     ) -> Result<()>;
 
-    /// Applies pending changes to the live datastore.  Returns the list of changed keys.
-    fn commit(&mut self) -> Result<HashSet<Key>>;
+    /// Applies pending changes from the given transaction to the live datastore, including its
+    /// metadata.  Returns the list of changed data keys.
+    fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>;
+
+    /// Removes the given pending transaction, including its metadata, from the datastore.  Returns
+    /// the list of removed data keys.  If the transaction doesn't exist, returns Ok with an empty
+    /// list.
+    fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>;
+
+    /// Returns the names of any pending transactions in the datastore.
+    fn list_transactions(&self) -> Result<HashSet<String>>;
 
     /// Set multiple data keys at once in the data store.
     ///
     /// Implementers can replace the default implementation if there's a faster way than setting
     /// each key individually.
-    fn set_keys<S1, S2>(&mut self, pairs: &HashMap<S1, S2>, committed: Committed) -> Result<()>
+    fn set_keys<S1, S2>(&mut self, pairs: &HashMap<S1, S2>, committed: &Committed) -> Result<()>
     where
         S1: AsRef<str>,
         S2: AsRef<str>,
@@ -116,13 +257,14 @@ pub trait DataStore {
         Ok(())
     }
 
+    /// Retrieves all keys starting with the given prefix, along with their values.
     fn get_prefix<S: AsRef<str>>(
         &self,
         find_prefix: S,
-        committed: Committed,
-    ) -> Result<HashMap<key, String>> {
+        committed: &Committed,
+    ) -> Result<HashMap<Key, String>> {
         let keys = self.list_populated_keys(&find_prefix, committed)?;
-        trace!("Found populated keys: {:?}", keys)
+        trace!("Found populated keys: {:?}", keys);
         if keys.is_empty() {
             return Ok(HashMap::new());
         }
@@ -143,17 +285,17 @@ pub trait DataStore {
     /// metadata_key_name, only metadata keys with that name will be returned.  Returns a
     /// mapping of each data key to its metadata, where metadata is a mapping of metadata Key to
     /// string value.
-    
     fn get_metadata_prefix<S1, S2>(
         &self,
         find_prefix: S1,
         metadata_key_name: &Option<S2>,
+        committed: &Committed,
     ) -> Result<HashMap<Key, HashMap<Key, String>>>
     where
         S1: AsRef<str>,
         S2: AsRef<str>,
     {
-        let meta_map = self.list_populated_metadata(&find_prefix, metadata_key_name)?;
+        let meta_map = self.list_populated_metadata(&find_prefix, metadata_key_name, committed)?;
         trace!("Found populated metadata: {:?}", meta_map);
         if meta_map.is_empty() {
             return Ok(HashMap::new());
@@ -163,33 +305,32 @@ pub trait DataStore {
         for (data_key, meta_keys) in meta_map {
             for meta_key in meta_keys {
                 if let Some(name) = metadata_key_name {
-                    if name.as_ref() != meta_key.name() {
+                    if name.as_ref() != meta_key.as_ref() {
                         continue;
                     }
                 }
-                
+
                 trace!(
                     "Pulling metadata '{}' from datastore for key: {}",
                     meta_key,
                     &data_key
                 );
 
-                let value = self.get_metadata(&meta_key, &data_key)?.context(
-                    error::ListMetaNotPresent {
+                let value = self.get_metadata(&meta_key, &data_key, committed)?.context(
+                    error::ListedMetaNotPresent {
                         meta_key: meta_key.as_ref(),
                         data_key: data_key.as_ref(),
                     },
                 )?;
-                
-                // insert a top level map entry fro the data key if found metadata for it
+
+                // Insert a top-level map entry for the data key if we found metadata for it
                 let data_entry = result.entry(data_key.clone()).or_insert_with(HashMap::new);
-                
+
                 data_entry.insert(meta_key, value);
             }
         }
         Ok(result)
     }
-    
 }
 
 // This section ties together serialization and deserialization of scalar values, so it's in the
@@ -231,13 +372,58 @@ pub type Value = serde_json::Value;
 #[cfg(test)]
 mod test {
     use super::memory::MemoryDataStore;
-    use super::{Committed, DataStore, Key, KeyType};
-    use maplit::hashmap;
+    use super::sqlite::DATABASE_FILE;
+    use super::{Committed, DataStore, FilesystemDataStore, Key, KeyType, SqliteDataStore};
+    use maplit::{hashmap, hashset};
+    use tempfile::TempDir;
+
+    /// Generates a test module for each backend, running each of the given test functions
+    /// against a new, empty data store.
+    macro_rules! backend_tests {
+        ($($test:ident),* $(,)?) => {
+            mod memory {
+                $(
+                    #[test]
+                    fn $test() {
+                        super::$test(&mut super::MemoryDataStore::new());
+                    }
+                )*
+            }
 
-    #[test]
-    fn set_keys() {
-        let mut m = MemoryDataStore::new();
+            mod filesystem {
+                $(
+                    #[test]
+                    fn $test() {
+                        let dir = super::TempDir::new().unwrap();
+                        super::$test(&mut super::FilesystemDataStore::new(dir.path()));
+                    }
+                )*
+            }
 
+            mod sqlite {
+                $(
+                    #[test]
+                    fn $test() {
+                        let dir = super::TempDir::new().unwrap();
+                        let path = dir.path().join(super::DATABASE_FILE);
+                        super::$test(&mut super::SqliteDataStore::open(path).unwrap());
+                    }
+                )*
+            }
+        };
+    }
+
+    backend_tests!(
+        set_keys,
+        get_metadata_inheritance,
+        get_prefix,
+        get_metadata_prefix,
+        separate_transactions,
+        pending_metadata,
+        commit_without_settings,
+    );
+
+    fn set_keys<D: DataStore>(m: &mut D) {
         let k1 = Key::new(KeyType::Data, "memtest1").unwrap();
         let k2 = Key::new(KeyType::Data, "memtest2").unwrap();
         let v1 = "memvalue1".to_string();
@@ -247,52 +433,53 @@ mod test {
             &k2 => &v2,
         );
 
-        m.set_keys(&data, Committed::Pending).unwrap();
+        let pending = Committed::Pending {
+            tx: "testtx".into(),
+        };
+        m.set_keys(&data, &pending).unwrap();
 
-        assert_eq!(m.get_key(&k1, Committed::Pending).unwrap(), Some(v1));
-        assert_eq!(m.get_key(&k2, Committed::Pending).unwrap(), Some(v2));
+        assert_eq!(m.get_key(&k1, &pending).unwrap(), Some(v1));
+        assert_eq!(m.get_key(&k2, &pending).unwrap(), Some(v2));
     }
 
-    #[test]
-    fn get_metadata_inheritance() {
-        let mut m = MemoryDataStore::new();
-
+    fn get_metadata_inheritance<D: DataStore>(m: &mut D) {
         let meta = Key::new(KeyType::Meta, "mymeta").unwrap();
         let parent = Key::new(KeyType::Data, "a").unwrap();
         let grandchild = Key::new(KeyType::Data, "a.b.c").unwrap();
 
         // Set metadata on parent
-        m.set_metadata(&meta, &parent, "value").unwrap();
+        m.set_metadata(&meta, &parent, "value", &Committed::Live).unwrap();
         // Metadata shows up on grandchild...
         assert_eq!(
-            m.get_metadata(&meta, &grandchild).unwrap(),
+            m.get_metadata(&meta, &grandchild, &Committed::Live).unwrap(),
             Some("value".to_string())
         );
         // ...but only through inheritance, not directly.
-        assert_eq!(m.get_metadata_raw(&meta, &grandchild).unwrap(), None);
+        assert_eq!(
+            m.get_metadata_raw(&meta, &grandchild, &Committed::Live).unwrap(),
+            None
+        );
     }
 
-    #[test]
-    fn get_prefix() {
-        let mut m = MemoryDataStore::new();
+    fn get_prefix<D: DataStore>(m: &mut D) {
         let data = hashmap!(
             Key::new(KeyType::Data, "x.1").unwrap() => "x1".to_string(),
             Key::new(KeyType::Data, "x.2").unwrap() => "x2".to_string(),
             Key::new(KeyType::Data, "y.3").unwrap() => "y3".to_string(),
         );
-        m.set_keys(&data, Committed::Pending).unwrap();
+        let tx = Committed::Pending {
+            tx: "testtx".into(),
+        };
+        m.set_keys(&data, &tx).unwrap();
 
         assert_eq!(
-            m.get_prefix("x.", Committed::Pending).unwrap(),
+            m.get_prefix("x.", &tx).unwrap(),
             hashmap!(Key::new(KeyType::Data, "x.1").unwrap() => "x1".to_string(),
                      Key::new(KeyType::Data, "x.2").unwrap() => "x2".to_string())
         );
     }
 
-    #[test]
-    fn get_metadata_prefix() {
-        let mut m = MemoryDataStore::new();
-
+    fn get_metadata_prefix<D: DataStore>(m: &mut D) {
         // Build some data keys to which we can attach metadata; they don't actually have to be
         // set in the data store.
         let k1 = Key::new(KeyType::Data, "x.1").unwrap();
@@ -303,21 +490,117 @@ mod test {
         let mk1 = Key::new(KeyType::Meta, "metatest1").unwrap();
         let mk2 = Key::new(KeyType::Meta, "metatest2").unwrap();
         let mk3 = Key::new(KeyType::Meta, "metatest3").unwrap();
-        m.set_metadata(&mk1, &k1, "41").unwrap();
-        m.set_metadata(&mk2, &k2, "42").unwrap();
-        m.set_metadata(&mk3, &k3, "43").unwrap();
+        let live = Committed::Live;
+        m.set_metadata(&mk1, &k1, "41", &live).unwrap();
+        m.set_metadata(&mk2, &k2, "42", &live).unwrap();
+        m.set_metadata(&mk3, &k3, "43", &live).unwrap();
 
         // Check all metadata
         assert_eq!(
-            m.get_metadata_prefix("x.", &None as &Option<&str>).unwrap(),
+            m.get_metadata_prefix("x.", &None as &Option<&str>, &live).unwrap(),
             hashmap!(k1 => hashmap!(mk1 => "41".to_string()),
                      k2.clone() => hashmap!(mk2.clone() => "42".to_string()))
         );
 
         // Check metadata matching a given name
         assert_eq!(
-            m.get_metadata_prefix("x.", &Some("metatest2")).unwrap(),
+            m.get_metadata_prefix("x.", &Some("metatest2"), &live).unwrap(),
             hashmap!(k2 => hashmap!(mk2 => "42".to_string()))
         );
     }
-}
\ No newline at end of file
+
+    fn separate_transactions<D: DataStore>(m: &mut D) {
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        let tx1 = Committed::Pending { tx: "tx1".into() };
+        let tx2 = Committed::Pending { tx: "tx2".into() };
+
+        m.set_key(&k1, "1", &tx1).unwrap();
+        m.set_key(&k2, "2", &tx2).unwrap();
+
+        // Each transaction only sees its own keys
+        assert_eq!(m.get_key(&k2, &tx1).unwrap(), None);
+        assert_eq!(m.get_key(&k1, &tx2).unwrap(), None);
+        assert_eq!(
+            m.list_transactions().unwrap(),
+            hashset!("tx1".to_string(), "tx2".to_string())
+        );
+
+        // Committing one transaction leaves the other pending
+        assert_eq!(m.commit_transaction("tx1").unwrap(), hashset!(k1.clone()));
+        assert_eq!(m.get_key(&k1, &Committed::Live).unwrap(), Some("1".into()));
+        assert_eq!(m.get_key(&k2, &Committed::Live).unwrap(), None);
+        assert_eq!(m.get_key(&k2, &tx2).unwrap(), Some("2".into()));
+
+        // Deleting a transaction drops its keys without committing them
+        assert_eq!(m.delete_transaction("tx2").unwrap(), hashset!(k2.clone()));
+        assert_eq!(m.get_key(&k2, &Committed::Live).unwrap(), None);
+        assert!(m.list_transactions().unwrap().is_empty());
+    }
+
+    fn pending_metadata<D: DataStore>(m: &mut D) {
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        let mk = Key::new(KeyType::Meta, "affected-services").unwrap();
+        let tx1 = Committed::Pending { tx: "tx1".into() };
+        let tx2 = Committed::Pending { tx: "tx2".into() };
+        m.set_metadata(&mk, &k1, "[\"old\"]", &Committed::Live).unwrap();
+        m.set_metadata(&mk, &k1, "[\"new\"]", &tx1).unwrap();
+        m.set_metadata(&mk, &k2, "[\"b\"]", &tx2).unwrap();
+
+        // Pending metadata is separate from live, and from other transactions
+        assert_eq!(
+            m.get_metadata(&mk, &k1, &Committed::Live).unwrap(),
+            Some("[\"old\"]".into())
+        );
+        assert_eq!(
+            m.get_metadata(&mk, &k1, &tx1).unwrap(),
+            Some("[\"new\"]".into())
+        );
+        // Keys without staged metadata fall back to live metadata, including inherited metadata
+        assert_eq!(
+            m.get_metadata(&mk, &k1, &tx2).unwrap(),
+            Some("[\"old\"]".into())
+        );
+        let parent = Key::new(KeyType::Data, "other").unwrap();
+        let k3 = Key::new(KeyType::Data, "other.c").unwrap();
+        m.set_metadata(&mk, &parent, "[\"parent\"]", &Committed::Live).unwrap();
+        assert_eq!(
+            m.get_metadata(&mk, &k3, &tx1).unwrap(),
+            Some("[\"parent\"]".into())
+        );
+        assert_eq!(
+            m.list_populated_metadata("settings", &None as &Option<&str>, &tx1).unwrap(),
+            hashmap!(k1.clone() => hashset!(mk.clone()))
+        );
+        assert_eq!(
+            m.list_transactions().unwrap(),
+            hashset!("tx1".to_string(), "tx2".to_string())
+        );
+
+        // Committing applies metadata, even without data
+        assert!(m.commit_transaction("tx1").unwrap().is_empty());
+        assert_eq!(
+            m.get_metadata(&mk, &k1, &Committed::Live).unwrap(),
+            Some("[\"new\"]".into())
+        );
+        assert_eq!(m.get_metadata_raw(&mk, &k1, &tx1).unwrap(), None);
+
+        // Deleting drops metadata without applying it
+        m.delete_transaction("tx2").unwrap();
+        assert_eq!(m.get_metadata(&mk, &k2, &Committed::Live).unwrap(), None);
+        assert!(m.list_transactions().unwrap().is_empty());
+    }
+
+    fn commit_without_settings<D: DataStore>(m: &mut D) {
+        let k1 = Key::new(KeyType::Data, "services.a").unwrap();
+        let tx = Committed::Pending { tx: "tx".into() };
+        m.set_key(&k1, "1", &tx).unwrap();
+
+        // Only settings are committed, but the transaction is used up either way
+        assert!(m.commit_transaction("tx").unwrap().is_empty());
+        assert_eq!(m.get_key(&k1, &Committed::Live).unwrap(), None);
+        assert_eq!(m.get_key(&k1, &tx).unwrap(), None);
+        assert!(m.list_transactions().unwrap().is_empty());
+    }
+}
@@ -5,9 +5,9 @@ pub use error::{Error, Result};
 pub use pairs::{to_pairs, to_pairs_with_prefix};
 
 use serde::{ser, Serialize};
-use snafu::{IntoError, NoneError as NoSource};
+use snafu::OptionExt;
 
-use crate::datastore::{Key, KeyType};
+use crate::datastore::key::quote_segment;
 
 // Below are serializers not specific to the pairs module that could be used for other serializers.
 // For example, a 'keys' serializer that just returns a set of keys, without associated data.
@@ -46,17 +46,13 @@ impl ser::Serializer for &MapKeySerializer {
     // Allow serialization of strings for map keys, but nothing else.
 
     fn serialize_str(self, value: &str) -> Result<String> {
-        // Make sure string is valid as a key.
-        // Note: we check as a metadata key here, because metadata keys are simpler - no dotted
-        // components, just one.  We wouldn't want dotted components in a single map key because
-        // it would falsely imply nesting.
-        let key = Key::new(KeyType::Meta, value)
-            .map_err(|e| {
-                debug!("MapKeySerializer got invalid key name: {}", value);
-                error::InvalidKey { msg: format!("{}", e) }.into_error(NoSource)
-            })?;
-        trace!("MapKeySerializer got OK key: {}", key);
-        Ok(key.to_string())
+        // Make sure string is valid as a key segment.  Map keys with dots would falsely imply
+        // nesting, so we quote them, along with anything else that isn't a bare segment.
+        let segment = quote_segment(value).context(error::InvalidKey {
+            msg: format!("map key '{}' can't be used as a key segment", value),
+        })?;
+        trace!("MapKeySerializer got OK key segment: {}", segment);
+        Ok(segment)
     }
 
     fn serialize_bool(self, _value: bool) -> Result<String> { bad_key("bool") }
@@ -127,6 +123,15 @@ mod test {
         assert_eq!(res, "A");
     }
 
+    #[test]
+    fn quoted_key() {
+        let serializer = MapKeySerializer::new();
+        let m = "app.io/role".to_string();
+        let res = m.serialize(&serializer).unwrap();
+        assert_eq!(res, r#""app.io/role""#);
+        r#"a"b"#.serialize(&serializer).unwrap_err();
+    }
+
     #[test]
     fn bad_keys() {
         let serializer = MapKeySerializer::new();
@@ -432,6 +432,20 @@ mod test {
         );
     }
 
+    #[test]
+    fn map_quoted_keys() {
+        let m = hashmap!(
+            "app.io/role".to_string() => "worker".to_string(),
+        );
+        let keys = to_pairs_with_prefix("labels".to_string(), &m).unwrap();
+        assert_eq!(
+            keys,
+            hashmap!(
+                r#"labels."app.io/role""#.to_string() => "\"worker\"".to_string(),
+            )
+        );
+    }
+
     #[test]
     fn concrete_fails() {
         let i = 42;
@@ -0,0 +1,349 @@
+//! A Snapshot holds the whole state of a data store -- live data, pending transactions, and
+//! their metadata -- in one document, so it can be copied from one host to another, or attached to a
+//! bug report.
+//!
+//! Data is nested the same way the API shows it, e.g. `{"settings": {"hostname": "x"}}`, and is
+//! turned back into data store keys on import.  Keys can change between data store versions, so
+//! a snapshot records the version it was exported from, and callers should only import snapshots
+//! from the version they're running.
+
+use data_store_version::Version;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::deserialization::from_map_with_prefix;
+use super::key::split_first_segment;
+use super::serialization::to_pairs;
+use super::{
+    deserialize_scalar, error, serialize_scalar, Committed, DataStore, Key, KeyType, Result,
+    ScalarError, Value, COMMIT_PREFIX,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Snapshot {
+    /// The version of the data store the snapshot was exported from.
+    #[serde(with = "version_string")]
+    pub version: Version,
+    /// Live data, nested by key segment.
+    pub live: Value,
+    /// Pending data, nested by key segment, for each transaction.
+    #[serde(default)]
+    pub pending: BTreeMap<String, Value>,
+    /// Live metadata values, by data key and then metadata key.
+    #[serde(default)]
+    pub metadata: MetadataValues,
+    /// Pending metadata values for each transaction.
+    #[serde(default)]
+    pub pending_metadata: BTreeMap<String, MetadataValues>,
+}
+
+/// Metadata values, by data key and then metadata key.
+pub type MetadataValues = BTreeMap<String, BTreeMap<String, Value>>;
+
+/// Serializes Version as its string form, e.g. "v1.5".
+mod version_string {
+    use data_store_version::Version;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S>(version: &Version, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(version)
+    }
+
+    pub(super) fn deserialize<'de, D>(deserializer: D) -> Result<Version, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let version_str = String::deserialize(deserializer)?;
+        version_str.parse().map_err(D::Error::custom)
+    }
+}
+
+/// Builds a snapshot of everything in the given data store, which has the given version.
+pub fn export<D: DataStore>(datastore: &D, version: Version) -> Result<Snapshot> {
+    let live = nest(datastore.get_prefix("", &Committed::Live)?)?;
+
+    let metadata = export_metadata(datastore, &Committed::Live)?;
+
+    let mut pending = BTreeMap::new();
+    let mut pending_metadata = BTreeMap::new();
+    for tx in datastore.list_transactions()? {
+        let committed = Committed::Pending { tx: tx.clone() };
+        let data = datastore.get_prefix("", &committed)?;
+        pending.insert(tx.clone(), nest(data)?);
+
+        let tx_metadata = export_metadata(datastore, &committed)?;
+        if !tx_metadata.is_empty() {
+            pending_metadata.insert(tx, tx_metadata);
+        }
+    }
+
+    Ok(Snapshot {
+        version,
+        live,
+        pending,
+        metadata,
+        pending_metadata,
+    })
+}
+
+/// Collects the metadata values for the given committed state.
+fn export_metadata<D: DataStore>(datastore: &D, committed: &Committed) -> Result<MetadataValues> {
+    let mut metadata = BTreeMap::new();
+    let populated = datastore.list_populated_metadata("", &None as &Option<&str>, committed)?;
+    for (data_key, metadata_keys) in populated {
+        let data_entry: &mut BTreeMap<String, Value> =
+            metadata.entry(data_key.to_string()).or_default();
+        for metadata_key in metadata_keys {
+            let raw = datastore
+                .get_metadata_raw(&metadata_key, &data_key, committed)?
+                .context(error::ListedMetaNotPresent {
+                    meta_key: metadata_key.as_ref(),
+                    data_key: data_key.as_ref(),
+                })?;
+            let value = deserialize_scalar::<_, ScalarError>(&raw)
+                .context(error::DeserializeScalar { given: &raw })?;
+            data_entry.insert(metadata_key.to_string(), value);
+        }
+    }
+    Ok(metadata)
+}
+
+/// Writes the data in a snapshot to the given data store.  Live settings and live metadata are
+/// staged in the given transaction, replacing anything already staged there, so the caller can
+/// commit them like any other change.  Other live data, like services, isn't committed by
+/// transactions, so it's set directly.  Each pending transaction in the snapshot replaces any
+/// transaction with the same name.  Nothing is written unless the whole snapshot is valid.
+///
+/// Returns the live settings keys that were staged.
+pub fn import<D: DataStore>(
+    datastore: &mut D,
+    snapshot: &Snapshot,
+    transaction: &str,
+) -> Result<HashSet<Key>> {
+    // Check everything before we write anything.
+    ensure!(
+        !snapshot.pending.contains_key(transaction)
+            && !snapshot.pending_metadata.contains_key(transaction),
+        error::SnapshotTransaction { tx: transaction }
+    );
+    let import = Committed::Pending {
+        tx: transaction.to_string(),
+    };
+
+    let live = to_pairs(&snapshot.live).context(error::Serialization {
+        given: "snapshot live data",
+    })?;
+    let (settings, other): (HashMap<_, _>, HashMap<_, _>) = live
+        .into_iter()
+        .partition(|(key, _)| key.starts_with(COMMIT_PREFIX));
+    let settings_keys = settings
+        .keys()
+        .map(|key| Key::new(KeyType::Data, key))
+        .collect::<Result<HashSet<_>>>()?;
+    for key in other.keys() {
+        Key::new(KeyType::Data, key)?;
+    }
+
+    let mut pending = Vec::new();
+    for (tx, data) in &snapshot.pending {
+        let pairs = to_pairs(data).context(error::Serialization {
+            given: format!("snapshot transaction '{}'", tx),
+        })?;
+        for key in pairs.keys() {
+            Key::new(KeyType::Data, key)?;
+        }
+        pending.push((tx, pairs));
+    }
+
+    let mut metadata = serialize_metadata(&snapshot.metadata, &import)?;
+    for (tx, tx_metadata) in &snapshot.pending_metadata {
+        let committed = Committed::Pending { tx: tx.clone() };
+        metadata.extend(serialize_metadata(tx_metadata, &committed)?);
+    }
+
+    datastore.set_keys(&other, &Committed::Live)?;
+    let transactions: HashSet<&str> = pending
+        .iter()
+        .map(|(tx, _)| tx.as_str())
+        .chain(snapshot.pending_metadata.keys().map(|tx| tx.as_str()))
+        .chain(Some(transaction))
+        .collect();
+    for tx in transactions {
+        datastore.delete_transaction(tx)?;
+    }
+    datastore.set_keys(&settings, &import)?;
+    for (tx, pairs) in pending {
+        datastore.set_keys(&pairs, &Committed::Pending { tx: tx.clone() })?;
+    }
+    for (metadata_key, data_key, value, committed) in metadata {
+        datastore.set_metadata(&metadata_key, &data_key, value, &committed)?;
+    }
+
+    Ok(settings_keys)
+}
+
+/// Checks and serializes metadata values from a snapshot, returning the keys and values to write
+/// for the given committed state.
+fn serialize_metadata(
+    metadata: &MetadataValues,
+    committed: &Committed,
+) -> Result<Vec<(Key, Key, String, Committed)>> {
+    let mut result = Vec::new();
+    for (data_key, metadata_values) in metadata {
+        let data_key = Key::new(KeyType::Data, data_key)?;
+        for (metadata_key, value) in metadata_values {
+            let metadata_key = Key::new(KeyType::Meta, metadata_key)?;
+            let value =
+                serialize_scalar::<_, ScalarError>(value).context(error::SerializeScalar {
+                    given: format!("metadata '{}' for '{}'", metadata_key, data_key),
+                })?;
+            result.push((metadata_key, data_key.clone(), value, committed.clone()));
+        }
+    }
+    Ok(result)
+}
+
+/// Turns data store keys and their serialized values into a nested structure, e.g. the key
+/// "settings.a.b" with value "1" becomes {"settings": {"a": {"b": 1}}}.
+fn nest(data: HashMap<Key, String>) -> Result<Value> {
+    // Deserializing a map needs a named root, so we handle each top-level segment separately.
+    let mut groups: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for (key, value) in data {
+        let root = split_first_segment(&key).0.to_string();
+        groups
+            .entry(root)
+            .or_default()
+            .insert(key.to_string(), value);
+    }
+
+    let mut nested = serde_json::Map::new();
+    for (root, group) in groups {
+        let value = match group.get(&root) {
+            // A key without a separator is a scalar at the top level.
+            Some(scalar) => deserialize_scalar::<_, ScalarError>(scalar)
+                .context(error::DeserializeScalar { given: scalar })?,
+            // The compound deserializer only hands out maps, not arbitrary values.
+            None => Value::Object(
+                from_map_with_prefix(Some(root.clone()), &group)
+                    .context(error::Deserialization { given: &root })?,
+            ),
+        };
+        nested.insert(root, value);
+    }
+    Ok(Value::Object(nested))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::datastore::memory::MemoryDataStore;
+    use maplit::{btreemap, hashset};
+    use serde_json::json;
+
+    #[test]
+    fn round_trip() {
+        let mut from = MemoryDataStore::from_fixture_str(
+            r#"
+            [live.settings]
+            hostname = "example"
+            ntp.time-servers = ["a", "b"]
+
+            [live.services.ntp]
+            restart-commands = ["/bin/systemctl restart chronyd"]
+
+            [pending.tx1.settings]
+            timezone = "tz"
+
+            [[metadata]]
+            key = "settings.hostname"
+            md = "affected-services"
+            val = ["hostname"]
+            "#,
+        )
+        .unwrap();
+        let tx1 = Committed::Pending { tx: "tx1".into() };
+        let timezone = Key::new(KeyType::Data, "settings.timezone").unwrap();
+        let affected = Key::new(KeyType::Meta, "affected-services").unwrap();
+        from.set_metadata(&affected, &timezone, "[\"timezone\"]", &tx1)
+            .unwrap();
+
+        let snapshot = export(&from, Version::new(1, 0)).unwrap();
+        assert_eq!(
+            snapshot.live["settings"],
+            json!({"hostname": "example", "ntp": {"time-servers": ["a", "b"]}})
+        );
+        assert_eq!(
+            snapshot.pending["tx1"],
+            json!({"settings": {"timezone": "tz"}})
+        );
+        assert_eq!(
+            snapshot.metadata["settings.hostname"]["affected-services"],
+            json!(["hostname"])
+        );
+        assert_eq!(
+            snapshot.pending_metadata["tx1"]["settings.timezone"]["affected-services"],
+            json!(["timezone"])
+        );
+        assert!(!snapshot.metadata.contains_key("settings.timezone"));
+
+        // The document itself round-trips, including the version
+        let document = serde_json::to_string(&snapshot).unwrap();
+        let parsed: Snapshot = serde_json::from_str(&document).unwrap();
+        assert_eq!(parsed, snapshot);
+
+        // Live settings are staged for the caller to commit; other live data is set directly
+        let mut to = MemoryDataStore::new();
+        let staged = import(&mut to, &parsed, "import").unwrap();
+        assert_eq!(staged.len(), 2);
+        let services = to
+            .list_populated_keys("services", &Committed::Live)
+            .unwrap();
+        assert_eq!(services.len(), 1);
+        assert!(to
+            .list_populated_keys("settings", &Committed::Live)
+            .unwrap()
+            .is_empty());
+        to.commit_transaction("import").unwrap();
+        assert_eq!(export(&to, Version::new(1, 0)).unwrap(), snapshot);
+
+        // Importing replaces a pending transaction of the same name
+        let stale = Key::new(KeyType::Data, "settings.stale").unwrap();
+        from.set_key(&stale, "1", &tx1).unwrap();
+        import(&mut from, &snapshot, "import").unwrap();
+        assert_eq!(
+            from.list_populated_keys("", &tx1).unwrap(),
+            hashset!(timezone)
+        );
+
+        // The snapshot can't have its own transaction with the name used for live settings
+        import(&mut from, &snapshot, "tx1").unwrap_err();
+    }
+
+    #[test]
+    fn invalid_import_writes_nothing() {
+        let snapshot = Snapshot {
+            version: Version::new(1, 0),
+            live: json!({"settings": {"hostname": "x"}}),
+            pending: BTreeMap::new(),
+            metadata: btreemap!(
+                "settings.hostname".to_string() => btreemap!(
+                    "not valid!".to_string() => json!(1),
+                ),
+            ),
+            pending_metadata: BTreeMap::new(),
+        };
+        let mut datastore = MemoryDataStore::new();
+        import(&mut datastore, &snapshot, "import").unwrap_err();
+        assert!(datastore
+            .list_populated_keys("", &Committed::Live)
+            .unwrap()
+            .is_empty());
+        assert!(datastore.list_transactions().unwrap().is_empty());
+    }
+}
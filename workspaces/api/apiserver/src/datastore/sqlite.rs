@@ -0,0 +1,490 @@
+//! SqliteDataStore keeps the whole data store in a single SQLite file.
+//!
+//! Live and pending data and metadata each get a table of key/value rows.  Writes that
+//! touch more than one key run in one SQLite transaction, so a commit is atomic without the
+//! staging and recovery FilesystemDataStore needs, and listing keys doesn't walk a directory tree.
+
+use rusqlite::types::ToSql;
+use rusqlite::{params, Connection, OptionalExtension};
+use snafu::{OptionExt, ResultExt};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use super::key::{Key, KeyType};
+use super::{
+    error, merge_list_elements, serialize_scalar, valid_transaction, Committed, DataStore, Result,
+    ScalarError, COMMIT_PREFIX,
+};
+
+/// The name of the database file inside a data store directory.
+pub const DATABASE_FILE: &str = "datastore.db";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS live (
+        key TEXT NOT NULL PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS pending (
+        tx TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (tx, key)
+    );
+    CREATE TABLE IF NOT EXISTS metadata (
+        data_key TEXT NOT NULL,
+        metadata_key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (data_key, metadata_key)
+    );
+    CREATE TABLE IF NOT EXISTS pending_metadata (
+        tx TEXT NOT NULL,
+        data_key TEXT NOT NULL,
+        metadata_key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (tx, data_key, metadata_key)
+    );
+";
+
+// SQLite connections can't be shared between threads, so we hold ours in a Mutex; callers
+// generally share a data store behind a RwLock, which requires it to be Sync.
+#[derive(Debug)]
+pub struct SqliteDataStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteDataStore {
+    /// Opens the data store in the given file, creating it if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteDataStore> {
+        let conn = Connection::open(path.as_ref()).context(error::Database { op: "open" })?;
+        conn.execute_batch(SCHEMA).context(error::Database {
+            op: "create tables",
+        })?;
+        Ok(SqliteDataStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.conn.lock().ok().context(error::DatabaseLock)
+    }
+}
+
+/// Runs a query that returns a single string column.
+fn query_strings(conn: &Connection, sql: &str, params: &[&dyn ToSql]) -> Result<Vec<String>> {
+    let mut statement = conn
+        .prepare(sql)
+        .context(error::Database { op: "prepare" })?;
+    let rows = statement
+        .query_map(params, |row| row.get(0))
+        .context(error::Database { op: "query" })?;
+    rows.collect::<rusqlite::Result<_>>()
+        .context(error::Database { op: "read rows" })
+}
+
+/// Turns stored key names back into Keys of the given type.
+fn to_keys(names: Vec<String>, key_type: KeyType) -> Result<HashSet<Key>> {
+    names
+        .into_iter()
+        .map(|name| Key::new(key_type, name))
+        .collect()
+}
+
+/// Gets a data key using the given connection, which may be in a transaction.
+fn get_key_with(conn: &Connection, key: &Key, committed: &Committed) -> Result<Option<String>> {
+    match committed {
+        Committed::Live => conn.query_row(
+            "SELECT value FROM live WHERE key = ?1",
+            params![key.as_ref()],
+            |row| row.get(0),
+        ),
+        Committed::Pending { tx } => conn.query_row(
+            "SELECT value FROM pending WHERE tx = ?1 AND key = ?2",
+            params![valid_transaction(tx)?, key.as_ref()],
+            |row| row.get(0),
+        ),
+    }
+    .optional()
+    .context(error::Database { op: "get_key" })
+}
+
+/// Sets a data key using the given connection, which may be in a transaction.
+fn set_key_with(conn: &Connection, key: &Key, value: &str, committed: &Committed) -> Result<()> {
+    match committed {
+        Committed::Live => conn.execute(
+            "INSERT OR REPLACE INTO live (key, value) VALUES (?1, ?2)",
+            params![key.as_ref(), value],
+        ),
+        Committed::Pending { tx } => conn.execute(
+            "INSERT OR REPLACE INTO pending (tx, key, value) VALUES (?1, ?2, ?3)",
+            params![valid_transaction(tx)?, key.as_ref(), value],
+        ),
+    }
+    .context(error::Database { op: "set_key" })?;
+    Ok(())
+}
+
+/// Merges list elements staged in a transaction into their lists, like fold_list_elements, but
+/// using the given connection, so a commit can fold them in the same SQLite transaction.
+fn fold_list_elements_with(conn: &Connection, tx: &str) -> Result<()> {
+    let pending = Committed::Pending { tx: tx.into() };
+    let mut statement = conn
+        .prepare(
+            "SELECT key, value FROM pending
+             WHERE tx = ?1 AND substr(key, 1, length(?2)) = ?2 AND substr(key, -1) = ']'",
+        )
+        .context(error::Database { op: "prepare" })?;
+    let rows = statement
+        .query_map(params![tx, COMMIT_PREFIX], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .context(error::Database { op: "query" })?;
+    let rows = rows
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .context(error::Database { op: "read rows" })?;
+
+    // Group elements by list.
+    let mut lists: HashMap<Key, BTreeMap<usize, &str>> = HashMap::new();
+    for (name, value) in &rows {
+        if let Some((list, index)) = Key::new(KeyType::Data, name)?.list_element() {
+            lists.entry(list).or_default().insert(index, value);
+        }
+    }
+
+    for (list_key, elements) in lists {
+        let existing = match get_key_with(conn, &list_key, &pending)? {
+            Some(value) => Some(value),
+            None => get_key_with(conn, &list_key, &Committed::Live)?,
+        };
+        let list =
+            merge_list_elements(&list_key, existing.as_ref().map(String::as_str), &elements)?;
+        let value = serialize_scalar::<_, ScalarError>(&list).context(error::SerializeScalar {
+            given: list_key.as_ref(),
+        })?;
+        set_key_with(conn, &list_key, &value, &pending)?;
+    }
+    Ok(())
+}
+
+/// Removes a transaction's data and metadata using the given connection, which may be in a
+/// transaction.
+fn delete_transaction_with(conn: &Connection, tx: &str, op: &str) -> Result<()> {
+    for sql in &[
+        "DELETE FROM pending WHERE tx = ?1",
+        "DELETE FROM pending_metadata WHERE tx = ?1",
+    ] {
+        conn.execute(sql, params![tx])
+            .context(error::Database { op })?;
+    }
+    Ok(())
+}
+
+impl DataStore for SqliteDataStore {
+    fn key_populated(&self, key: &Key, committed: &Committed) -> Result<bool> {
+        Ok(self.get_key(key, committed)?.is_some())
+    }
+
+    fn list_populated_keys<S: AsRef<str>>(
+        &self,
+        prefix: S,
+        committed: &Committed,
+    ) -> Result<HashSet<Key>> {
+        let conn = self.conn()?;
+        let prefix = prefix.as_ref();
+        let names = match committed {
+            Committed::Live => query_strings(
+                &conn,
+                "SELECT key FROM live WHERE substr(key, 1, length(?1)) = ?1",
+                params![prefix],
+            )?,
+            Committed::Pending { tx } => query_strings(
+                &conn,
+                "SELECT key FROM pending WHERE tx = ?2 AND substr(key, 1, length(?1)) = ?1",
+                params![prefix, valid_transaction(tx)?],
+            )?,
+        };
+        to_keys(names, KeyType::Data)
+    }
+
+    fn list_populated_metadata<S1, S2>(
+        &self,
+        prefix: S1,
+        metadata_key_name: &Option<S2>,
+        committed: &Committed,
+    ) -> Result<HashMap<Key, HashSet<Key>>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let conn = self.conn()?;
+        let prefix = prefix.as_ref();
+        if let Committed::Pending { tx } = committed {
+            valid_transaction(tx)?;
+        }
+        let (sql, params): (&str, Vec<&dyn ToSql>) = match committed {
+            Committed::Live => (
+                "SELECT data_key, metadata_key FROM metadata
+                 WHERE substr(data_key, 1, length(?1)) = ?1",
+                vec![&prefix],
+            ),
+            Committed::Pending { tx } => (
+                "SELECT data_key, metadata_key FROM pending_metadata
+                 WHERE substr(data_key, 1, length(?1)) = ?1 AND tx = ?2",
+                vec![&prefix, tx],
+            ),
+        };
+        let mut statement = conn
+            .prepare(sql)
+            .context(error::Database { op: "prepare" })?;
+        let rows = statement
+            .query_map(&params, |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .context(error::Database { op: "query" })?;
+
+        let mut result = HashMap::new();
+        for row in rows {
+            let (data_key, meta_key) = row.context(error::Database { op: "read rows" })?;
+
+            // If the user requested specific metadata, move to the next key unless it matches.
+            if let Some(name) = metadata_key_name {
+                if name.as_ref() != meta_key {
+                    continue;
+                }
+            }
+
+            let data_key = Key::new(KeyType::Data, data_key)?;
+            let meta_key = Key::new(KeyType::Meta, meta_key)?;
+            result
+                .entry(data_key)
+                .or_insert_with(HashSet::new)
+                .insert(meta_key);
+        }
+        Ok(result)
+    }
+
+    fn get_key(&self, key: &Key, committed: &Committed) -> Result<Option<String>> {
+        let conn = self.conn()?;
+        get_key_with(&conn, key, committed)
+    }
+
+    fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()> {
+        let conn = self.conn()?;
+        set_key_with(&conn, key, value.as_ref(), committed)
+    }
+
+    /// Sets all of the keys in one SQLite transaction, which is much faster than one per key,
+    /// and means we don't leave some of them set if one fails.
+    fn set_keys<S1, S2>(&mut self, pairs: &HashMap<S1, S2>, committed: &Committed) -> Result<()>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let mut conn = self.conn()?;
+        let transaction = conn
+            .transaction()
+            .context(error::Database { op: "set_keys" })?;
+        for (key_str, value) in pairs {
+            trace!("Setting data key {}", key_str.as_ref());
+            let key = Key::new(KeyType::Data, key_str)?;
+            set_key_with(&transaction, &key, value.as_ref(), committed)?;
+        }
+        transaction
+            .commit()
+            .context(error::Database { op: "set_keys" })
+    }
+
+    fn get_metadata_raw(
+        &self,
+        metadata_key: &Key,
+        data_key: &Key,
+        committed: &Committed,
+    ) -> Result<Option<String>> {
+        let conn = self.conn()?;
+        match committed {
+            Committed::Live => conn.query_row(
+                "SELECT value FROM metadata WHERE data_key = ?1 AND metadata_key = ?2",
+                params![data_key.as_ref(), metadata_key.as_ref()],
+                |row| row.get(0),
+            ),
+            Committed::Pending { tx } => conn.query_row(
+                "SELECT value FROM pending_metadata
+                 WHERE tx = ?1 AND data_key = ?2 AND metadata_key = ?3",
+                params![
+                    valid_transaction(tx)?,
+                    data_key.as_ref(),
+                    metadata_key.as_ref()
+                ],
+                |row| row.get(0),
+            ),
+        }
+        .optional()
+        .context(error::Database {
+            op: "get_metadata_raw",
+        })
+    }
+
+    fn set_metadata<S: AsRef<str>>(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        value: S,
+        committed: &Committed,
+    ) -> Result<()> {
+        let conn = self.conn()?;
+        let (data_key, metadata_key, value) =
+            (data_key.as_ref(), metadata_key.as_ref(), value.as_ref());
+        match committed {
+            Committed::Live => conn.execute(
+                "INSERT OR REPLACE INTO metadata (data_key, metadata_key, value)
+                 VALUES (?1, ?2, ?3)",
+                params![data_key, metadata_key, value],
+            ),
+            Committed::Pending { tx } => conn.execute(
+                "INSERT OR REPLACE INTO pending_metadata (tx, data_key, metadata_key, value)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![valid_transaction(tx)?, data_key, metadata_key, value],
+            ),
+        }
+        .context(error::Database { op: "set_metadata" })?;
+        Ok(())
+    }
+
+    /// Copies the transaction's settings and metadata to live and removes the transaction, all
+    /// in one SQLite transaction.
+    fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        let tx = valid_transaction(transaction.as_ref())?;
+        let mut conn = self.conn()?;
+        let sql_tx = conn
+            .transaction()
+            .context(error::Database { op: "commit" })?;
+        fold_list_elements_with(&sql_tx, tx)?;
+
+        // Matches commits_key: list elements, whose names end with an index, were folded into
+        // their lists above.
+        let names = query_strings(
+            &sql_tx,
+            "SELECT key FROM pending
+             WHERE tx = ?1 AND substr(key, 1, length(?2)) = ?2 AND substr(key, -1) != ']'",
+            params![tx, COMMIT_PREFIX],
+        )?;
+        let pending_keys = to_keys(names, KeyType::Data)?;
+
+        sql_tx
+            .execute(
+                "INSERT OR REPLACE INTO live (key, value)
+                 SELECT key, value FROM pending
+                 WHERE tx = ?1 AND substr(key, 1, length(?2)) = ?2 AND substr(key, -1) != ']'",
+                params![tx, COMMIT_PREFIX],
+            )
+            .context(error::Database { op: "commit" })?;
+        sql_tx
+            .execute(
+                "INSERT OR REPLACE INTO metadata (data_key, metadata_key, value)
+                 SELECT data_key, metadata_key, value FROM pending_metadata WHERE tx = ?1",
+                params![tx],
+            )
+            .context(error::Database { op: "commit" })?;
+        delete_transaction_with(&sql_tx, tx, "commit")?;
+        sql_tx.commit().context(error::Database { op: "commit" })?;
+
+        Ok(pending_keys)
+    }
+
+    fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        let tx = valid_transaction(transaction.as_ref())?;
+        let mut conn = self.conn()?;
+        let sql_tx = conn.transaction().context(error::Database {
+            op: "delete_transaction",
+        })?;
+
+        let names = query_strings(
+            &sql_tx,
+            "SELECT key FROM pending WHERE tx = ?1",
+            params![tx],
+        )?;
+        let pending_keys = to_keys(names, KeyType::Data)?;
+
+        delete_transaction_with(&sql_tx, tx, "delete_transaction")?;
+        sql_tx.commit().context(error::Database {
+            op: "delete_transaction",
+        })?;
+
+        Ok(pending_keys)
+    }
+
+    fn list_transactions(&self) -> Result<HashSet<String>> {
+        let conn = self.conn()?;
+        let names = query_strings(
+            &conn,
+            "SELECT tx FROM pending UNION SELECT tx FROM pending_metadata",
+            params![],
+        )?;
+        Ok(names.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maplit::hashset;
+    use tempfile::TempDir;
+
+    #[test]
+    fn persists() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(DATABASE_FILE);
+        let key = Key::new(KeyType::Data, "settings.hostname").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+
+        let mut s = SqliteDataStore::open(&path).unwrap();
+        s.set_key(&key, "\"abc\"", &pending).unwrap();
+        drop(s);
+
+        let mut s = SqliteDataStore::open(&path).unwrap();
+        assert_eq!(s.list_transactions().unwrap(), hashset!("tx".to_string()));
+        assert_eq!(s.commit_transaction("tx").unwrap(), hashset!(key.clone()));
+        drop(s);
+
+        let s = SqliteDataStore::open(&path).unwrap();
+        assert_eq!(
+            s.get_key(&key, &Committed::Live).unwrap(),
+            Some("\"abc\"".to_string())
+        );
+        assert!(s.list_transactions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn commit_list_elements() {
+        let dir = TempDir::new().unwrap();
+        let mut s = SqliteDataStore::open(dir.path().join(DATABASE_FILE)).unwrap();
+        let list = Key::new(KeyType::Data, "settings.list").unwrap();
+        let element = Key::new(KeyType::Data, "settings.list[0]").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        s.set_key(&list, "[1,2]", &Committed::Live).unwrap();
+        s.set_key(&element, "3", &pending).unwrap();
+
+        assert_eq!(s.commit_transaction("tx").unwrap(), hashset!(list.clone()));
+        assert_eq!(
+            s.get_key(&list, &Committed::Live).unwrap(),
+            Some("[3,2]".to_string())
+        );
+        assert!(!s.key_populated(&element, &Committed::Live).unwrap());
+    }
+
+    #[test]
+    fn transaction_names() {
+        let dir = TempDir::new().unwrap();
+        let mut s = SqliteDataStore::open(dir.path().join(DATABASE_FILE)).unwrap();
+        let key = Key::new(KeyType::Data, "settings.hostname").unwrap();
+        let pending = Committed::Pending { tx: "../x".into() };
+        s.set_key(&key, "\"abc\"", &pending).unwrap_err();
+        s.commit_transaction("../x").unwrap_err();
+    }
+}
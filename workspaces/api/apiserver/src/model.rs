@@ -7,7 +7,15 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::Ipv4Addr;
 
-use crate::modeled_types::ValidBase64;
+use crate::modeled_types::{
+    AwsRegion, FriendlyVersion, HostContainerMount, HostContainerName, KmodKey,
+    KubernetesAuthenticationMode, KubernetesBootstrapToken, KubernetesEvictionHardKey,
+    KubernetesFeatureGate, KubernetesLabelKey, KubernetesLabelValue, KubernetesQuantityValue,
+    KubernetesReservedResourceKey, KubernetesTaintValue, KubernetesThresholdValue,
+    SingleLineString, SysctlKey, ValidBase64, ValidEnvVarName, ValidHostname, ValidHostnameOrIp,
+    ValidImageReference, ValidNoProxyEntry, ValidProxyUrl, ValidRegistryHost, ValidTimezone,
+    ValidUnitName, ValidUrl,
+};
 
 ///// Primary user-visible settings
 
@@ -20,10 +28,10 @@ use crate::modeled_types::ValidBase64;
 #[serde(deny_unknown_fields, rename = "settings", rename_all = "kebab-case")]
 pub struct Settings {
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub timezone: Option<String>,
+    pub timezone: Option<ValidTimezone>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub hostname: Option<String>,
+    pub hostname: Option<ValidHostname>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub kubernetes: Option<KubernetesSettings>,
@@ -32,12 +40,37 @@ pub struct Settings {
     pub updates: Option<UpdatesSettings>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub host_containers: Option<HostContainersSettings>,
+    pub host_containers: Option<HashMap<HostContainerName, HostContainer>>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ntp: Option<NtpSettings>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_registry: Option<ContainerRegistrySettings>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<KernelSettings>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<NetworkSettings>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<MetricsSettings>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aws: Option<AwsSettings>,
 }
 
+/// Settings whose values are secret.  The API shows a placeholder instead of the values of these
+/// settings, and anything under them, to clients other than root.  A "*" segment matches any one
+/// segment, e.g. any host container name.
+pub const SECRET_SETTINGS: &[&str] = &[
+    "settings.container-registry.credentials",
+    "settings.kubernetes.bootstrap-token",
+    "settings.host-containers.*.environment",
+    "settings.host-containers.*.user-data",
+];
+
 // Kubernetes related settings. The dynamic settings are retrieved from
 // IMDS via Sundog's child "Pluto".
 #[rustfmt::skip]
@@ -53,7 +86,37 @@ pub struct KubernetesSettings {
     pub cluster_certificate: Option<ValidBase64>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub api_server: Option<String>,
+    pub api_server: Option<ValidUrl>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster_domain: Option<ValidHostname>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authentication_mode: Option<KubernetesAuthenticationMode>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bootstrap_token: Option<KubernetesBootstrapToken>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_labels: Option<HashMap<KubernetesLabelKey, KubernetesLabelValue>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_taints: Option<HashMap<KubernetesLabelKey, KubernetesTaintValue>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_pods: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kube_reserved: Option<HashMap<KubernetesReservedResourceKey, KubernetesQuantityValue>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_reserved: Option<HashMap<KubernetesReservedResourceKey, KubernetesQuantityValue>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eviction_hard: Option<HashMap<KubernetesEvictionHardKey, KubernetesThresholdValue>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feature_gates: Option<HashMap<KubernetesFeatureGate, bool>>,
 
     // Dynamic settings.
 
@@ -64,7 +127,7 @@ pub struct KubernetesSettings {
     pub node_ip: Option<Ipv4Addr>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub pod_infra_container_image: Option<String>,
+    pub pod_infra_container_image: Option<ValidImageReference>,
 }
 
 // Updog settings. Taken from userdata. The 'seed' setting is generated
@@ -73,38 +136,49 @@ pub struct KubernetesSettings {
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct UpdatesSettings {
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub metadata_base_url: Option<String>,
+    pub metadata_base_url: Option<ValidUrl>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub target_base_url: Option<String>,
+    pub target_base_url: Option<ValidUrl>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub seed: Option<String>,
-}
 
-// Settings for HostContainers, which manages the lifecycle of privileged, unorchestrated
-// containers that are used for system management purposes.
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
-#[serde(deny_unknown_fields, rename_all = "kebab-case")]
-pub struct HostContainersSettings {
+    // The version to update to: "latest", or a specific version to pin to.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub admin: Option<ContainerImage>,
+    pub version_lock: Option<FriendlyVersion>,
 
+    // If true, updates are applied as soon as they're published, rather than when our wave
+    // (chosen by the seed) comes up.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub control: Option<ContainerImage>,
+    pub ignore_waves: Option<bool>,
 }
 
+// Settings for host containers, which are privileged, unorchestrated containers that are used
+// for system management purposes.  They're keyed by name, like "admin" or "control", and each runs
+// as an instance of the host-containers@ systemd unit.
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
-pub struct ContainerImage {
+pub struct HostContainer {
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub source: Option<String>,
+    pub source: Option<ValidImageReference>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub enabled: Option<bool>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub superpowered: Option<bool>,
+
+    // Written to the container's persistent storage, so the container can read it at startup.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_data: Option<ValidBase64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<HashMap<ValidEnvVarName, SingleLineString>>,
+
+    // Extra bind mounts from the host, like "/var/log:/host/var/log:ro".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mounts: Option<Vec<HostContainerMount>>,
 }
 
 // NTP settings
@@ -112,7 +186,95 @@ pub struct ContainerImage {
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct NtpSettings {
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub time_servers: Option<Vec<String>>,
+    pub time_servers: Option<Vec<ValidHostnameOrIp>>,
+}
+
+// Container registry settings, used by containerd when pulling images.  Each is keyed by the
+// host of the registry, like "docker.io" or "registry.local:5000".
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ContainerRegistrySettings {
+    // Endpoints to pull the registry's images from instead, tried in order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirrors: Option<HashMap<ValidRegistryHost, Vec<ValidUrl>>>,
+
+    // Base64-encoded PEM bundles of CA certificates for registries that use a private CA.
+    // containerd doesn't support per-registry CAs, so every bundle is trusted for every registry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_bundles: Option<HashMap<ValidRegistryHost, ValidBase64>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<HashMap<ValidRegistryHost, RegistryCredential>>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RegistryCredential {
+    // Base64-encoded "username:password", as in a Docker config.json.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<ValidBase64>,
+}
+
+// Kernel settings, applied by corndog when they change.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct KernelSettings {
+    // Values to write to /proc/sys, keyed by sysctl name, like "vm.max_map_count".  Removing a
+    // sysctl from this map doesn't restore its earlier value; that takes a reboot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sysctl: Option<HashMap<SysctlKey, String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modules: Option<HashMap<KmodKey, KmodSetting>>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct KmodSetting {
+    // If false, the module is unloaded and can't be loaded again, even on demand.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed: Option<bool>,
+
+    // If true, the module is loaded rather than waiting for something to need it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub autoload: Option<bool>,
+}
+
+// Network settings.  The proxy settings are given to containerd, docker, the kubelet, and updog
+// through their environment.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct NetworkSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub https_proxy: Option<ValidProxyUrl>,
+
+    // Hosts and networks to reach directly instead of through the proxy.  Localhost and the
+    // instance metadata service are always reached directly.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<Vec<ValidNoProxyEntry>>,
+}
+
+// Metricdog settings.  Metricdog periodically reports the OS version and the health of the
+// services in service-checks, if send-metrics is true.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct MetricsSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_url: Option<ValidUrl>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_metrics: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_checks: Option<Vec<ValidUnitName>>,
+}
+
+// AWS settings.  The region is generated at runtime by regiondog.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AwsSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<AwsRegion>,
 }
 
 ///// Internal services
@@ -1,18 +1,177 @@
 //! This module contains data types that can be used in the model when special input/output
 //! (ser/de) behavior is desired.  For example, the ValidBase64 type can be used for a model field
 //! when we don't even want to accept an API call with invalid base64 data.
+//!
+//! Each type can only be created from valid input, by deserializing or with TryFrom<&str>, and
+//! stores the original string, which is what's serialized back out.
 
+use lazy_static::lazy_static;
+use regex::Regex;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 // Just need serde's Error in scope to get its trait methods
 use serde::de::Error as _;
+use snafu::{ensure, OptionExt, ResultExt};
 use std::borrow::Borrow;
+use std::convert::TryFrom;
 use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
 use std::ops::Deref;
 
-/// ValidBase64 can only be created by deserializing from valid base64 text.  It stores the
-/// original text, not the decoded form.  Its purpose is input validation, namely being used as a
-/// field in a model structure so that you don't even accept a request with a field that has
-/// invalid base64.
+pub mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Invalid base64: {}", source))]
+        InvalidBase64 { source: base64::DecodeError },
+
+        #[snafu(display("Invalid hostname '{}': {}", input, msg))]
+        InvalidHostname { input: String, msg: &'static str },
+
+        #[snafu(display("Invalid timezone '{}': {}", input, msg))]
+        InvalidTimezone { input: String, msg: String },
+
+        #[snafu(display("Invalid registry host '{}': {}", input, msg))]
+        InvalidRegistryHost { input: String, msg: &'static str },
+
+        #[snafu(display("Invalid host '{}': must be a hostname or IP address", input))]
+        InvalidHostnameOrIp { input: String },
+
+        #[snafu(display("Invalid URL '{}': {}", input, source))]
+        InvalidUrl {
+            input: String,
+            source: url::ParseError,
+        },
+
+        #[snafu(display("Invalid proxy URL '{}': {}", input, msg))]
+        InvalidProxyUrl { input: String, msg: &'static str },
+
+        #[snafu(display("Invalid no-proxy entry '{}': {}", input, msg))]
+        InvalidNoProxyEntry { input: String, msg: &'static str },
+
+        #[snafu(display("Invalid systemd unit name '{}'", input))]
+        InvalidUnitName { input: String },
+
+        #[snafu(display("Invalid AWS region '{}'", input))]
+        InvalidAwsRegion { input: String },
+
+        #[snafu(display("Invalid host container name '{}'", input))]
+        InvalidHostContainerName { input: String },
+
+        #[snafu(display("Invalid host container mount '{}': {}", input, msg))]
+        InvalidHostContainerMount { input: String, msg: &'static str },
+
+        #[snafu(display("Invalid environment variable name '{}'", input))]
+        InvalidEnvVarName { input: String },
+
+        #[snafu(display("Value must be a single line"))]
+        MultipleLines,
+
+        #[snafu(display("Invalid Kubernetes label key '{}': {}", input, msg))]
+        InvalidLabelKey { input: String, msg: &'static str },
+
+        #[snafu(display("Invalid Kubernetes label value '{}': {}", input, msg))]
+        InvalidLabelValue { input: String, msg: &'static str },
+
+        #[snafu(display("Invalid Kubernetes taint value '{}': {}", input, msg))]
+        InvalidTaintValue { input: String, msg: &'static str },
+
+        #[snafu(display("Invalid Kubernetes quantity '{}'", input))]
+        InvalidQuantity { input: String },
+
+        #[snafu(display("Invalid Kubernetes eviction threshold '{}'", input))]
+        InvalidThreshold { input: String },
+
+        #[snafu(display("Invalid {} '{}', expected one of: {}", kind, input, valid.join(", ")))]
+        NotInList {
+            kind: &'static str,
+            input: String,
+            valid: &'static [&'static str],
+        },
+
+        #[snafu(display("Invalid Kubernetes feature gate '{}'", input))]
+        InvalidFeatureGate { input: String },
+
+        #[snafu(display(
+            "Invalid Kubernetes bootstrap token; expected 'abcdef.0123456789abcdef'"
+        ))]
+        InvalidBootstrapToken,
+
+        #[snafu(display("Invalid image reference '{}'", input))]
+        InvalidImageReference { input: String },
+
+        #[snafu(display("Invalid sysctl name '{}'", input))]
+        InvalidSysctlKey { input: String },
+
+        #[snafu(display("Invalid kernel module name '{}'", input))]
+        InvalidKmodKey { input: String },
+    }
+}
+
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// Implements the traits shared by our string types: Deserialize, which only accepts input that
+/// TryFrom<&str> accepts; Serialize, which writes the original string back out rather than our
+/// structure, which is just there to force validation; and the traits that let the type be used
+/// like the string it holds.
+macro_rules! string_impls_for {
+    ($for:ident) => {
+        impl<'de> Deserialize<'de> for $for {
+            fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                let original = String::deserialize(deserializer)?;
+                Self::try_from(original.as_str()).map_err(D::Error::custom)
+            }
+        }
+
+        impl Serialize for $for {
+            fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                serializer.serialize_str(&self.inner)
+            }
+        }
+
+        impl Deref for $for {
+            type Target = str;
+            fn deref(&self) -> &Self::Target {
+                &self.inner
+            }
+        }
+
+        impl Borrow<String> for $for {
+            fn borrow(&self) -> &String {
+                &self.inner
+            }
+        }
+
+        impl Borrow<str> for $for {
+            fn borrow(&self) -> &str {
+                &self.inner
+            }
+        }
+
+        impl AsRef<str> for $for {
+            fn as_ref(&self) -> &str {
+                &self.inner
+            }
+        }
+
+        impl fmt::Display for $for {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.inner)
+            }
+        }
+    };
+}
+
+/// ValidBase64 can only be created from valid base64 text.  It stores the original text, not the
+/// decoded form.  Its purpose is input validation, namely being used as a field in a model
+/// structure so that you don't even accept a request with a field that has invalid base64.
 // Note: we use the default base64::STANDARD config which uses/allows "=" padding.
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct ValidBase64 {
@@ -20,63 +179,896 @@ pub struct ValidBase64 {
 }
 
 /// Validate base64 format before we accept the input.
-impl<'de> Deserialize<'de> for ValidBase64 {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let original = String::deserialize(deserializer)?;
-        base64::decode(&original)
-            .map_err(|e| D::Error::custom(format!("Invalid base64: {}", e)))?;
-        Ok(ValidBase64 { inner: original })
+impl TryFrom<&str> for ValidBase64 {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        base64::decode(input).context(error::InvalidBase64)?;
+        Ok(ValidBase64 {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(ValidBase64);
+
+/// ValidHostname can only be created from a valid DNS hostname: dot-separated labels of letters,
+/// digits, and hyphens that don't start or end with a hyphen, as described in RFC 1123.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ValidHostname {
+    inner: String,
+}
+
+lazy_static! {
+    static ref HOSTNAME_LABEL: Regex =
+        Regex::new(r"^[A-Za-z0-9]([A-Za-z0-9-]{0,61}[A-Za-z0-9])?$").unwrap();
+}
+
+impl TryFrom<&str> for ValidHostname {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(
+            !input.is_empty() && input.len() <= 253,
+            error::InvalidHostname {
+                input,
+                msg: "must be 1 to 253 characters long",
+            }
+        );
+        ensure!(
+            input.split('.').all(|label| HOSTNAME_LABEL.is_match(label)),
+            error::InvalidHostname {
+                input,
+                msg: "labels must be 1 to 63 letters, digits, or inner '-'",
+            }
+        );
+        Ok(ValidHostname {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(ValidHostname);
+
+/// ValidHostnameOrIp can only be created from a valid hostname or an IP address, like
+/// "time.example.com", "169.254.169.123", or "fd00:ec2::123".  An IPv6 address may also be
+/// written in brackets, like "[fd00:ec2::123]".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ValidHostnameOrIp {
+    inner: String,
+}
+
+impl TryFrom<&str> for ValidHostnameOrIp {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        let bracketed_ipv6 = input.starts_with('[')
+            && input.ends_with(']')
+            && input[1..input.len() - 1].parse::<Ipv6Addr>().is_ok();
+        ensure!(
+            bracketed_ipv6
+                || input.parse::<IpAddr>().is_ok()
+                || ValidHostname::try_from(input).is_ok(),
+            error::InvalidHostnameOrIp { input }
+        );
+        Ok(ValidHostnameOrIp {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(ValidHostnameOrIp);
+
+/// ValidRegistryHost can only be created from the host of a container registry, which is a valid
+/// hostname with an optional port, like "docker.io" or "registry.local:5000".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ValidRegistryHost {
+    inner: String,
+}
+
+impl TryFrom<&str> for ValidRegistryHost {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        let mut parts = input.splitn(2, ':');
+        // splitn always returns at least one item.
+        let host = parts.next().unwrap_or_default();
+        if let Some(port) = parts.next() {
+            ensure!(
+                port.parse::<u16>().is_ok(),
+                error::InvalidRegistryHost {
+                    input,
+                    msg: "port must be a number from 0 to 65535",
+                }
+            );
+        }
+        ensure!(
+            ValidHostname::try_from(host).is_ok(),
+            error::InvalidRegistryHost {
+                input,
+                msg: "host must be a valid hostname",
+            }
+        );
+        Ok(ValidRegistryHost {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(ValidRegistryHost);
+
+/// ValidTimezone can only be created from the name of a timezone in the IANA timezone database,
+/// like "America/Los_Angeles" or "UTC".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ValidTimezone {
+    inner: String,
+}
+
+impl TryFrom<&str> for ValidTimezone {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        if let Err(msg) = input.parse::<chrono_tz::Tz>() {
+            return error::InvalidTimezone { input, msg }.fail();
+        }
+        Ok(ValidTimezone {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(ValidTimezone);
+
+/// ValidUrl can only be created from an absolute URL, like "https://example.com/path".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ValidUrl {
+    inner: String,
+}
+
+impl TryFrom<&str> for ValidUrl {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        url::Url::parse(input).context(error::InvalidUrl { input })?;
+        Ok(ValidUrl {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(ValidUrl);
+
+/// ValidProxyUrl can only be created from the URL of an HTTP proxy, like
+/// "http://proxy.example.com:3128".  Clients handle the scheme differently, so we require one.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ValidProxyUrl {
+    inner: String,
+}
+
+impl TryFrom<&str> for ValidProxyUrl {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        let url = url::Url::parse(input)
+            .ok()
+            .context(error::InvalidProxyUrl {
+                input,
+                msg: "must be an absolute URL",
+            })?;
+        ensure!(
+            url.scheme() == "http" || url.scheme() == "https",
+            error::InvalidProxyUrl {
+                input,
+                msg: "scheme must be 'http' or 'https'",
+            }
+        );
+        ensure!(
+            url.host_str().is_some(),
+            error::InvalidProxyUrl {
+                input,
+                msg: "must include a host",
+            }
+        );
+        Ok(ValidProxyUrl {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(ValidProxyUrl);
+
+/// ValidNoProxyEntry can only be created from something a client can match against the hosts it
+/// connects to: an IP address, a CIDR block like "10.0.0.0/8", or a hostname, which also matches
+/// its subdomains and may start with a dot, like ".example.com".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ValidNoProxyEntry {
+    inner: String,
+}
+
+impl TryFrom<&str> for ValidNoProxyEntry {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        let mut parts = input.splitn(2, '/');
+        // splitn always returns at least one item.
+        let host = parts.next().unwrap_or_default();
+        if let Some(prefix_len) = parts.next() {
+            let address = host
+                .parse::<IpAddr>()
+                .ok()
+                .context(error::InvalidNoProxyEntry {
+                    input,
+                    msg: "CIDR block must start with an IP address",
+                })?;
+            let max_len = if address.is_ipv4() { 32 } else { 128 };
+            ensure!(
+                prefix_len
+                    .parse::<u8>()
+                    .map(|l| l <= max_len)
+                    .unwrap_or(false),
+                error::InvalidNoProxyEntry {
+                    input,
+                    msg: "CIDR prefix length is out of range for the address",
+                }
+            );
+        } else if host.parse::<IpAddr>().is_err() {
+            let name = host.trim_start_matches('.');
+            ensure!(
+                ValidHostname::try_from(name).is_ok(),
+                error::InvalidNoProxyEntry {
+                    input,
+                    msg: "must be an IP address, CIDR block, or hostname",
+                }
+            );
+        }
+        Ok(ValidNoProxyEntry {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(ValidNoProxyEntry);
+
+// FriendlyVersion is shared with thar-be-updates, which can't depend on the API server.
+pub use friendly_version::FriendlyVersion;
+
+lazy_static! {
+    /// A systemd unit name, with or without its type suffix, like "containerd" or
+    /// "host-containers@admin.service".
+    static ref UNIT_NAME: Regex = Regex::new(r"^[a-zA-Z0-9:_.@-]{1,255}$").unwrap();
+
+    /// An AWS region, like "us-west-2" or "us-gov-east-1".
+    static ref AWS_REGION: Regex = Regex::new(r"^[a-z]{2}(?:-[a-z]+)+-[0-9]+$").unwrap();
+}
+
+/// ValidUnitName can only be created from a valid systemd unit name.  We don't check that the
+/// unit exists, since that depends on the variant.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ValidUnitName {
+    inner: String,
+}
+
+impl TryFrom<&str> for ValidUnitName {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(UNIT_NAME.is_match(input), error::InvalidUnitName { input });
+        Ok(ValidUnitName {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(ValidUnitName);
+
+/// AwsRegion can only be created from something shaped like an AWS region name, like
+/// "us-west-2".  We don't check it against a list, since new regions are added over time.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct AwsRegion {
+    inner: String,
+}
+
+impl TryFrom<&str> for AwsRegion {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(
+            AWS_REGION.is_match(input),
+            error::InvalidAwsRegion { input }
+        );
+        Ok(AwsRegion {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(AwsRegion);
+
+lazy_static! {
+    /// A host container name, which is used in file paths and systemd unit names.
+    static ref HOST_CONTAINER_NAME: Regex =
+        Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9_-]{0,63}$").unwrap();
+
+    /// An environment variable name, like "HTTP_PROXY".
+    static ref ENV_VAR_NAME: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+}
+
+/// HostContainerName can only be created from a valid host container name, like "admin".  Names
+/// are letters, digits, dashes, and underscores, so they're safe to use in paths and unit names.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct HostContainerName {
+    inner: String,
+}
+
+impl TryFrom<&str> for HostContainerName {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(
+            HOST_CONTAINER_NAME.is_match(input),
+            error::InvalidHostContainerName { input }
+        );
+        Ok(HostContainerName {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(HostContainerName);
+
+/// HostContainerMount can only be created from a bind mount description in the style of
+/// "docker run -v": an absolute host path and an absolute container path, separated by a colon,
+/// and optionally followed by ":ro" or ":rw", like "/var/log:/host/var/log:ro".  Mounts are
+/// read-write unless given ":ro".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct HostContainerMount {
+    inner: String,
+}
+
+impl HostContainerMount {
+    /// Returns the host path.
+    pub fn source(&self) -> &str {
+        self.parts().0
+    }
+
+    /// Returns the path in the container.
+    pub fn destination(&self) -> &str {
+        self.parts().1
+    }
+
+    /// Returns whether the mount is read-only.
+    pub fn read_only(&self) -> bool {
+        self.parts().2 == Some("ro")
+    }
+
+    fn parts(&self) -> (&str, &str, Option<&str>) {
+        // We checked the format on creation.
+        let mut parts = self.inner.split(':');
+        let source = parts.next().unwrap_or_default();
+        let destination = parts.next().unwrap_or_default();
+        (source, destination, parts.next())
+    }
+}
+
+impl TryFrom<&str> for HostContainerMount {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        let parts: Vec<&str> = input.split(':').collect();
+        ensure!(
+            parts.len() == 2 || parts.len() == 3,
+            error::InvalidHostContainerMount {
+                input,
+                msg: "expected 'source:destination' with an optional ':ro' or ':rw'",
+            }
+        );
+        ensure!(
+            parts[..2].iter().all(|p| p.starts_with('/')),
+            error::InvalidHostContainerMount {
+                input,
+                msg: "paths must be absolute",
+            }
+        );
+        ensure!(
+            !input.contains('\n'),
+            error::InvalidHostContainerMount {
+                input,
+                msg: "paths can't contain newlines",
+            }
+        );
+        if let Some(mode) = parts.get(2) {
+            ensure!(
+                *mode == "ro" || *mode == "rw",
+                error::InvalidHostContainerMount {
+                    input,
+                    msg: "mode must be 'ro' or 'rw'",
+                }
+            );
+        }
+        Ok(HostContainerMount {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(HostContainerMount);
+
+/// ValidEnvVarName can only be created from a valid environment variable name, like "HOME".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ValidEnvVarName {
+    inner: String,
+}
+
+impl TryFrom<&str> for ValidEnvVarName {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(
+            ENV_VAR_NAME.is_match(input),
+            error::InvalidEnvVarName { input }
+        );
+        Ok(ValidEnvVarName {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(ValidEnvVarName);
+
+/// SingleLineString can be created from any string without a line break, so it can be written
+/// to line-based files like environment files.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SingleLineString {
+    inner: String,
+}
+
+impl TryFrom<&str> for SingleLineString {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        // Don't include the input in the error, since it could be a secret.
+        ensure!(
+            !input.contains(|c| c == '\n' || c == '\r'),
+            error::MultipleLines
+        );
+        Ok(SingleLineString {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(SingleLineString);
+
+lazy_static! {
+    /// The name part of a Kubernetes label key, and the form of a non-empty label value.
+    static ref LABEL_NAME: Regex =
+        Regex::new(r"^[A-Za-z0-9]([-A-Za-z0-9_.]*[A-Za-z0-9])?$").unwrap();
+
+    /// A DNS subdomain, the form of the optional prefix of a Kubernetes label key.
+    static ref DNS_SUBDOMAIN: Regex =
+        Regex::new(r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?(\.[a-z0-9]([-a-z0-9]*[a-z0-9])?)*$").unwrap();
+}
+
+/// KubernetesLabelKey can only be created from a valid Kubernetes label key: a name of up to 63
+/// characters, optionally preceded by a DNS subdomain prefix of up to 253 characters and a slash,
+/// like "example.com/role".
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct KubernetesLabelKey {
+    inner: String,
+}
+
+impl TryFrom<&str> for KubernetesLabelKey {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        let mut parts = input.rsplitn(2, '/');
+        // rsplitn always returns at least one item.
+        let name = parts.next().unwrap_or_default();
+        if let Some(prefix) = parts.next() {
+            ensure!(
+                prefix.len() <= 253 && DNS_SUBDOMAIN.is_match(prefix),
+                error::InvalidLabelKey {
+                    input,
+                    msg: "prefix must be a lowercase DNS subdomain of up to 253 characters",
+                }
+            );
+        }
+        ensure!(
+            name.len() <= 63 && LABEL_NAME.is_match(name),
+            error::InvalidLabelKey {
+                input,
+                msg: "name must be 1 to 63 characters; see KubernetesLabelKey",
+            }
+        );
+        Ok(KubernetesLabelKey {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(KubernetesLabelKey);
+
+/// KubernetesLabelValue can only be created from a valid Kubernetes label value: empty, or up to
+/// 63 characters that follow the same rules as the name part of a label key.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KubernetesLabelValue {
+    inner: String,
+}
+
+impl TryFrom<&str> for KubernetesLabelValue {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(
+            input.is_empty() || (input.len() <= 63 && LABEL_NAME.is_match(input)),
+            error::InvalidLabelValue {
+                input,
+                msg: "must be empty or 1 to 63 characters; see KubernetesLabelValue",
+            }
+        );
+        Ok(KubernetesLabelValue {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(KubernetesLabelValue);
+
+/// The effects a Kubernetes taint can have on pods that don't tolerate it.
+const TAINT_EFFECTS: &[&str] = &["NoSchedule", "PreferNoSchedule", "NoExecute"];
+
+/// KubernetesTaintValue can only be created from a valid Kubernetes taint value and effect,
+/// separated by a colon, like "special:NoSchedule".  The value can be empty, as in ":NoExecute",
+/// and otherwise follows the rules for a label value.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KubernetesTaintValue {
+    inner: String,
+}
+
+impl TryFrom<&str> for KubernetesTaintValue {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        let mut parts = input.rsplitn(2, ':');
+        // rsplitn always returns at least one item.
+        let effect = parts.next().unwrap_or_default();
+        let value = parts.next().context(error::InvalidTaintValue {
+            input,
+            msg: "must be a value and effect separated by ':'",
+        })?;
+        ensure!(
+            TAINT_EFFECTS.contains(&effect),
+            error::InvalidTaintValue {
+                input,
+                msg: "effect must be NoSchedule, PreferNoSchedule, or NoExecute",
+            }
+        );
+        ensure!(
+            value.is_empty() || (value.len() <= 63 && LABEL_NAME.is_match(value)),
+            error::InvalidTaintValue {
+                input,
+                msg: "value must be empty or 1 to 63 characters; see KubernetesLabelValue",
+            }
+        );
+        Ok(KubernetesTaintValue {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(KubernetesTaintValue);
+
+lazy_static! {
+    /// A non-negative Kubernetes resource quantity, like "100m", "1.5", or "512Mi".
+    static ref QUANTITY: Regex =
+        Regex::new(r"^(?:[0-9]+(?:\.[0-9]*)?|\.[0-9]+)(?:[KMGTPE]i|[mkMGTPE]|[eE][-+]?[0-9]+)?$")
+            .unwrap();
+
+    /// A percentage from 0 to 100, like "10%" or "2.5%".
+    static ref PERCENTAGE: Regex =
+        Regex::new(r"^(?:100(?:\.0*)?|[0-9]{1,2}(?:\.[0-9]*)?)%$").unwrap();
+
+    /// A Kubernetes feature gate name, like "RotateKubeletServerCertificate".
+    static ref FEATURE_GATE: Regex = Regex::new(r"^[A-Z][A-Za-z0-9]*$").unwrap();
+}
+
+/// KubernetesQuantityValue can only be created from a non-negative Kubernetes resource quantity,
+/// like "100m" of CPU or "512Mi" of memory.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KubernetesQuantityValue {
+    inner: String,
+}
+
+impl TryFrom<&str> for KubernetesQuantityValue {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(QUANTITY.is_match(input), error::InvalidQuantity { input });
+        Ok(KubernetesQuantityValue {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(KubernetesQuantityValue);
+
+/// The resources the kubelet can reserve for Kubernetes and system daemons.
+const RESERVED_RESOURCES: &[&str] = &["cpu", "memory", "ephemeral-storage", "pid"];
+
+/// KubernetesReservedResourceKey can only be created from the name of a resource the kubelet can
+/// reserve, like "cpu" or "memory".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KubernetesReservedResourceKey {
+    inner: String,
+}
+
+impl TryFrom<&str> for KubernetesReservedResourceKey {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(
+            RESERVED_RESOURCES.contains(&input),
+            error::NotInList {
+                kind: "reserved resource",
+                input,
+                valid: RESERVED_RESOURCES,
+            }
+        );
+        Ok(KubernetesReservedResourceKey {
+            inner: input.to_string(),
+        })
     }
 }
 
-/// We want to serialize the original string back out, not our structure, which is just there to
-/// force validation.
-impl Serialize for ValidBase64 {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_str(&self.inner)
+string_impls_for!(KubernetesReservedResourceKey);
+
+/// The signals the kubelet can use for hard eviction.
+const EVICTION_SIGNALS: &[&str] = &[
+    "memory.available",
+    "nodefs.available",
+    "nodefs.inodesFree",
+    "imagefs.available",
+    "imagefs.inodesFree",
+    "pid.available",
+];
+
+/// KubernetesEvictionHardKey can only be created from the name of an eviction signal, like
+/// "memory.available".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KubernetesEvictionHardKey {
+    inner: String,
+}
+
+impl TryFrom<&str> for KubernetesEvictionHardKey {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(
+            EVICTION_SIGNALS.contains(&input),
+            error::NotInList {
+                kind: "eviction signal",
+                input,
+                valid: EVICTION_SIGNALS,
+            }
+        );
+        Ok(KubernetesEvictionHardKey {
+            inner: input.to_string(),
+        })
     }
 }
 
-impl Deref for ValidBase64 {
-    type Target = str;
-    fn deref(&self) -> &Self::Target {
-        &self.inner
+string_impls_for!(KubernetesEvictionHardKey);
+
+/// KubernetesThresholdValue can only be created from an eviction threshold, which is either a
+/// resource quantity, like "100Mi", or a percentage, like "10%".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KubernetesThresholdValue {
+    inner: String,
+}
+
+impl TryFrom<&str> for KubernetesThresholdValue {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(
+            QUANTITY.is_match(input) || PERCENTAGE.is_match(input),
+            error::InvalidThreshold { input }
+        );
+        Ok(KubernetesThresholdValue {
+            inner: input.to_string(),
+        })
     }
 }
 
-impl Borrow<String> for ValidBase64 {
-    fn borrow(&self) -> &String {
-        &self.inner
+string_impls_for!(KubernetesThresholdValue);
+
+/// KubernetesFeatureGate can only be created from something shaped like a Kubernetes feature gate
+/// name, like "RotateKubeletServerCertificate".  We don't check it against the kubelet's list,
+/// which changes with each release.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KubernetesFeatureGate {
+    inner: String,
+}
+
+impl TryFrom<&str> for KubernetesFeatureGate {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(
+            FEATURE_GATE.is_match(input),
+            error::InvalidFeatureGate { input }
+        );
+        Ok(KubernetesFeatureGate {
+            inner: input.to_string(),
+        })
     }
 }
 
-impl Borrow<str> for ValidBase64 {
-    fn borrow(&self) -> &str {
-        &self.inner
+string_impls_for!(KubernetesFeatureGate);
+
+/// The ways the kubelet can authenticate to the API server: "aws" uses aws-iam-authenticator with
+/// the node's IAM role, and "tls" uses TLS bootstrapping to get a client certificate.
+const AUTHENTICATION_MODES: &[&str] = &["aws", "tls"];
+
+/// KubernetesAuthenticationMode can only be created from one of the supported ways for the kubelet
+/// to authenticate to the API server, "aws" or "tls".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KubernetesAuthenticationMode {
+    inner: String,
+}
+
+impl TryFrom<&str> for KubernetesAuthenticationMode {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(
+            AUTHENTICATION_MODES.contains(&input),
+            error::NotInList {
+                kind: "authentication mode",
+                input,
+                valid: AUTHENTICATION_MODES,
+            }
+        );
+        Ok(KubernetesAuthenticationMode {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(KubernetesAuthenticationMode);
+
+lazy_static! {
+    /// A Kubernetes bootstrap token: a six-character ID and a sixteen-character secret.
+    static ref BOOTSTRAP_TOKEN: Regex = Regex::new(r"^[a-z0-9]{6}\.[a-z0-9]{16}$").unwrap();
+}
+
+/// KubernetesBootstrapToken can only be created from a valid Kubernetes bootstrap token, which
+/// the kubelet uses to request a client certificate in the "tls" authentication mode.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KubernetesBootstrapToken {
+    inner: String,
+}
+
+impl TryFrom<&str> for KubernetesBootstrapToken {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        // Don't include the token in the error, since it's a secret.
+        ensure!(
+            BOOTSTRAP_TOKEN.is_match(input),
+            error::InvalidBootstrapToken
+        );
+        Ok(KubernetesBootstrapToken {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(KubernetesBootstrapToken);
+
+lazy_static! {
+    /// A container image reference, following the grammar in the distribution project's
+    /// reference package: an optional registry host and port, a path of lowercase components,
+    /// and an optional tag and digest, like "example.com:5000/team/image:v1.2".
+    static ref IMAGE_REFERENCE: Regex = {
+        let domain_component = r"(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9])";
+        let domain = format!(r"{0}(?:\.{0})*(?::[0-9]+)?", domain_component);
+        let path_component = r"[a-z0-9]+(?:(?:[._]|__|[-]*)[a-z0-9]+)*";
+        let tag = r"[\w][\w.-]{0,127}";
+        let digest = r"[A-Za-z][A-Za-z0-9]*(?:[-_+.][A-Za-z][A-Za-z0-9]*)*:[0-9a-fA-F]{32,}";
+        Regex::new(&format!(
+            r"^(?:{domain}/)?{path}(?:/{path})*(?::{tag})?(?:@{digest})?$",
+            domain = domain,
+            path = path_component,
+            tag = tag,
+            digest = digest
+        ))
+        .unwrap()
+    };
+}
+
+/// ValidImageReference can only be created from a valid container image reference, like
+/// "example.com/team/image:v1.2" or "image@sha256:<digest>".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ValidImageReference {
+    inner: String,
+}
+
+impl TryFrom<&str> for ValidImageReference {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(
+            IMAGE_REFERENCE.is_match(input),
+            error::InvalidImageReference { input }
+        );
+        Ok(ValidImageReference {
+            inner: input.to_string(),
+        })
     }
 }
 
-impl AsRef<str> for ValidBase64 {
-    fn as_ref(&self) -> &str {
-        &self.inner
+string_impls_for!(ValidImageReference);
+
+lazy_static! {
+    /// A sysctl name, like "net.ipv4.ip_forward".  Components are separated by dots, and a dot
+    /// that's part of a component, like in an interface name, is written as a slash, as sysctl(8)
+    /// does.  Slashes can't start or end a component, so a name can't become a path like ".."
+    /// under /proc/sys.
+    static ref SYSCTL_KEY: Regex = {
+        let component = r"[a-zA-Z0-9_-](?:[a-zA-Z0-9_/-]*[a-zA-Z0-9_-])?";
+        Regex::new(&format!(r"^{0}(?:\.{0})*$", component)).unwrap()
+    };
+
+    /// A kernel module name, like "br_netfilter".
+    static ref KMOD_KEY: Regex = Regex::new(r"^[a-zA-Z0-9_-]{1,55}$").unwrap();
+}
+
+/// SysctlKey can only be created from a valid sysctl name, like "vm.max_map_count".  We don't
+/// check that the sysctl exists, since that depends on the kernel and its loaded modules.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SysctlKey {
+    inner: String,
+}
+
+impl TryFrom<&str> for SysctlKey {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(
+            SYSCTL_KEY.is_match(input),
+            error::InvalidSysctlKey { input }
+        );
+        Ok(SysctlKey {
+            inner: input.to_string(),
+        })
     }
 }
 
-impl fmt::Display for ValidBase64 {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.inner)
+string_impls_for!(SysctlKey);
+
+/// KmodKey can only be created from a valid kernel module name, like "nf_conntrack".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct KmodKey {
+    inner: String,
+}
+
+impl TryFrom<&str> for KmodKey {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        ensure!(KMOD_KEY.is_match(input), error::InvalidKmodKey { input });
+        Ok(KmodKey {
+            inner: input.to_string(),
+        })
     }
 }
 
+string_impls_for!(KmodKey);
+
 #[cfg(test)]
 mod test {
-    use super::ValidBase64;
+    use super::*;
 
     #[test]
     fn valid_base64() {
@@ -91,4 +1083,318 @@ mod test {
         assert!(serde_json::from_str::<ValidBase64>("\"invalid base64\"").is_err());
         assert!(serde_json::from_str::<ValidBase64>("").is_err());
     }
+
+    #[test]
+    fn hostname() {
+        for ok in &[
+            "localhost",
+            "ip-10-0-0-1.us-west-2.compute.internal",
+            "169.254.169.123",
+        ] {
+            ValidHostname::try_from(*ok).unwrap();
+        }
+        let long_label = "a".repeat(64);
+        let long_name = vec!["a"; 127].join(".") + ".ab";
+        for bad in &["", "a b", "-a", "a-", "a..b", &long_label, &long_name] {
+            ValidHostname::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn hostname_or_ip() {
+        for ok in &[
+            "time.example.com",
+            "169.254.169.123",
+            "fd00:ec2::123",
+            "[fd00:ec2::123]",
+        ] {
+            ValidHostnameOrIp::try_from(*ok).unwrap();
+        }
+        for bad in &[
+            "",
+            "a b",
+            "[time.example.com]",
+            "[fd00:ec2::123",
+            "fd00::ec2::123",
+        ] {
+            ValidHostnameOrIp::try_from(*bad).unwrap_err();
+        }
+
+        // NTP servers are commonly given by address
+        let ntp: crate::model::NtpSettings =
+            serde_json::from_str(r#"{"time-servers": ["time.example.com", "fd00:ec2::123"]}"#)
+                .unwrap();
+        assert_eq!(ntp.time_servers.unwrap()[1].as_ref(), "fd00:ec2::123");
+    }
+
+    #[test]
+    fn registry_host() {
+        for ok in &["docker.io", "registry.local:5000", "10.0.0.1:443"] {
+            ValidRegistryHost::try_from(*ok).unwrap();
+        }
+        for bad in &[
+            "",
+            ":5000",
+            "registry.local:",
+            "registry.local:http",
+            "a:1:2",
+            "a/b",
+        ] {
+            ValidRegistryHost::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn timezone() {
+        ValidTimezone::try_from("America/Los_Angeles").unwrap();
+        ValidTimezone::try_from("UTC").unwrap();
+        ValidTimezone::try_from("Mars/Olympus_Mons").unwrap_err();
+        ValidTimezone::try_from("").unwrap_err();
+    }
+
+    #[test]
+    fn url() {
+        ValidUrl::try_from("https://example.eks.amazonaws.com").unwrap();
+        ValidUrl::try_from("example.com").unwrap_err();
+    }
+
+    #[test]
+    fn proxy_url() {
+        for ok in &[
+            "http://proxy.example.com:3128",
+            "https://10.0.0.1",
+            "http://user:pw@proxy",
+        ] {
+            ValidProxyUrl::try_from(*ok).unwrap();
+        }
+        for bad in &[
+            "",
+            "proxy.example.com:3128",
+            "socks5://proxy:1080",
+            "http://",
+        ] {
+            ValidProxyUrl::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn no_proxy_entry() {
+        for ok in &[
+            "10.0.0.0/8",
+            "192.168.1.1",
+            "fd00::/8",
+            "::1",
+            "example.com",
+            ".example.com",
+        ] {
+            ValidNoProxyEntry::try_from(*ok).unwrap();
+        }
+        for bad in &[
+            "",
+            "10.0.0.0/33",
+            "fd00::/129",
+            "example.com/8",
+            "10.0.0.0/",
+            "a,b",
+            ".",
+        ] {
+            ValidNoProxyEntry::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn unit_name() {
+        for ok in &["containerd", "host-containers@admin.service", "var.mount"] {
+            ValidUnitName::try_from(*ok).unwrap();
+        }
+        for bad in &["", "a b", "a/b", "a\"b"] {
+            ValidUnitName::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn aws_region() {
+        for ok in &["us-west-2", "us-gov-east-1", "cn-north-1", "ap-southeast-1"] {
+            AwsRegion::try_from(*ok).unwrap();
+        }
+        for bad in &["", "us-west", "US-WEST-2", "us_west_2", "west-2"] {
+            AwsRegion::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn host_container_name() {
+        for ok in &["admin", "control", "my_debug-1"] {
+            HostContainerName::try_from(*ok).unwrap();
+        }
+        for bad in &["", "-admin", "a.b", "a/b", "a@b", &"a".repeat(65)] {
+            HostContainerName::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn host_container_mount() {
+        let mount = HostContainerMount::try_from("/var/log:/host/var/log:ro").unwrap();
+        assert_eq!(mount.source(), "/var/log");
+        assert_eq!(mount.destination(), "/host/var/log");
+        assert!(mount.read_only());
+        assert!(!HostContainerMount::try_from("/a:/b:rw")
+            .unwrap()
+            .read_only());
+        assert!(!HostContainerMount::try_from("/a:/b").unwrap().read_only());
+        for bad in &[
+            "",
+            "/a",
+            "a:/b",
+            "/a:b",
+            "/a:/b:rx",
+            "/a:/b:ro:x",
+            "/a\n:/b",
+        ] {
+            HostContainerMount::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn env() {
+        ValidEnvVarName::try_from("HTTP_PROXY").unwrap();
+        ValidEnvVarName::try_from("_a1").unwrap();
+        for bad in &["", "1A", "A-B", "A=B", "A B"] {
+            ValidEnvVarName::try_from(*bad).unwrap_err();
+        }
+        SingleLineString::try_from("").unwrap();
+        SingleLineString::try_from("a b=c").unwrap();
+        SingleLineString::try_from("a\nb").unwrap_err();
+        SingleLineString::try_from("a\rb").unwrap_err();
+    }
+
+    #[test]
+    fn label_key() {
+        for ok in &["role", "node.example.com/role", "a_b.c-d", "example.com/A"] {
+            KubernetesLabelKey::try_from(*ok).unwrap();
+        }
+        let long_name = "a".repeat(64);
+        for bad in &[
+            "",
+            "/role",
+            "Example.com/role",
+            "a/b/c",
+            "-role",
+            &long_name,
+        ] {
+            KubernetesLabelKey::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn label_value() {
+        for ok in &["", "worker", "a.b_c-d"] {
+            KubernetesLabelValue::try_from(*ok).unwrap();
+        }
+        for bad in &["a b", "_a", "a/b", &"a".repeat(64)] {
+            KubernetesLabelValue::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn taint_value() {
+        for ok in &[
+            "special:NoSchedule",
+            ":NoExecute",
+            "a.b_c-d:PreferNoSchedule",
+        ] {
+            KubernetesTaintValue::try_from(*ok).unwrap();
+        }
+        for bad in &["", "special", "special:", "special:Never", "a b:NoSchedule"] {
+            KubernetesTaintValue::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn quantity() {
+        for ok in &["0", "100m", "1.5", ".5", "512Mi", "1Gi", "2e3"] {
+            KubernetesQuantityValue::try_from(*ok).unwrap();
+        }
+        for bad in &["", "-1", "1.2.3", "1 Gi", "1gi", "10%"] {
+            KubernetesQuantityValue::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn threshold() {
+        for ok in &["100Mi", "10%", "2.5%", "100%"] {
+            KubernetesThresholdValue::try_from(*ok).unwrap();
+        }
+        for bad in &["", "%", "101%", "-5%", "ten%"] {
+            KubernetesThresholdValue::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn lists() {
+        KubernetesReservedResourceKey::try_from("ephemeral-storage").unwrap();
+        KubernetesReservedResourceKey::try_from("gpu").unwrap_err();
+        KubernetesEvictionHardKey::try_from("nodefs.inodesFree").unwrap();
+        KubernetesEvictionHardKey::try_from("memory").unwrap_err();
+        KubernetesAuthenticationMode::try_from("tls").unwrap();
+        KubernetesAuthenticationMode::try_from("TLS").unwrap_err();
+        KubernetesBootstrapToken::try_from("abcdef.0123456789abcdef").unwrap();
+        KubernetesBootstrapToken::try_from("abcdef.0123456789").unwrap_err();
+    }
+
+    #[test]
+    fn feature_gate() {
+        KubernetesFeatureGate::try_from("CSIMigration").unwrap();
+        for bad in &["", "csiMigration", "CSI-Migration", "CSI Migration"] {
+            KubernetesFeatureGate::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn image_reference() {
+        for ok in &[
+            "busybox",
+            "library/busybox:latest",
+            "328549459982.dkr.ecr.us-west-2.amazonaws.com/thar-admin:v0.1",
+            &format!("localhost:5000/a/b@sha256:{}", "0123456789abcdef".repeat(4)),
+        ] {
+            ValidImageReference::try_from(*ok).unwrap();
+        }
+        for bad in &["", "Busybox", "busybox:", "a//b", "busybox:tag with space"] {
+            ValidImageReference::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn sysctl_key() {
+        for ok in &[
+            "vm.max_map_count",
+            "net.ipv4.conf.eth0/1.rp_filter",
+            "kernel.pid_max",
+        ] {
+            SysctlKey::try_from(*ok).unwrap();
+        }
+        for bad in &[
+            "", ".vm", "vm.", "vm..a", "net./", "net.//", "net./a", "a b", "../etc",
+        ] {
+            SysctlKey::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn kmod_key() {
+        KmodKey::try_from("br_netfilter").unwrap();
+        KmodKey::try_from("nf-conntrack").unwrap();
+        for bad in &["", "a.b", "a/b", &"a".repeat(56)] {
+            KmodKey::try_from(*bad).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn serde() {
+        let hostname: ValidHostname = serde_json::from_str("\"example\"").unwrap();
+        assert_eq!(&*hostname, "example");
+        assert_eq!(serde_json::to_string(&hostname).unwrap(), "\"example\"");
+        serde_json::from_str::<ValidHostname>("\"not a hostname\"").unwrap_err();
+    }
 }
@@ -0,0 +1,298 @@
+//! The auth module decides whether a client connected to the API socket may make a request.
+//!
+//! We read the peer credentials (SO_PEERCRED) of each connection when it's accepted, and check
+//! them against a policy loaded from a TOML file.  The policy is a list of rules; each rule names
+//! a uid and/or gid, the HTTP methods it allows, and the key prefixes it allows them on.  For
+//! example, to let containers running with gid 1000 read Kubernetes settings:
+//!
+//! ```toml
+//! [[rules]]
+//! gid = 1000
+//! methods = ["GET"]
+//! prefixes = ["settings.kubernetes"]
+//! ```
+//!
+//! Root (uid 0) is always allowed, so system services keep working.  If no policy is given,
+//! every client is allowed, as before.
+
+use nix::sys::socket::{getsockopt, sockopt};
+use serde::Deserialize;
+use snafu::{ensure, ResultExt};
+use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use tokio_uds::UnixStream;
+
+use crate::datastore::{Key, KeyType};
+use crate::server::error::{self, Result};
+
+/// Log target for audit entries, so they can be found or filtered easily.
+const AUDIT_TARGET: &str = "apiserver::audit";
+
+/// The credentials of the process on the other end of a connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PeerCredentials {
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    pub(crate) pid: i32,
+}
+
+/// Request-local data holding the credentials of the connection the request came in on.  It's
+/// None if we couldn't read them.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionCredentials(pub(crate) Option<PeerCredentials>);
+
+/// Reads the peer credentials of a newly accepted connection.  This is given to the HTTP
+/// service's on_connect hook, which stores the result with each request on the connection.
+pub(crate) fn connection_credentials(stream: &UnixStream) -> ConnectionCredentials {
+    match getsockopt(stream.as_raw_fd(), sockopt::PeerCredentials) {
+        Ok(creds) => ConnectionCredentials(Some(PeerCredentials {
+            uid: creds.uid(),
+            gid: creds.gid(),
+            pid: creds.pid(),
+        })),
+        Err(e) => {
+            warn!("Unable to read peer credentials of connection: {}", e);
+            ConnectionCredentials(None)
+        }
+    }
+}
+
+/// A single policy rule; see the module docs.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    uid: Option<u32>,
+    gid: Option<u32>,
+    methods: Vec<String>,
+    prefixes: Vec<String>,
+}
+
+impl Rule {
+    /// Returns whether the rule applies to the given client.
+    fn matches_client(&self, creds: &PeerCredentials) -> bool {
+        self.uid.map(|uid| uid == creds.uid).unwrap_or(true)
+            && self.gid.map(|gid| gid == creds.gid).unwrap_or(true)
+    }
+
+    /// Returns whether the rule allows the given method on the given key.
+    fn allows(&self, method: &str, key: &str) -> bool {
+        self.methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+            && self
+                .prefixes
+                .iter()
+                .any(|prefix| key_has_prefix(key, prefix))
+    }
+}
+
+/// Returns whether the key is the prefix or falls under it.  We match on whole key segments, so
+/// "settings.kubernetes" covers "settings.kubernetes.cluster-name", but not
+/// "settings.kubernetes-extra".  Quoted segments and list indexes count too, so
+/// "settings.ntp.time-servers" covers "settings.ntp.time-servers[2]".
+pub(crate) fn key_has_prefix(key: &str, prefix: &str) -> bool {
+    key == prefix
+        || Key::new(KeyType::Data, key)
+            .map(|key| key.ancestors().contains(&prefix))
+            .unwrap_or(false)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Policy {
+    #[serde(default)]
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    /// Loads a policy from the TOML file at the given path.
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let policy_str = fs::read_to_string(path).context(error::PolicyRead { path })?;
+        Self::parse(&policy_str, path)
+    }
+
+    /// Parses a policy from TOML; the path is only used for error messages.
+    fn parse(policy_str: &str, path: &Path) -> Result<Self> {
+        let policy: Policy = toml::from_str(policy_str).context(error::PolicyParse { path })?;
+        for rule in &policy.rules {
+            // A rule without a uid or gid would apply to everyone, which is more likely a
+            // mistake than a policy.
+            ensure!(
+                rule.uid.is_some() || rule.gid.is_some(),
+                error::PolicyInvalid {
+                    path,
+                    msg: "every rule needs a uid or gid",
+                }
+            );
+        }
+        Ok(policy)
+    }
+
+    /// Returns whether the given client may use the given method on all of the given keys.  Each
+    /// key can be allowed by a different rule.
+    fn allows<S: AsRef<str>>(&self, creds: &PeerCredentials, method: &str, keys: &[S]) -> bool {
+        if creds.uid == 0 {
+            return true;
+        }
+
+        let rules: Vec<&Rule> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.matches_client(creds))
+            .collect();
+        keys.iter()
+            .all(|key| rules.iter().any(|rule| rule.allows(method, key.as_ref())))
+    }
+}
+
+/// Returns whether the client may see the values of secret settings.  Only root can, since system
+/// services need them to render configuration files.
+pub(crate) fn reveals_secrets(creds: Option<&ConnectionCredentials>) -> bool {
+    creds.and_then(|c| c.0).map(|c| c.uid == 0).unwrap_or(false)
+}
+
+/// Checks whether the client that made a request may use the given method on the given keys,
+/// and writes an audit log entry if not.  Always allows the request if there's no policy.
+pub(crate) fn authorize<S: AsRef<str>>(
+    policy: Option<&Policy>,
+    creds: Option<&ConnectionCredentials>,
+    method: &str,
+    path: &str,
+    keys: &[S],
+) -> Result<()> {
+    let policy = match policy {
+        Some(policy) => policy,
+        None => return Ok(()),
+    };
+
+    let key_list = || {
+        keys.iter()
+            .map(|k| k.as_ref())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    match creds.and_then(|c| c.0) {
+        Some(creds) => {
+            if !policy.allows(&creds, method, keys) {
+                warn!(
+                    target: AUDIT_TARGET,
+                    "Denied {} {} on keys [{}] for uid={} gid={} pid={}",
+                    method,
+                    path,
+                    key_list(),
+                    creds.uid,
+                    creds.gid,
+                    creds.pid
+                );
+                return error::Forbidden { method, path }.fail();
+            }
+        }
+        None => {
+            warn!(
+                target: AUDIT_TARGET,
+                "Denied {} {} on keys [{}] for client with unknown credentials",
+                method,
+                path,
+                key_list()
+            );
+            return error::Forbidden { method, path }.fail();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn creds(uid: u32, gid: u32) -> PeerCredentials {
+        PeerCredentials { uid, gid, pid: 1 }
+    }
+
+    const POLICY: &str = r#"
+        [[rules]]
+        gid = 1000
+        methods = ["GET"]
+        prefixes = ["settings.kubernetes"]
+
+        [[rules]]
+        uid = 1001
+        methods = ["GET", "PATCH"]
+        prefixes = ["settings.updates", "settings.kubernetes"]
+    "#;
+
+    fn policy() -> Policy {
+        Policy::parse(POLICY, Path::new("test")).unwrap()
+    }
+
+    #[test]
+    fn group_rule() {
+        let policy = policy();
+        let client = creds(2000, 1000);
+        assert!(policy.allows(&client, "GET", &["settings.kubernetes"]));
+        assert!(policy.allows(&client, "get", &["settings.kubernetes.cluster-name"]));
+        assert!(!policy.allows(&client, "PATCH", &["settings.kubernetes.cluster-name"]));
+        assert!(!policy.allows(&client, "GET", &["settings.updates"]));
+        assert!(!policy.allows(&client, "GET", &["settings.kubernetes-extra"]));
+        assert!(!policy.allows(&client, "GET", &["settings"]));
+        assert!(policy.allows(&client, "GET", &[r#"settings.kubernetes."a.b""#]));
+    }
+
+    #[test]
+    fn every_key_must_be_allowed() {
+        let policy = policy();
+        let client = creds(1001, 1001);
+        assert!(policy.allows(
+            &client,
+            "PATCH",
+            &["settings.updates.seed", "settings.kubernetes.cluster-name"]
+        ));
+        assert!(!policy.allows(
+            &client,
+            "PATCH",
+            &["settings.updates.seed", "settings.hostname"]
+        ));
+    }
+
+    #[test]
+    fn root_and_strangers() {
+        let policy = policy();
+        assert!(policy.allows(&creds(0, 0), "PATCH", &["settings.hostname"]));
+        assert!(!policy.allows(&creds(3000, 3000), "GET", &["settings.kubernetes"]));
+    }
+
+    #[test]
+    fn no_policy_or_no_credentials() {
+        authorize(None, None, "PATCH", "/settings", &["settings.hostname"]).unwrap();
+
+        let policy = policy();
+        authorize(
+            Some(&policy),
+            None,
+            "GET",
+            "/settings",
+            &["settings.kubernetes"],
+        )
+        .unwrap_err();
+        let conn = ConnectionCredentials(Some(creds(2000, 1000)));
+        authorize(
+            Some(&policy),
+            Some(&conn),
+            "GET",
+            "/settings",
+            &["settings.kubernetes"],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rule_needs_client() {
+        let policy = r#"
+            [[rules]]
+            methods = ["GET"]
+            prefixes = ["settings"]
+        "#;
+        Policy::parse(policy, Path::new("test")).unwrap_err();
+    }
+}
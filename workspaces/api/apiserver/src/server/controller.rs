@@ -1,129 +1,1129 @@
-// This section ties together serialization and deserialization of scalar values, so it's in the
-// parent module of serialization and deserialization.
+//! The controller module maps between the datastore and the API interface, similar to the
+//! controller in the MVC model.
 
-/// Concrete error type for scalar ser/de.
-pub type ScalarError = serde_json::Error;
+use serde::de::DeserializeOwned;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::process::{Command, Stdio};
 
-/// Serialize a given scalar value to the module-standard serialization format.
-pub fn serialize_scalar<S, E>(scalar: &S) -> std::result::Result<String, E>
+use crate::datastore::deserialization::{from_map, from_map_with_prefix};
+use crate::datastore::serialization::to_pairs;
+use crate::datastore::{
+    commits_key, deserialize_scalar, fold_list_elements, serialize_scalar, Committed, DataStore,
+    Key, KeyType, Snapshot, Value, COMMIT_PREFIX,
+};
+use crate::model::{ConfigurationFiles, Services, Settings, SECRET_SETTINGS};
+use crate::server::error::{self, Result};
+use crate::server::history::{Change, History, HistoryEntry};
+
+/// Build a Settings based on pending data in the datastore for the given transaction.
+pub(crate) fn get_transaction<D, S>(datastore: &D, transaction: S) -> Result<Settings>
+where
+    D: DataStore,
+    S: Into<String>,
+{
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    get_prefix(datastore, &pending, "settings.", None)
+        .map(|maybe_settings| maybe_settings.unwrap_or_else(Settings::default))
+}
+
+/// Returns the keys with pending changes in the given transaction.
+pub(crate) fn get_transaction_keys<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+) -> Result<HashSet<Key>> {
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    datastore
+        .list_populated_keys("settings.", &pending)
+        .context(error::DataStore {
+            op: "list_populated_keys",
+        })
+}
+
+/// Deletes the transaction from the data store, removing any uncommitted settings under that
+/// transaction name.
+pub(crate) fn delete_transaction<D: DataStore>(
+    datastore: &mut D,
+    transaction: &str,
+) -> Result<HashSet<Key>> {
+    datastore
+        .delete_transaction(transaction)
+        .context(error::DataStore {
+            op: "delete_pending",
+        })
+}
+
+/// Returns the names of any pending transactions in the data store.
+pub(crate) fn list_transactions<D>(datastore: &D) -> Result<HashSet<String>>
+where
+    D: DataStore,
+{
+    datastore.list_transactions().context(error::DataStore {
+        op: "list_transactions",
+    })
+}
+
+/// Build a Settings based on the data in the datastore.  Errors if no settings are found.
+pub(crate) fn get_settings<D: DataStore>(datastore: &D, committed: &Committed) -> Result<Settings> {
+    get_prefix(datastore, committed, "settings.", None)
+        .transpose()
+        // None is not OK here - we always have *some* settings
+        .context(error::MissingData { prefix: "settings" })?
+}
+
+/// Build a Settings based on the data in the datastore that begins with the given prefix.
+pub(crate) fn get_settings_prefix<D: DataStore, S: AsRef<str>>(
+    datastore: &D,
+    prefix: S,
+    committed: &Committed,
+) -> Result<Settings> {
+    let prefix = "settings.".to_string() + prefix.as_ref();
+    get_prefix(datastore, committed, &prefix, None)
+        .map(|maybe_settings| maybe_settings.unwrap_or_else(Settings::default))
+}
+
+/// Build a collection of Service items with the given names using data from the datastore.
+pub(crate) fn get_services_names<'a, D: DataStore>(
+    datastore: &D,
+    names: &'a HashSet<&str>,
+    committed: &Committed,
+) -> Result<Services> {
+    get_map_from_prefix(datastore, "services.".to_string(), names, committed)
+}
+
+/// Build a collection of ConfigurationFile items with the given names using data from the
+/// datastore.
+pub(crate) fn get_configuration_files_names<D: DataStore>(
+    datastore: &D,
+    names: &HashSet<&str>,
+    committed: &Committed,
+) -> Result<ConfigurationFiles> {
+    get_map_from_prefix(
+        datastore,
+        "configuration-files.".to_string(),
+        names,
+        committed,
+    )
+}
+
+/// Build a collection of all Service items using data from the datastore.
+pub(crate) fn get_services<D: DataStore>(datastore: &D) -> Result<Services> {
+    get_prefix(datastore, &Committed::Live, "services.", Some("services"))
+        .transpose()
+        // None is not OK here - we always have services
+        .context(error::MissingData { prefix: "services" })?
+}
+
+/// Build a collection of all ConfigurationFile items using data from the datastore.
+pub(crate) fn get_configuration_files<D: DataStore>(datastore: &D) -> Result<ConfigurationFiles> {
+    get_prefix(
+        datastore,
+        &Committed::Live,
+        "configuration-files.",
+        Some("configuration-files"),
+    )
+    .transpose()
+    // None is not OK here - we always have configuration files
+    .context(error::MissingData {
+        prefix: "configuration-files",
+    })?
+}
+
+/// Helper to get data from the datastore, starting with the given find_prefix, and deserialize
+/// it into the desired type.  map_prefix should be the prefix to remove if you're deserializing
+/// into a map; see docs on from_map_with_prefix.  Returns Err if we couldn't pull expected data;
+/// returns Ok(None) if we found there were no populated keys.
+fn get_prefix<D, T, S>(
+    datastore: &D,
+    committed: &Committed,
+    find_prefix: S,
+    map_prefix: Option<&str>,
+) -> Result<Option<T>>
+where
+    D: DataStore,
+    T: DeserializeOwned,
+    S: AsRef<str>,
+{
+    let find_prefix = find_prefix.as_ref();
+    let data = datastore
+        .get_prefix(find_prefix, committed)
+        .with_context(|| error::DataStore {
+            op: format!("get_prefix '{}' for {:?}", find_prefix, committed),
+        })?;
+    if data.is_empty() {
+        return Ok(None);
+    }
+
+    from_map_with_prefix(map_prefix.map(|s| s.to_string()), &data)
+        .context(error::Deserialization { given: find_prefix })
+}
+
+/// Build a Settings based on the data in the datastore for the given keys.
+pub(crate) fn get_settings_keys<D: DataStore>(
+    datastore: &D,
+    keys: &HashSet<&str>,
+    committed: &Committed,
+) -> Result<Settings> {
+    let mut data = HashMap::new();
+    for key_str in keys {
+        trace!("Pulling value from datastore for key: {}", key_str);
+        let key = Key::new(KeyType::Data, &key_str).context(error::NewKey {
+            key_type: "data",
+            name: *key_str,
+        })?;
+        let value = match datastore
+            .get_key(&key, committed)
+            .context(error::DataStore { op: "get_key" })?
+        {
+            Some(v) => v,
+            // TODO: confirm we want to skip requested keys if not populated, or error
+            None => continue,
+        };
+        data.insert(key, value);
+    }
+
+    let settings = from_map(&data).context(error::Deserialization {
+        given: "given keys",
+    })?;
+    Ok(settings)
+}
+
+/// Helper to get data from the datastore for a collection of requested items under a given
+/// prefix.  For example, a collection of Service items under "services" that have the requested
+/// names.  Returns Err if we couldn't pull expected data, including the case where a name was
+/// specified for which we have no data.
+fn get_map_from_prefix<D: DataStore, T>(
+    datastore: &D,
+    prefix: String,
+    names: &HashSet<&str>,
+    committed: &Committed,
+) -> Result<HashMap<String, T>>
+where
+    T: DeserializeOwned,
+{
+    let mut result = HashMap::new();
+    for &name in names {
+        let item_prefix = prefix.clone() + name;
+
+        let item_data = datastore
+            .get_prefix(&item_prefix, committed)
+            .with_context(|| error::DataStore {
+                op: format!("get_prefix '{}' for {:?}", &item_prefix, committed),
+            })?;
+
+        ensure_found(&item_data, &item_prefix)?;
+
+        let item = from_map_with_prefix(Some(item_prefix.clone()), &item_data)
+            .context(error::Deserialization { given: item_prefix })?;
+        result.insert(name.to_string(), item);
+    }
+
+    Ok(result)
+}
+
+/// Returns a ListKeys error if the given data is empty, i.e. nothing was found for the request.
+fn ensure_found<K, V>(data: &HashMap<K, V>, requested: &str) -> Result<()> {
+    if data.is_empty() {
+        return error::ListKeys { requested }.fail();
+    }
+    Ok(())
+}
+
+/// Given a Settings, takes any Some values and updates them in the datastore under the given
+/// pending transaction.
+pub(crate) fn set_settings<D: DataStore>(
+    datastore: &mut D,
+    settings: &Settings,
+    transaction: &str,
+) -> Result<()> {
+    trace!("Serializing Settings to write to data store");
+    let pairs = to_pairs(settings).context(error::DataStoreSerialization { given: "Settings" })?;
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    datastore
+        .set_keys(&pairs, &pending)
+        .context(error::DataStore { op: "set_keys" })
+}
+
+// This is not as nice as get_settings, which uses Serializer/Deserializer to properly use the
+// data model and check types.
+/// Gets the value of a metadata key for the requested list of data keys, live or in a pending
+/// transaction.
+pub(crate) fn get_metadata_for_data_keys<D: DataStore, S: AsRef<str>>(
+    datastore: &D,
+    md_key_str: S,
+    data_key_strs: &HashSet<&str>,
+    committed: &Committed,
+) -> Result<HashMap<String, Value>> {
+    trace!("Getting metadata '{}'", md_key_str.as_ref());
+    let md_key = Key::new(KeyType::Meta, md_key_str.as_ref()).context(error::NewKey {
+        key_type: "meta",
+        name: md_key_str.as_ref(),
+    })?;
+
+    let mut result = HashMap::new();
+    for data_key_str in data_key_strs {
+        trace!("Pulling metadata from datastore for key: {}", data_key_str);
+        let data_key = Key::new(KeyType::Data, data_key_str).context(error::NewKey {
+            key_type: "data",
+            name: *data_key_str,
+        })?;
+        let value_str = match datastore.get_metadata(&md_key, &data_key, committed) {
+            Ok(Some(v)) => v,
+            // TODO: confirm we want to skip requested keys if not populated, or error
+            Ok(None) => continue,
+            Err(e) => return Err(e).context(error::DataStore { op: "get_metadata" }),
+        };
+        trace!("Deserializing scalar from metadata");
+        let value: Value = deserialize_scalar::<_, crate::datastore::ScalarError>(&value_str)
+            .context(error::InvalidMetadata {
+                key: md_key.as_ref(),
+            })?;
+        result.insert(data_key.to_string(), value);
+    }
+
+    Ok(result)
+}
+
+/// Gets the value of a metadata key for all data keys in the datastore, live or in a pending
+/// transaction.
+pub(crate) fn get_metadata_for_all_data_keys<D: DataStore, S: AsRef<str>>(
+    datastore: &D,
+    md_key_str: S,
+    committed: &Committed,
+) -> Result<HashMap<String, Value>> {
+    trace!("Getting metadata '{}'", md_key_str.as_ref());
+    let md_key_name = Some(md_key_str.as_ref());
+
+    // Like get_metadata, a transaction sees live metadata for keys it hasn't staged metadata
+    // for, so we start with the live metadata and let the transaction's replace it.
+    let mut meta_map = HashMap::new();
+    if *committed != Committed::Live {
+        meta_map = datastore
+            .get_metadata_prefix("", &md_key_name, &Committed::Live)
+            .context(error::DataStore {
+                op: "get_metadata_prefix",
+            })?;
+    }
+    meta_map.extend(
+        datastore
+            .get_metadata_prefix("", &md_key_name, committed)
+            .context(error::DataStore {
+                op: "get_metadata_prefix",
+            })?,
+    );
+
+    let mut result = HashMap::new();
+    for (data_key, meta_map) in meta_map.into_iter() {
+        for (meta_key, value_str) in meta_map.into_iter() {
+            trace!("Deserializing scalar from metadata");
+            let value: Value = deserialize_scalar::<_, crate::datastore::ScalarError>(&value_str)
+                .context(error::InvalidMetadata {
+                    key: meta_key.as_ref(),
+                })?;
+            result.insert(data_key.to_string(), value);
+        }
+    }
+    Ok(result)
+}
+
+/// Makes live any changes made in the given transaction, and records their old and new values in
+/// the settings history.  Returns the list of changed keys.
+///
+/// Recording history is best-effort; once the data store commit succeeds, the changes are live
+/// and have to be applied, so a failure to record them is logged rather than returned.
+pub(crate) fn commit_transaction<D>(
+    datastore: &mut D,
+    history: &mut History,
+    transaction: &str,
+) -> Result<HashSet<Key>>
 where
-    S: Serialize,
-    E: From<ScalarError>,
+    D: DataStore,
 {
-    serde_json::to_string(scalar).map_err(Into::into)
+    // Gather old and new values before the commit so we can record them afterward.  List
+    // elements are committed as part of their list, so we fold them in first, as the commit
+    // will, and record the whole list; folding again in the commit doesn't change anything.
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    fold_list_elements(datastore, transaction).context(error::DataStore {
+        op: "fold list elements",
+    })?;
+    let mut pending_data = datastore
+        .get_prefix(COMMIT_PREFIX, &pending)
+        .context(error::DataStore { op: "get_prefix" })?;
+    pending_data.retain(|key, _| commits_key(key));
+    let mut changes = HashMap::new();
+    for (key, new_str) in pending_data {
+        let old = match datastore
+            .get_key(&key, &Committed::Live)
+            .context(error::DataStore { op: "get_key" })?
+        {
+            Some(old_str) => Some(history_value(&key, &old_str)?),
+            None => None,
+        };
+        let new = history_value(&key, &new_str)?;
+        changes.insert(key.to_string(), Change { old, new });
+    }
+
+    let changed = datastore
+        .commit_transaction(transaction)
+        .context(error::DataStore { op: "commit" })?;
+
+    if !changed.is_empty() {
+        match history.record(changes) {
+            Ok(id) => debug!("Recorded commit {} in settings history", id),
+            Err(e) => error!("Unable to record commit in settings history: {}", e),
+        }
+    }
+    Ok(changed)
 }
 
-/// Deserialize a given scalar value from the module-standard serialization format.
-pub fn deserialize_scalar<'de, D, E>(scalar: &'de str) -> std::result::Result<D, E>
+/// Deserializes a datastore value for the settings history.
+fn history_value(key: &Key, value_str: &str) -> Result<Value> {
+    deserialize_scalar::<_, crate::datastore::ScalarError>(value_str)
+        .context(error::HistoryValue { key: key.as_ref() })
+}
+
+/// Stages changes in the given transaction that return any settings changed since the given
+/// commit to their values as of that commit.  Returns the list of staged keys.
+///
+/// The datastore has no way to unset a key, so if any of the changed settings weren't set as of
+/// the given commit, we can't return to that commit; we fail without staging anything.
+pub(crate) fn rollback_settings<D>(
+    datastore: &mut D,
+    history: &History,
+    to: u64,
+    transaction: &str,
+) -> Result<HashSet<Key>>
 where
-    D: Deserialize<'de>,
-    E: From<ScalarError>,
+    D: DataStore,
 {
-    serde_json::from_str(scalar).map_err(Into::into)
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+
+    let changes = history.rollback_to(to)?;
+    let mut unset: Vec<&str> = changes
+        .iter()
+        .filter(|(_, old)| old.is_none())
+        .map(|(key_str, _)| key_str.as_str())
+        .collect();
+    if !unset.is_empty() {
+        unset.sort();
+        return error::RollbackUnset {
+            id: to,
+            keys: unset.join(", "),
+        }
+        .fail();
+    }
+
+    let mut staged = HashSet::new();
+    for (key_str, old) in changes {
+        let key = Key::new(KeyType::Data, &key_str).context(error::NewKey {
+            key_type: "data",
+            name: &key_str,
+        })?;
+        // We checked above that every key has an old value.
+        let old = match old {
+            Some(old) => old,
+            None => continue,
+        };
+
+        let value = serialize_scalar::<_, crate::datastore::ScalarError>(&old)
+            .context(error::HistoryValue { key: key.as_ref() })?;
+        datastore
+            .set_key(&key, value, &pending)
+            .context(error::DataStore { op: "set_key" })?;
+        staged.insert(key);
+    }
+
+    Ok(staged)
+}
+
+/// The placeholder shown instead of the values of secret settings.
+const MASKED_VALUE: &str = "********";
+
+/// Returns whether the given data key is a secret setting, or falls under one.
+fn is_secret(key: &str) -> bool {
+    let key = match Key::new(KeyType::Data, key) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    key.ancestors().into_iter().any(|ancestor| {
+        SECRET_SETTINGS
+            .iter()
+            .any(|secret| matches_secret(ancestor, secret))
+    })
+}
+
+/// Returns whether the given key name is the given secret setting, where a "*" segment of the
+/// secret matches any one segment of the key.
+fn matches_secret(name: &str, secret: &str) -> bool {
+    let key = match Key::new(KeyType::Data, name) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    let segments = key.segments();
+    let secret_segments: Vec<&str> = secret.split('.').collect();
+    segments.len() == secret_segments.len()
+        && segments
+            .iter()
+            .zip(secret_segments)
+            .all(|(segment, secret)| secret == "*" || *segment == secret)
+}
+
+/// Serializes the given settings, replacing the values of any secret settings with a placeholder.
+pub(crate) fn mask_settings(settings: &Settings) -> Result<Value> {
+    let mut value = serde_json::to_value(settings).context(error::ResponseSerialization)?;
+    // Serialized settings don't include the "settings" prefix.
+    mask_secrets(&mut value, 1);
+    Ok(value)
+}
+
+/// Replaces the values of secret settings in the live and pending data of the given snapshot.
+pub(crate) fn mask_snapshot(snapshot: &mut Snapshot) {
+    mask_secrets(&mut snapshot.live, 0);
+    for data in snapshot.pending.values_mut() {
+        mask_secrets(data, 0);
+    }
+}
+
+/// Replaces the diffs in a report from preview_changes with the placeholder.  Rendered files can
+/// hold secret settings, and a diff doesn't say which lines came from them, so the whole diff is
+/// hidden; the paths of changed files and the restart commands are kept.
+pub(crate) fn mask_preview(report: &mut Value) {
+    if let Some(files) = report.get_mut("files").and_then(Value::as_array_mut) {
+        for diff in files.iter_mut().filter_map(|file| file.get_mut("diff")) {
+            mask_value(diff);
+        }
+    }
+}
+
+/// Replaces the values of secret settings in the given value, which is nested by key segment.
+/// The value may leave out leading segments of the setting names; `skip` says how many.
+fn mask_secrets(value: &mut Value, skip: usize) {
+    for secret in SECRET_SETTINGS {
+        // Secret settings are named with plain segments, so we can split them on dots.
+        let segments: Vec<&str> = secret.split('.').skip(skip).collect();
+        mask_path(value, &segments);
+    }
+}
+
+/// Masks whatever is at the given path of segments under the given value, if anything; a "*"
+/// segment matches every child.
+fn mask_path(value: &mut Value, segments: &[&str]) {
+    match segments.split_first() {
+        None => mask_value(value),
+        Some((&"*", rest)) => {
+            if let Value::Object(map) = value {
+                map.values_mut().for_each(|child| mask_path(child, rest));
+            }
+        }
+        Some((segment, rest)) => {
+            if let Some(child) = value.get_mut(*segment) {
+                mask_path(child, rest);
+            }
+        }
+    }
+}
+
+/// Replaces the old and new values of secret settings in the given history entries.
+pub(crate) fn mask_history(entries: &mut [HistoryEntry]) {
+    for entry in entries {
+        for (_, change) in entry.changes.iter_mut().filter(|(key, _)| is_secret(key)) {
+            if let Some(old) = change.old.as_mut() {
+                mask_value(old);
+            }
+            mask_value(&mut change.new);
+        }
+    }
+}
+
+/// Replaces every scalar in the given value with the placeholder.
+fn mask_value(value: &mut Value) {
+    match value {
+        Value::Object(map) => map.values_mut().for_each(mask_value),
+        Value::Array(list) => list.iter_mut().for_each(mask_value),
+        _ => *value = Value::String(MASKED_VALUE.to_string()),
+    }
 }
 
-/// Serde Deserializer type matching the deserialize_scalar implementation.
-type ScalarDeserializer<'de> = serde_json::Deserializer<serde_json::de::StrRead<'de>>;
+/// Launches the config applier to make appropriate changes to the system based on any settings
+/// that have changed.  Can be called after a commit, with the keys that changed in that commit,
+/// or called on its own to reset configuration state with all known keys.
+///
+/// If `keys_limit` is Some, gives those keys to the applier so only changes relevant to those
+/// keys are made.  Otherwise, tells the applier to apply changes for all known keys.
+pub(crate) fn apply_changes<S>(keys_limit: Option<&HashSet<S>>) -> Result<()>
+where
+    S: AsRef<str>,
+{
+    if let Some(keys_limit) = keys_limit {
+        let keys_limit: Vec<&str> = keys_limit.iter().map(|s| s.as_ref()).collect();
+        // Prepare input to config applier; it uses the changed keys to update the right config
+        trace!("Serializing the commit's changed keys: {:?}", keys_limit);
+        let cmd_input =
+            serde_json::to_string(&keys_limit).context(error::CommandSerialization {
+                given: "commit's changed keys",
+            })?;
+
+        // Start config applier
+        debug!("Launching thar-be-settings to apply changes");
+        let mut cmd = Command::new("/usr/bin/thar-be-settings")
+            .stdin(Stdio::piped())
+            // FIXME where to send output?
+            //.stdout()
+            //.stderr()
+            .spawn()
+            .context(error::ConfigApplierStart)?;
+
+        // Send changed keys to config applier
+        trace!("Sending changed keys");
+        cmd.stdin
+            .as_mut()
+            .context(error::ConfigApplierStdin)?
+            .write_all(cmd_input.as_bytes())
+            .context(error::ConfigApplierWrite)?;
+    } else {
+        // Start config applier
+        debug!("Launching thar-be-settings to apply all changes");
+        Command::new("/usr/bin/thar-be-settings")
+            .arg("--all")
+            .spawn()
+            .context(error::ConfigApplierStart)?;
+    }
 
-/// Constructor for ScalarDeserializer.
-fn deserializer_for_scalar(scalar: &str) -> ScalarDeserializer {
-    serde_json::Deserializer::from_str(scalar)
+    // Leave config applier to run in the background; we can't wait for it
+    Ok(())
 }
 
-/// Serde generic "Value" type representing a tree of deserialized values.  Should be able to hold
-/// anything returned by the deserialization bits above.
-pub type Value = serde_json::Value;
+/// Runs the config applier in dry-run mode for the given transaction's changed keys, and returns
+/// its report of the changes it would make to configuration files and the restart commands it
+/// would run.  Nothing is committed, written, or restarted.
+pub(crate) fn preview_changes(keys: &HashSet<Key>, transaction: &str) -> Result<serde_json::Value> {
+    let keys: Vec<&str> = keys.iter().map(|k| k.as_ref()).collect();
+    trace!("Serializing the transaction's changed keys: {:?}", keys);
+    let cmd_input = serde_json::to_string(&keys).context(error::CommandSerialization {
+        given: "transaction's changed keys",
+    })?;
+
+    debug!("Launching thar-be-settings to preview changes");
+    let mut cmd = Command::new("/usr/bin/thar-be-settings")
+        .args(&["--dry-run", "--tx", transaction])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(error::ConfigApplierStart)?;
+
+    trace!("Sending changed keys");
+    cmd.stdin
+        .as_mut()
+        .context(error::ConfigApplierStdin)?
+        .write_all(cmd_input.as_bytes())
+        .context(error::ConfigApplierWrite)?;
+
+    // This closes stdin so the applier knows it has all the keys.
+    let output = cmd.wait_with_output().context(error::ConfigApplierWait)?;
+    ensure!(
+        output.status.success(),
+        error::ConfigApplierFailed {
+            stderr: String::from_utf8_lossy(&output.stderr),
+        }
+    );
+    serde_json::from_slice(&output.stdout).context(error::ConfigApplierOutput)
+}
 
 #[cfg(test)]
 mod test {
-    use super::memory::MemoryDataStore;
-    use super::{Committed, DataStore, Key, KeyType};
-    use maplit::hashmap;
+    use super::*;
+    use crate::datastore::memory::MemoryDataStore;
+    use crate::datastore::{Committed, DataStore, Key, KeyType};
+    use crate::model::Service;
+    use crate::modeled_types::{ValidHostname, ValidTimezone};
+    use maplit::{btreemap, hashmap, hashset};
+    use serde_json::json;
+    use std::convert::TryFrom;
+    use tempfile::TempDir;
+
+    #[test]
+    fn get_settings_works() {
+        let mut ds = MemoryDataStore::new();
+        // Set directly with data store
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.hostname").unwrap(),
+            "\"json-string\"",
+            &Committed::Live,
+        )
+        .unwrap();
+
+        // Retrieve with helper
+        let settings = get_settings(&ds, &Committed::Live).unwrap();
+        assert_eq!(settings.hostname.as_deref(), Some("json-string"));
+    }
+
+    #[test]
+    fn get_settings_prefix_works() {
+        let mut ds = MemoryDataStore::new();
+        // Set directly with data store
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.hostname").unwrap(),
+            "\"json-string\"",
+            &Committed::Live,
+        )
+        .unwrap();
+
+        // Retrieve with helper
+        let settings = get_settings_prefix(&ds, "", &Committed::Live).unwrap();
+        assert_eq!(settings.hostname.as_deref(), Some("json-string"));
+
+        let settings = get_settings_prefix(&ds, "host", &Committed::Live).unwrap();
+        assert_eq!(settings.hostname.as_deref(), Some("json-string"));
+
+        let settings = get_settings_prefix(&ds, "x", &Committed::Live).unwrap();
+        assert_eq!(settings.hostname, None);
+    }
+
+    #[test]
+    fn get_settings_keys_works() {
+        let mut ds = MemoryDataStore::new();
+        // Set directly with data store
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.timezone").unwrap(),
+            "\"America/Los_Angeles\"",
+            &Committed::Live,
+        )
+        .unwrap();
+
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.hostname").unwrap(),
+            "\"json-string-2\"",
+            &Committed::Live,
+        )
+        .unwrap();
+
+        // Retrieve with helper
+        let settings =
+            get_settings_keys(&ds, &hashset!("settings.timezone"), &Committed::Live).unwrap();
+        assert_eq!(settings.timezone.as_deref(), Some("America/Los_Angeles"));
+        assert_eq!(settings.hostname, None);
+    }
 
     #[test]
-    fn set_keys() {
-        let mut m = MemoryDataStore::new();
+    fn get_services_names_works() {
+        let mut ds = MemoryDataStore::new();
+        // Set directly with data store
+        ds.set_key(
+            &Key::new(KeyType::Data, "services.foo.configuration-files").unwrap(),
+            "[\"file1\"]",
+            &Committed::Live,
+        )
+        .unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "services.foo.restart-commands").unwrap(),
+            "[\"echo hi\"]",
+            &Committed::Live,
+        )
+        .unwrap();
 
-        let k1 = Key::new(KeyType::Data, "memtest1").unwrap();
-        let k2 = Key::new(KeyType::Data, "memtest2").unwrap();
-        let v1 = "memvalue1".to_string();
-        let v2 = "memvalue2".to_string();
-        let data = hashmap!(
-            &k1 => &v1,
-            &k2 => &v2,
+        // Retrieve built service
+        let names = hashset!("foo");
+        let services = get_services_names(&ds, &names, &Committed::Live).unwrap();
+        assert_eq!(
+            services,
+            hashmap!("foo".to_string() => Service {
+                configuration_files: vec!["file1".to_string()],
+                restart_commands: vec!["echo hi".to_string()],
+            })
         );
+    }
+
+    #[test]
+    fn set_settings_works() {
+        let mut settings = Settings::default();
+        settings.timezone = Some(ValidTimezone::try_from("UTC").unwrap());
 
-        m.set_keys(&data, Committed::Pending).unwrap();
+        // Set with helper
+        let mut ds = MemoryDataStore::new();
+        let tx = "test-transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        set_settings(&mut ds, &settings, tx).unwrap();
 
-        assert_eq!(m.get_key(&k1, Committed::Pending).unwrap(), Some(v1));
-        assert_eq!(m.get_key(&k2, Committed::Pending).unwrap(), Some(v2));
+        // Retrieve directly
+        let key = Key::new(KeyType::Data, "settings.timezone").unwrap();
+        assert_eq!(Some("\"UTC\"".to_string()), ds.get_key(&key, &pending).unwrap());
     }
 
     #[test]
-    fn get_metadata_inheritance() {
-        let mut m = MemoryDataStore::new();
+    fn kubernetes_maps_round_trip() {
+        let settings: Settings = serde_json::from_str(
+            r#"{"kubernetes": {
+                "node-labels": {"node.example.com/role": "worker"},
+                "node-taints": {"dedicated": "gpu:NoSchedule"},
+                "eviction-hard": {"memory.available": "10%"},
+                "feature-gates": {"CSIMigration": false},
+                "max-pods": 58
+            }}"#,
+        )
+        .unwrap();
+        let mut ds = MemoryDataStore::new();
+        set_settings(&mut ds, &settings, "tx").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+
+        // Map keys that aren't valid key segments are quoted
+        let key = Key::new(
+            KeyType::Data,
+            r#"settings.kubernetes.eviction-hard."memory.available""#,
+        )
+        .unwrap();
+        assert_eq!(Some("\"10%\"".to_string()), ds.get_key(&key, &pending).unwrap());
+
+        assert_eq!(get_settings(&ds, &pending).unwrap(), settings);
+    }
+
+    #[test]
+    fn secret_settings_are_masked() {
+        let settings: Settings = serde_json::from_str(
+            r#"{"hostname": "abc", "container-registry": {
+                "mirrors": {"docker.io": ["https://mirror.local"]},
+                "credentials": {"registry.local:5000": {"auth": "dXNlcjpwYXNz"}}
+            }}"#,
+        )
+        .unwrap();
+        let masked = mask_settings(&settings).unwrap();
+        assert_eq!(masked["hostname"], "abc");
+        assert_eq!(masked["container-registry"]["mirrors"]["docker.io"][0], "https://mirror.local");
+        assert_eq!(
+            masked["container-registry"]["credentials"]["registry.local:5000"]["auth"],
+            MASKED_VALUE
+        );
+
+        // Host containers' environment and user data are masked, whatever the container's name
+        let settings: Settings = serde_json::from_str(
+            r#"{"host-containers": {"admin": {
+                "enabled": true,
+                "user-data": "dXNlcjpwYXNz",
+                "environment": {"TOKEN": "secret"}
+            }}}"#,
+        )
+        .unwrap();
+        let masked = mask_settings(&settings).unwrap();
+        let admin = &masked["host-containers"]["admin"];
+        assert_eq!(admin["enabled"], true);
+        assert_eq!(admin["user-data"], MASKED_VALUE);
+        assert_eq!(admin["environment"]["TOKEN"], MASKED_VALUE);
+
+        // Settings without secrets serialize as usual
+        let settings: Settings = serde_json::from_str(r#"{"hostname": "abc"}"#).unwrap();
+        assert_eq!(mask_settings(&settings).unwrap(), serde_json::to_value(&settings).unwrap());
+    }
+
+    #[test]
+    fn secret_snapshot_is_masked() {
+        let mut snapshot = Snapshot {
+            version: data_store_version::Version::new(1, 0),
+            live: json!({"settings": {"hostname": "abc", "kubernetes": {"bootstrap-token": "t"}}}),
+            pending: btreemap!(
+                "tx".to_string() => json!({"settings": {"container-registry": {
+                    "credentials": {"registry.local:5000": {"auth": "dXNlcjpwYXNz"}}
+                }}}),
+            ),
+            metadata: Default::default(),
+            pending_metadata: Default::default(),
+        };
+        mask_snapshot(&mut snapshot);
+        assert_eq!(snapshot.live["settings"]["hostname"], "abc");
+        assert_eq!(snapshot.live["settings"]["kubernetes"]["bootstrap-token"], MASKED_VALUE);
+        assert_eq!(
+            snapshot.pending["tx"]["settings"]["container-registry"]["credentials"]
+                ["registry.local:5000"]["auth"],
+            MASKED_VALUE
+        );
+    }
 
-        let meta = Key::new(KeyType::Meta, "mymeta").unwrap();
-        let parent = Key::new(KeyType::Data, "a").unwrap();
-        let grandchild = Key::new(KeyType::Data, "a.b.c").unwrap();
+    #[test]
+    fn preview_diffs_are_masked() {
+        let mut report = json!({
+            "files": [{"path": "/etc/containerd/config.toml", "diff": "+auth = \"secret\""}],
+            "restart-commands": ["/bin/systemctl restart containerd"],
+        });
+        mask_preview(&mut report);
+        assert_eq!(report["files"][0]["path"], "/etc/containerd/config.toml");
+        assert_eq!(report["files"][0]["diff"], MASKED_VALUE);
+        assert_eq!(report["restart-commands"], json!(["/bin/systemctl restart containerd"]));
+    }
 
-        // Set metadata on parent
-        m.set_metadata(&meta, &parent, "value").unwrap();
-        // Metadata shows up on grandchild...
+    #[test]
+    fn secret_history_is_masked() {
+        let secret = r#"settings.container-registry.credentials."registry.local:5000".auth"#;
+        let mut entries = vec![HistoryEntry {
+            id: 1,
+            timestamp: chrono::Utc::now(),
+            changes: hashmap!(
+                secret.to_string() => Change { old: Some("b2xk".into()), new: "bmV3".into() },
+                "settings.hostname".to_string() => Change { old: None, new: "abc".into() },
+                "settings.host-containers.admin.environment.TOKEN".to_string() =>
+                    Change { old: None, new: "secret".into() },
+                "settings.host-containers.admin.enabled".to_string() =>
+                    Change { old: None, new: true.into() },
+            ),
+        }];
+        mask_history(&mut entries);
+        let changes = &entries[0].changes;
+        assert_eq!(
+            changes[secret],
+            Change {
+                old: Some(MASKED_VALUE.into()),
+                new: MASKED_VALUE.into(),
+            }
+        );
+        assert_eq!(changes["settings.hostname"].new, "abc");
         assert_eq!(
-            m.get_metadata(&meta, &grandchild).unwrap(),
-            Some("value".to_string())
+            changes["settings.host-containers.admin.environment.TOKEN"].new,
+            MASKED_VALUE
+        );
+        assert_eq!(changes["settings.host-containers.admin.enabled"].new, true);
+    }
+
+    #[test]
+    fn get_metadata_keys_works() {
+        let mut ds = MemoryDataStore::new();
+        for data_key in &["abc", "def"] {
+            ds.set_metadata(
+                &Key::new(KeyType::Meta, "my-meta").unwrap(),
+                &Key::new(KeyType::Data, data_key).unwrap(),
+                "\"json string\"",
+                &Committed::Live,
+            )
+            .unwrap();
+        }
+
+        let expected = hashmap!(
+            "abc".to_string() => "json string".into(),
         );
-        // ...but only through inheritance, not directly.
-        assert_eq!(m.get_metadata_raw(&meta, &grandchild).unwrap(), None);
+        let actual =
+            get_metadata_for_data_keys(&ds, "my-meta", &hashset!("abc"), &Committed::Live).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn get_metadata_all_works() {
+        let mut ds = MemoryDataStore::new();
+        for data_key in &["abc", "def"] {
+            ds.set_metadata(
+                &Key::new(KeyType::Meta, "my-meta").unwrap(),
+                &Key::new(KeyType::Data, data_key).unwrap(),
+                "\"json string\"",
+                &Committed::Live,
+            )
+            .unwrap();
+        }
+
+        let expected = hashmap!(
+            "abc".to_string() => "json string".into(),
+            "def".to_string() => "json string".into(),
+        );
+        let actual = get_metadata_for_all_data_keys(&ds, "my-meta", &Committed::Live).unwrap();
+        assert_eq!(expected, actual);
+
+        // A transaction sees its own metadata where it has some, and live metadata elsewhere
+        let pending = Committed::Pending { tx: "tx".into() };
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "my-meta").unwrap(),
+            &Key::new(KeyType::Data, "def").unwrap(),
+            "\"pending string\"",
+            &pending,
+        )
+        .unwrap();
+        let expected = hashmap!(
+            "abc".to_string() => "json string".into(),
+            "def".to_string() => "pending string".into(),
+        );
+        let actual = get_metadata_for_all_data_keys(&ds, "my-meta", &pending).unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn commit_works() {
+        // Set directly with data store
+        let mut ds = MemoryDataStore::new();
+        let tx = "test-transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.hostname").unwrap(),
+            "\"json-string\"",
+            &pending,
+        )
+        .unwrap();
+
+        // Confirm pending
+        let settings = get_settings(&ds, &pending).unwrap();
+        assert_eq!(settings.hostname.as_deref(), Some("json-string"));
+        // No live settings yet
+        get_settings(&ds, &Committed::Live).unwrap_err();
+
+        // Commit, pending -> live
+        let dir = TempDir::new().unwrap();
+        let mut history = History::load(dir.path().join("history")).unwrap();
+        commit_transaction(&mut ds, &mut history, tx).unwrap();
+
+        // No more pending settings
+        get_settings(&ds, &pending).unwrap_err();
+        // Confirm live
+        let settings = get_settings(&ds, &Committed::Live).unwrap();
+        assert_eq!(settings.hostname.as_deref(), Some("json-string"));
     }
 
     #[test]
-    fn get_prefix() {
-        let mut m = MemoryDataStore::new();
-        let data = hashmap!(
-            Key::new(KeyType::Data, "x.1").unwrap() => "x1".to_string(),
-            Key::new(KeyType::Data, "x.2").unwrap() => "x2".to_string(),
-            Key::new(KeyType::Data, "y.3").unwrap() => "y3".to_string(),
+    fn transactions_are_isolated() {
+        let mut ds = MemoryDataStore::new();
+        let mut settings = Settings::default();
+        settings.hostname = Some(ValidHostname::try_from("from-tx1").unwrap());
+        set_settings(&mut ds, &settings, "tx1").unwrap();
+        let mut settings = Settings::default();
+        settings.timezone = Some(ValidTimezone::try_from("Europe/Berlin").unwrap());
+        set_settings(&mut ds, &settings, "tx2").unwrap();
+
+        assert_eq!(
+            list_transactions(&ds).unwrap(),
+            hashset!("tx1".to_string(), "tx2".to_string())
+        );
+        assert_eq!(
+            get_transaction_keys(&ds, "tx2").unwrap(),
+            hashset!(Key::new(KeyType::Data, "settings.timezone").unwrap())
         );
-        m.set_keys(&data, Committed::Pending).unwrap();
 
+        // Committing tx1 doesn't pick up tx2's half-written changes
+        let dir = TempDir::new().unwrap();
+        let mut history = History::load(dir.path().join("history")).unwrap();
+        let changed = commit_transaction(&mut ds, &mut history, "tx1").unwrap();
         assert_eq!(
-            m.get_prefix("x.", Committed::Pending).unwrap(),
-            hashmap!(Key::new(KeyType::Data, "x.1").unwrap() => "x1".to_string(),
-                     Key::new(KeyType::Data, "x.2").unwrap() => "x2".to_string())
+            changed,
+            hashset!(Key::new(KeyType::Data, "settings.hostname").unwrap())
         );
+        let live = get_settings(&ds, &Committed::Live).unwrap();
+        assert_eq!(live.hostname.as_deref(), Some("from-tx1"));
+        assert_eq!(live.timezone, None);
+
+        // tx2 is still pending, and can be dropped without touching live
+        let pending = get_transaction(&ds, "tx2").unwrap();
+        assert_eq!(pending.timezone.as_deref(), Some("Europe/Berlin"));
+        delete_transaction(&mut ds, "tx2").unwrap();
+        assert_eq!(get_transaction(&ds, "tx2").unwrap(), Settings::default());
+        assert!(list_transactions(&ds).unwrap().is_empty());
     }
 
     #[test]
-    fn get_metadata_prefix() {
-        let mut m = MemoryDataStore::new();
+    fn history_and_rollback_work() {
+        let mut ds = MemoryDataStore::new();
+        let dir = TempDir::new().unwrap();
+        let mut history = History::load(dir.path().join("history")).unwrap();
 
-        // Build some data keys to which we can attach metadata; they don't actually have to be
-        // set in the data store.
-        let k1 = Key::new(KeyType::Data, "x.1").unwrap();
-        let k2 = Key::new(KeyType::Data, "x.2").unwrap();
-        let k3 = Key::new(KeyType::Data, "y.3").unwrap();
+        let mut settings = Settings::default();
+        settings.hostname = Some(ValidHostname::try_from("first").unwrap());
+        set_settings(&mut ds, &settings, "default").unwrap();
+        commit_transaction(&mut ds, &mut history, "default").unwrap();
 
-        // Set some metadata to check
-        let mk1 = Key::new(KeyType::Meta, "metatest1").unwrap();
-        let mk2 = Key::new(KeyType::Meta, "metatest2").unwrap();
-        let mk3 = Key::new(KeyType::Meta, "metatest3").unwrap();
-        m.set_metadata(&mk1, &k1, "41").unwrap();
-        m.set_metadata(&mk2, &k2, "42").unwrap();
-        m.set_metadata(&mk3, &k3, "43").unwrap();
+        settings.hostname = Some(ValidHostname::try_from("second").unwrap());
+        settings.timezone = Some(ValidTimezone::try_from("UTC").unwrap());
+        set_settings(&mut ds, &settings, "default").unwrap();
+        commit_transaction(&mut ds, &mut history, "default").unwrap();
 
-        // Check all metadata
+        // The second commit records the old and new values
+        let entry = history.entries()[1].clone();
         assert_eq!(
-            m.get_metadata_prefix("x.", &None as &Option<&str>).unwrap(),
-            hashmap!(k1 => hashmap!(mk1 => "41".to_string()),
-                     k2.clone() => hashmap!(mk2.clone() => "42".to_string()))
+            entry.changes,
+            hashmap!(
+                "settings.hostname".to_string() => Change {
+                    old: Some("first".into()),
+                    new: "second".into(),
+                },
+                "settings.timezone".to_string() => Change { old: None, new: "UTC".into() },
+            )
         );
 
-        // Check metadata matching a given name
+        // Timezone wasn't set as of the first commit, and we can't unset it, so we can't roll
+        // back to the first commit; nothing is staged
+        rollback_settings(&mut ds, &history, entry.id - 1, "rollback").unwrap_err();
+        assert_eq!(get_transaction(&ds, "rollback").unwrap(), Settings::default());
+
+        // Rolling back to the second commit stages the old hostname
+        settings.hostname = Some(ValidHostname::try_from("third").unwrap());
+        settings.timezone = None;
+        set_settings(&mut ds, &settings, "default").unwrap();
+        commit_transaction(&mut ds, &mut history, "default").unwrap();
+        let staged = rollback_settings(&mut ds, &history, entry.id, "rollback").unwrap();
         assert_eq!(
-            m.get_metadata_prefix("x.", &Some("metatest2")).unwrap(),
-            hashmap!(k2 => hashmap!(mk2 => "42".to_string()))
+            staged,
+            hashset!(Key::new(KeyType::Data, "settings.hostname").unwrap())
         );
+        let pending = get_transaction(&ds, "rollback").unwrap();
+        assert_eq!(pending.hostname.as_deref(), Some("second"));
+        assert_eq!(pending.timezone, None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rollback_past_list_element() {
+        let mut ds = MemoryDataStore::new();
+        let dir = TempDir::new().unwrap();
+        let mut history = History::load(dir.path().join("history")).unwrap();
+        let list = Key::new(KeyType::Data, "settings.ntp.time-servers").unwrap();
+        let element = Key::new(KeyType::Data, "settings.ntp.time-servers[1]").unwrap();
+        let default = Committed::Pending {
+            tx: "default".into(),
+        };
+
+        ds.set_key(&list, r#"["a.example.com", "b.example.com"]"#, &default)
+            .unwrap();
+        commit_transaction(&mut ds, &mut history, "default").unwrap();
+        ds.set_key(&element, r#""c.example.com""#, &default)
+            .unwrap();
+        commit_transaction(&mut ds, &mut history, "default").unwrap();
+
+        // The element's commit is recorded as a change to the whole list
+        let entry = history.entries()[1].clone();
+        assert_eq!(
+            entry.changes,
+            hashmap!(
+                "settings.ntp.time-servers".to_string() => Change {
+                    old: Some(json!(["a.example.com", "b.example.com"])),
+                    new: json!(["a.example.com", "c.example.com"]),
+                },
+            )
+        );
+
+        // So rolling back past it restores the list
+        let staged = rollback_settings(&mut ds, &history, entry.id - 1, "rollback").unwrap();
+        assert_eq!(staged, hashset!(list));
+        let pending = get_transaction(&ds, "rollback").unwrap();
+        let servers = pending.ntp.unwrap().time_servers.unwrap();
+        let servers: Vec<&str> = servers.iter().map(|server| server.as_ref()).collect();
+        assert_eq!(servers, vec!["a.example.com", "b.example.com"]);
+    }
+
+    #[test]
+    fn history_failure_doesnt_fail_commit() {
+        let mut ds = MemoryDataStore::new();
+        // The journal can't be written under a path that doesn't exist
+        let dir = TempDir::new().unwrap();
+        let mut history = History::load(dir.path().join("missing/history")).unwrap();
+
+        let mut settings = Settings::default();
+        settings.hostname = Some(ValidHostname::try_from("committed").unwrap());
+        set_settings(&mut ds, &settings, "default").unwrap();
+        let changed = commit_transaction(&mut ds, &mut history, "default").unwrap();
+        assert_eq!(
+            changed,
+            hashset!(Key::new(KeyType::Data, "settings.hostname").unwrap())
+        );
+        let live = get_settings(&ds, &Committed::Live).unwrap();
+        assert_eq!(live.hostname.as_deref(), Some("committed"));
+    }
+}
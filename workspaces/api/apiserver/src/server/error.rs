@@ -1,4 +1,5 @@
 use crate::datastore::{self, deserialization, serialization};
+use data_store_version::Version;
 use std::io;
 use std::path::PathBuf;
 use snafu::Snafu;
@@ -20,6 +21,13 @@ pub enum Error {
 
     // Server errors
 
+    #[snafu(display("Invalid settings at '{}': {}", path, source))]
+    InvalidSettings {
+        path: String,
+        expected: Option<String>,
+        source: serde_json::Error,
+    },
+
     #[snafu(display("Missing required input '{}'", input))]
     MissingInput { input: String },
 
@@ -41,6 +49,31 @@ pub enum Error {
     #[snafu(display("Tried to commit with no pending changes"))]
     CommitWithNoPending,
 
+    #[snafu(display("Invalid value '{}' for '{}': {}", input, name, source))]
+    InvalidFlag {
+        name: String,
+        input: String,
+        source: std::str::ParseBoolError,
+    },
+
+    #[snafu(display("Request was canceled before it finished"))]
+    Canceled,
+
+    #[snafu(display("Client not allowed to {} {}", method, path))]
+    Forbidden { method: String, path: String },
+
+    #[snafu(display("Unable to read authorization policy from {}: {}", path.display(), source))]
+    PolicyRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Authorization policy at {} is not valid TOML: {}", path.display(), source))]
+    PolicyParse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display("Invalid authorization policy at {}: {}", path.display(), msg))]
+    PolicyInvalid { path: PathBuf, msg: String },
+
     // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
     // Controller errors
@@ -99,6 +132,168 @@ pub enum Error {
 
     #[snafu(display("Unable to send input to config applier: {}", source))]
     ConfigApplierWrite { source: io::Error },
+
+    #[snafu(display("Unable to get output of config applier: {}", source))]
+    ConfigApplierWait { source: io::Error },
+
+    #[snafu(display("Config applier failed: {}", stderr))]
+    ConfigApplierFailed { stderr: String },
+
+    #[snafu(display("Config applier output is not valid JSON: {}", source))]
+    ConfigApplierOutput { source: serde_json::Error },
+
+    // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+    // OS information errors
+
+    #[snafu(display("Unable to get OS release information: {}", source))]
+    ReleaseVersion { source: gilmanos_release::Error },
+
+    #[snafu(display("Unable to read OS disk partition table: {}", source))]
+    PartitionTableRead {
+        // signpost::Error triggers clippy::large_enum_variant
+        #[snafu(source(from(signpost::Error, Box::new)))]
+        source: Box<signpost::Error>,
+    },
+
+    // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+    // Host action errors
+
+    #[snafu(display("Unable to start thar-be-updates: {}", source))]
+    UpdateDispatcherStart { source: io::Error },
+
+    #[snafu(display("Unable to use update lock: {}", source))]
+    UpdateLock { source: io::Error },
+
+    #[snafu(display("Update lock held; another update command is in flight"))]
+    UpdateLockHeld,
+
+    #[snafu(display("Update command '{}' not allowed in the current update state", command))]
+    DisallowCommand { command: String },
+
+    #[snafu(display("Chosen update does not exist"))]
+    UpdateDoesNotExist,
+
+    #[snafu(display("No update image applied to the inactive partition set"))]
+    NoStagedImage,
+
+    #[snafu(display("Update command '{}' failed with exit code {}", command, code))]
+    UpdateCommandFailed { command: String, code: i32 },
+
+    #[snafu(display("Unable to get update status: {}", source))]
+    UpdateStatusRead { source: thar_be_updates::error::Error },
+
+    #[snafu(display("Unable to start reboot: {}", source))]
+    RebootStart { source: io::Error },
+
+    #[snafu(display("Reboot failed: {}", stderr))]
+    RebootFailed { stderr: String },
+
+    // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+    // Data store snapshot errors
+
+    #[snafu(display("Unknown snapshot format '{}', expected 'json' or 'toml'", format))]
+    InvalidSnapshotFormat { format: String },
+
+    #[snafu(display("Unable to parse JSON snapshot: {}", source))]
+    SnapshotParseJson { source: serde_json::Error },
+
+    #[snafu(display("Unable to parse TOML snapshot: {}", source))]
+    SnapshotParseToml { source: toml::de::Error },
+
+    #[snafu(display("Unable to serialize snapshot to TOML: {}", source))]
+    SnapshotSerializeToml { source: toml::ser::Error },
+
+    #[snafu(display(
+        "Snapshot is from data store version {}, but this host runs {}",
+        given,
+        expected
+    ))]
+    SnapshotVersion { given: Version, expected: Version },
+
+    #[snafu(display("Unable to read data store version: {}", source))]
+    DataStoreVersion {
+        source: data_store_version::error::Error,
+    },
+
+    // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+    // Settings history errors
+
+    #[snafu(display("Another thread poisoned the history lock by panicking"))]
+    HistoryLock,
+
+    #[snafu(display("Unable to read settings history from {}: {}", path.display(), source))]
+    HistoryRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Settings history at {} is not valid JSON: {}", path.display(), source))]
+    HistoryParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Unable to serialize settings history: {}", source))]
+    HistorySerialize { source: serde_json::Error },
+
+    #[snafu(display("Unable to write settings history to {}: {}", path.display(), source))]
+    HistoryWrite { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to move settings history into place at {}: {}", path.display(), source))]
+    HistoryPersist {
+        path: PathBuf,
+        source: tempfile::PersistError,
+    },
+
+    #[snafu(display("Value of '{}' is not valid JSON: {}", key, source))]
+    HistoryValue {
+        key: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("No commit with ID {} in settings history", id))]
+    HistoryMissing { id: u64 },
+
+    #[snafu(display(
+        "Can't roll back to commit {} because these settings weren't set then: {}",
+        id,
+        keys
+    ))]
+    RollbackUnset { id: u64, keys: String },
+
+    #[snafu(display("Invalid commit ID '{}': {}", input, source))]
+    InvalidCommitId {
+        input: String,
+        source: std::num::ParseIntError,
+    },
+
+    // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+    // Settings watch errors
+
+    #[snafu(display("Another thread poisoned the watch lock by panicking"))]
+    WatchLock,
+
+    #[snafu(display("Invalid value '{}' for '{}': {}", input, name, source))]
+    InvalidWatchParameter {
+        name: String,
+        input: String,
+        source: std::num::ParseIntError,
+    },
+
+    #[snafu(display(
+        "Changes since revision {} are no longer known (latest is {}); reload settings and watch again",
+        since,
+        revision
+    ))]
+    WatchMissedChanges { since: u64, revision: u64 },
+
+    #[snafu(display("Too many requests are watching settings (limit {}); try again later", max))]
+    TooManyWatchers { max: usize },
+
+    #[snafu(display("Unable to serialize watch response: {}", source))]
+    WatchSerialization { source: serde_json::Error },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
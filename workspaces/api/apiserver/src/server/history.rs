@@ -0,0 +1,191 @@
+//! The history module keeps a journal of the changes made by each settings commit, so users can
+//! see what changed and roll it back.
+//!
+//! The journal is a JSON file next to the datastore.  It's bounded; once it holds MAX_ENTRIES
+//! commits, the oldest are dropped as new ones are recorded.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+use crate::datastore::Value;
+use crate::server::error::{self, Result};
+
+/// The number of commits we keep in the journal.
+const MAX_ENTRIES: usize = 100;
+
+/// The old and new values of a key changed in a commit.  The old value is None if the key
+/// wasn't set before the commit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Change {
+    pub(crate) old: Option<Value>,
+    pub(crate) new: Value,
+}
+
+/// A single commit in the journal.  Changes are keyed by data key name; we can't deserialize Key
+/// directly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HistoryEntry {
+    pub(crate) id: u64,
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) changes: HashMap<String, Change>,
+}
+
+#[derive(Debug)]
+pub(crate) struct History {
+    path: PathBuf,
+    entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// Loads the journal from the given path.  If there's no journal yet, starts an empty one.
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let entries = match fs::read_to_string(path) {
+            Ok(s) => serde_json::from_str(&s).context(error::HistoryParse { path })?,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e).context(error::HistoryRead { path });
+                }
+                Vec::new()
+            }
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    /// Returns the recorded commits, oldest first.
+    pub(crate) fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// Records a commit with the given changes, and returns its ID.
+    pub(crate) fn record(&mut self, changes: HashMap<String, Change>) -> Result<u64> {
+        let id = self.entries.last().map(|e| e.id + 1).unwrap_or(1);
+        self.entries.push(HistoryEntry {
+            id,
+            timestamp: Utc::now(),
+            changes,
+        });
+        if self.entries.len() > MAX_ENTRIES {
+            let excess = self.entries.len() - MAX_ENTRIES;
+            self.entries.drain(..excess);
+        }
+
+        self.write()?;
+        Ok(id)
+    }
+
+    /// Returns the changes needed to get the settings changed since the given commit back to
+    /// their values as of that commit.  Keys that weren't set as of that commit are returned
+    /// with a value of None, since there's no earlier value to restore.
+    pub(crate) fn rollback_to(&self, id: u64) -> Result<HashMap<String, Option<Value>>> {
+        let position = self
+            .entries
+            .iter()
+            .position(|e| e.id == id)
+            .context(error::HistoryMissing { id })?;
+
+        // Walk the later commits from newest to oldest so the value we end up with for each key
+        // is the one it had just before the first change after the requested commit.
+        let mut result = HashMap::new();
+        for entry in self.entries[position + 1..].iter().rev() {
+            for (key, change) in &entry.changes {
+                result.insert(key.clone(), change.old.clone());
+            }
+        }
+        Ok(result)
+    }
+
+    /// Writes the journal to disk, replacing the old one atomically.
+    fn write(&self) -> Result<()> {
+        let dir = self.path.parent().unwrap_or_else(|| Path::new("."));
+        let data = serde_json::to_vec(&self.entries).context(error::HistorySerialize)?;
+
+        let mut file = NamedTempFile::new_in(dir).context(error::HistoryWrite { path: dir })?;
+        file.write_all(&data)
+            .and_then(|_| file.as_file().sync_all())
+            .context(error::HistoryWrite { path: file.path() })?;
+        file.persist(&self.path).context(error::HistoryPersist {
+            path: &self.path,
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maplit::hashmap;
+    use tempfile::TempDir;
+
+    #[test]
+    fn record_and_load() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("history");
+
+        let mut history = History::load(&path).unwrap();
+        assert!(history.entries().is_empty());
+        let id = history
+            .record(hashmap!("settings.a".to_string() => Change { old: None, new: "x".into() }))
+            .unwrap();
+        assert_eq!(id, 1);
+
+        let history = History::load(&path).unwrap();
+        assert_eq!(history.entries().len(), 1);
+        assert_eq!(history.entries()[0].id, 1);
+    }
+
+    #[test]
+    fn bounded() {
+        let dir = TempDir::new().unwrap();
+        let mut history = History::load(dir.path().join("history")).unwrap();
+        for i in 0..MAX_ENTRIES + 5 {
+            history
+                .record(hashmap!("settings.a".to_string() => Change { old: None, new: i.into() }))
+                .unwrap();
+        }
+
+        assert_eq!(history.entries().len(), MAX_ENTRIES);
+        // IDs keep counting up after old entries are dropped
+        assert_eq!(history.entries()[0].id, 6);
+        assert_eq!(history.entries().last().unwrap().id, MAX_ENTRIES as u64 + 5);
+    }
+
+    #[test]
+    fn rollback() {
+        let dir = TempDir::new().unwrap();
+        let mut history = History::load(dir.path().join("history")).unwrap();
+        history
+            .record(hashmap!("settings.a".to_string() => Change { old: None, new: "a1".into() }))
+            .unwrap();
+        history
+            .record(hashmap!(
+                "settings.a".to_string() => Change { old: Some("a1".into()), new: "a2".into() },
+                "settings.b".to_string() => Change { old: None, new: "b1".into() },
+            ))
+            .unwrap();
+        history
+            .record(hashmap!("settings.a".to_string() => Change { old: Some("a2".into()), new: "a3".into() }))
+            .unwrap();
+
+        assert_eq!(
+            history.rollback_to(1).unwrap(),
+            hashmap!("settings.a".to_string() => Some("a1".into()), "settings.b".to_string() => None)
+        );
+        assert_eq!(
+            history.rollback_to(2).unwrap(),
+            hashmap!("settings.a".to_string() => Some("a2".into()))
+        );
+        assert!(history.rollback_to(3).unwrap().is_empty());
+        history.rollback_to(4).unwrap_err();
+    }
+}
@@ -1,19 +1,51 @@
 //! The server module owns the API surface.  It interfaces with the datastore through the
 //! server::controller module.
 
+mod auth;
 mod controller;
 mod error;
+mod history;
+mod os;
+mod updates;
+mod watch;
 pub use error::Error;
 
-use actix_web::{error::ResponseError, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
-use snafu::{OptionExt, ResultExt};
+use actix_http::HttpService;
+use actix_server::Server;
+use actix_web::error::{BlockingError, ResponseError};
+use actix_web::http::StatusCode;
+use actix_web::{web, App, HttpRequest, HttpResponse, Responder};
+use bytes::Bytes;
+use data_store_version::Version;
+use futures::{future, stream, Future};
+use serde::Serialize;
+use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync;
+use std::time::Duration;
 
-use crate::datastore::{Committed, FilesystemDataStore, Key, Value};
+use crate::datastore::serialization::to_pairs;
+use crate::datastore::{self, snapshot, AnyDataStore, Backend, Committed, Key, Snapshot, Value};
 use crate::model::{ConfigurationFiles, Services, Settings};
+use auth::{ConnectionCredentials, Policy};
 use error::Result;
+use history::{History, HistoryEntry};
+use os::OsInfo;
+use thar_be_updates::status::{UpdateCommand, UpdateStatus};
+use watch::{WatchChanges, Watcher};
+
+// How long a watch waits for changes if the caller doesn't say.
+const DEFAULT_WATCH_TIMEOUT: Duration = Duration::from_secs(60);
+// The longest we let a single watch wait.
+const MAX_WATCH_TIMEOUT: Duration = Duration::from_secs(3600);
+// How long a streaming watch waits before sending an empty update, so clients can tell the
+// connection is still alive.
+const STREAM_HEARTBEAT: Duration = Duration::from_secs(60);
+// The version of the data store this OS uses, which we record in snapshots.
+const DATASTORE_VERSION_FILE: &str = "/usr/share/thar/data-store-version";
+// The transaction that live settings from an imported snapshot are staged in before commit.
+const IMPORT_TRANSACTION: &str = "snapshot-import";
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
@@ -40,43 +72,120 @@ fn notify_unix_socket_ready() -> Result<()> {
 /// This is the primary interface of the module.  It defines the server and application that actix
 /// spawns for requests.  It creates a shared datastore handle that can be used by handler methods
 /// to interface with the controller.
-pub fn serve<P1, P2>(socket_path: P1, datastore_path: P2, threads: usize) -> Result<()>
+///
+/// If policy_path is given, requests are checked against the authorization policy there; see the
+/// auth module.  The backend chooses the DataStore implementation used in datastore_path.
+pub fn serve<P1, P2>(
+    socket_path: P1,
+    datastore_path: P2,
+    backend: Backend,
+    policy_path: Option<&Path>,
+    threads: usize,
+) -> Result<()>
 where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
 {
+    let policy = match policy_path {
+        Some(path) => Some(Policy::load(path)?),
+        None => None,
+    };
+
+    // Finish or roll back any commit that was interrupted, e.g. by a power loss, before we serve
+    // any data from the datastore.
+    let mut datastore =
+        AnyDataStore::open(backend, datastore_path.as_ref()).context(error::DataStore {
+            op: "open datastore",
+        })?;
+    datastore.recover().context(error::DataStore {
+        op: "recover interrupted commit",
+    })?;
+
+    // The settings history journal lives next to the datastore.
+    let history = History::load(datastore_path.as_ref().join("history"))?;
+
     let shared_datastore = web::Data::new(SharedDataStore {
-        ds: sync::RwLock::new(FilesystemDataStore::new(datastore_path)),
+        ds: sync::RwLock::new(datastore),
+        history: sync::RwLock::new(history),
+        watcher: Watcher::new(),
+        policy,
     });
 
-    let http_server = HttpServer::new(move || {
+    let app = move || {
         App::new()
             .register_data(shared_datastore.clone())
             .service(
                 web::scope("/settings")
                     .route("", web::get().to(get_settings))
                     .route("", web::patch().to(patch_settings))
-                    .route("/pending", web::get().to(get_pending_settings))
-                    .route("/commit", web::post().to(commit_settings))
-                    .route("/apply", web::post().to(apply_settings))
-                    .route("/commit_and_apply", web::post().to(commit_and_apply_settings))
+                    .route("/history", web::get().to(get_settings_history))
+                    .route("/rollback", web::post().to(rollback_settings))
+                    // The default transaction is also reachable at its original paths.
+                    .route("/pending", web::get().to(get_transaction))
+                    .route("/commit", web::post().to(commit_transaction))
+                    .route("/apply", web::post().to(apply_changes))
+                    .route(
+                        "/commit_and_apply",
+                        web::post().to_async(commit_transaction_and_apply),
+                    )
+                    .route("/watch", web::get().to_async(watch_settings))
+                    .route("/watch/stream", web::get().to(stream_settings_changes)),
+            )
+            .service(
+                web::scope("/tx")
+                    .route("", web::get().to(get_transaction))
+                    .route("", web::delete().to(delete_transaction))
+                    .route("/list", web::get().to(get_transaction_list))
+                    .route("/commit", web::post().to(commit_transaction))
+                    .route("/apply", web::post().to(apply_changes))
+                    .route(
+                        "/commit_and_apply",
+                        web::post().to_async(commit_transaction_and_apply),
+                    ),
             )
             .service(
                 web::scope("/metadata")
                     .route("/affected-services", web::get().to(get_affected_services))
-                    .route("/setting-generators", web::get().to(get_setting_generators)),
+                    .route("/setting-generators", web::get().to(get_setting_generators))
+                    .route("/templates", web::get().to(get_templates)),
+            )
+            .service(web::scope("/os").route("", web::get().to(get_os_info)))
+            .service(
+                web::scope("/datastore")
+                    .route("/export", web::get().to(export_datastore))
+                    .route("/import", web::post().to(import_datastore)),
+            )
+            .service(
+                web::scope("/actions")
+                    .route("/reboot", web::post().to_async(reboot))
+                    .route("/refresh-updates", web::post().to_async(refresh_updates))
+                    .route("/prepare-update", web::post().to_async(prepare_update))
+                    .route("/activate-update", web::post().to_async(activate_update))
+                    .route("/deactivate-update", web::post().to_async(deactivate_update)),
+            )
+            .service(
+                web::scope("/updates").route("/status", web::get().to(get_update_status)),
             )
             .service(web::scope("/services").route("", web::get().to(get_services)))
             .service(
                 web::scope("/configuration-files")
                     .route("", web::get().to(get_configuration_files)),
             )
-    })
-    .workers(threads)
-    .bind_uds(socket_path.as_ref())
-    .context(error::BindSocket {
-        path: socket_path.as_ref(),
-    })?;
+    };
+
+    // actix-web's HttpServer doesn't tell us who's on the other end of a connection, so we put
+    // the server together from actix-server and actix-http, as HttpServer does, and read each
+    // client's credentials as it connects, so handlers can check them against the policy.
+    let server = Server::build()
+        .workers(threads)
+        .bind_uds("apiserver", socket_path.as_ref(), move || {
+            HttpService::build()
+                .on_connect(auth::connection_credentials)
+                .h1(app())
+        })
+        .context(error::BindSocket {
+            path: socket_path.as_ref(),
+        })?;
 
     // Notify system manager the UNIX socket has been initialized, so other service units can proceed
     #[cfg(feature = "sd_notify")]
@@ -84,7 +193,9 @@ where
         notify_unix_socket_ready()?;
     }
 
-    http_server.run().context(error::ServerStart)
+    let system = actix_rt::System::new("apiserver");
+    server.start();
+    system.run().context(error::ServerStart)
 }
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
@@ -96,91 +207,464 @@ where
 /// Return the live settings from the data store; if 'keys' or 'prefix' are specified in query
 /// parameters, return the subset of matching settings.
 fn get_settings(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
-) -> Result<Settings> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
-
-    if let Some(keys_str) = query.get("keys") {
+) -> Result<SettingsResponse> {
+    let settings = if let Some(keys_str) = query.get("keys") {
         let keys = comma_separated("keys", keys_str)?;
-        controller::get_settings_keys(&*datastore, &keys, Committed::Live)
+        authorize(&req, &data, &keys.iter().collect::<Vec<_>>())?;
+        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+        controller::get_settings_keys(&*datastore, &keys, &Committed::Live)?
     } else if let Some(prefix_str) = query.get("prefix") {
         if prefix_str.is_empty() {
             return error::EmptyInput { input: "prefix" }.fail();
         }
         // Note: the prefix should not include "settings."
-        controller::get_settings_prefix(&*datastore, prefix_str, Committed::Live)
+        authorize(&req, &data, &["settings.".to_string() + prefix_str])?;
+        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+        controller::get_settings_prefix(&*datastore, prefix_str, &Committed::Live)?
     } else {
-        controller::get_settings(&*datastore, Committed::Live)
-    }
+        authorize(&req, &data, &["settings"])?;
+        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+        controller::get_settings(&*datastore, &Committed::Live)?
+    };
+    settings_response(&req, &settings)
 }
 
-/// Apply the requested settings to the pending data store
+/// Apply the requested settings to the pending data store under the transaction given in the
+/// 'tx' query parameter, or the default transaction if none is given.
 fn patch_settings(
-    settings: web::Json<Settings>,
+    req: HttpRequest,
+    body: web::Bytes,
+    query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
 ) -> Result<HttpResponse> {
+    let settings = deserialize_settings(&body)?;
+    let pairs = to_pairs(&settings).context(error::DataStoreSerialization { given: "Settings" })?;
+    authorize(&req, &data, &pairs.keys().collect::<Vec<_>>())?;
+
+    let transaction = transaction_name(&query);
     let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
-    controller::set_settings(&mut *datastore, &settings)?;
+    controller::set_settings(&mut *datastore, &settings, transaction)?;
     Ok(HttpResponse::NoContent().finish()) // 204
 }
 
-/// Return any settings that have been received but not committed
-fn get_pending_settings(data: web::Data<SharedDataStore>) -> Result<Settings> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
-    controller::get_pending_settings(&*datastore)
+/// Return the recorded changes made by recent commits, oldest first.
+fn get_settings_history(
+    req: HttpRequest,
+    data: web::Data<SharedDataStore>,
+) -> Result<HistoryResponse> {
+    authorize(&req, &data, &["settings"])?;
+    let history = data.history.read().ok().context(error::HistoryLock)?;
+    let mut entries = history.entries().to_vec();
+    if !auth::reveals_secrets(req.extensions().get::<ConnectionCredentials>()) {
+        controller::mask_history(&mut entries);
+    }
+    Ok(HistoryResponse(entries))
 }
 
-/// Save settings changes to the main data store and kick off appliers.
-fn commit_settings(data: web::Data<SharedDataStore>) -> Result<ChangedKeysResponse> {
+/// Stage changes that return settings to their values as of the commit given in the 'to' query
+/// parameter.  Changes go in the transaction given in the 'tx' query parameter, or the default
+/// transaction; they have to be committed like any other changes.
+fn rollback_settings(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<ChangedKeysResponse> {
+    authorize(&req, &data, &["settings"])?;
+    let to_str = query.get("to").context(error::MissingInput { input: "to" })?;
+    let to = to_str
+        .parse()
+        .context(error::InvalidCommitId { input: to_str.as_str() })?;
+    let transaction = transaction_name(&query);
+
     let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+    let history = data.history.read().ok().context(error::HistoryLock)?;
+    let staged = controller::rollback_settings(&mut *datastore, &*history, to, transaction)?;
+    Ok(ChangedKeysResponse(staged))
+}
 
-    let changes = controller::commit(&mut *datastore)?;
+/// Wait until a commit changes a setting under the prefix given in the 'prefix' query parameter,
+/// then return the changed keys and the new revision.  If 'since' is given, changes after that
+/// revision are returned right away; otherwise we wait for the next commit.  If 'timeout' (in
+/// seconds) passes first, we return the latest revision and no keys.
+fn watch_settings(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> impl Future<Item = WatchResponse, Error = error::Error> {
+    let parameters = watch_parameters(&query).and_then(|(prefix, since)| {
+        authorize(&req, &data, &[&prefix])?;
+        let timeout = watch_timeout(&query)?;
+        Ok((prefix, since, timeout, data.watcher.reserve()?))
+    });
 
-    if changes.is_empty() {
-        return error::CommitWithNoPending.fail();
-    }
+    future::result(parameters).and_then(move |(prefix, since, timeout, slot)| {
+        // Waiting blocks, so do it off of the server's worker threads.  The slot is released
+        // once the wait is done.
+        web::block(move || {
+            let _slot = slot;
+            data.watcher.wait(&prefix, since, timeout)
+        })
+        .map(WatchResponse)
+        .map_err(blocking_error)
+    })
+}
+
+/// Like watch_settings, but keeps the connection open and sends each change as a line of JSON in
+/// a chunked response.  If nothing changes for a while, sends the latest revision with no keys.
+fn stream_settings_changes(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<HttpResponse> {
+    let (prefix, since) = watch_parameters(&query)?;
+    authorize(&req, &data, &[&prefix])?;
+    let since = match since {
+        Some(since) => since,
+        None => data.watcher.revision()?,
+    };
+    // The stream holds its slot until the client goes away and the stream is dropped.
+    let slot = data.watcher.reserve()?;
+
+    let changes = stream::unfold(since, move |since| {
+        let _slot = &slot;
+        let data = data.clone();
+        let prefix = prefix.clone();
+        let next = web::block(move || data.watcher.wait(&prefix, Some(since), STREAM_HEARTBEAT))
+            .map_err(blocking_error)
+            .and_then(|changes: WatchChanges| {
+                let mut line =
+                    serde_json::to_vec(&changes).context(error::WatchSerialization)?;
+                line.push(b'\n');
+                Ok((Bytes::from(line), changes.revision))
+            });
+        Some(next)
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .streaming(changes))
+}
+
+/// Return the names of any transactions that have pending settings.
+fn get_transaction_list(
+    req: HttpRequest,
+    data: web::Data<SharedDataStore>,
+) -> Result<TransactionListResponse> {
+    authorize(&req, &data, &["settings"])?;
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let data = controller::list_transactions(&*datastore)?;
+    Ok(TransactionListResponse(data))
+}
+
+/// Return any settings that have been received but not committed in the given transaction.
+fn get_transaction(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<SettingsResponse> {
+    authorize(&req, &data, &["settings"])?;
+    let transaction = transaction_name(&query);
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let settings = controller::get_transaction(&*datastore, transaction)?;
+    settings_response(&req, &settings)
+}
+
+/// Delete the given transaction, discarding any settings that were received but not committed.
+fn delete_transaction(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<ChangedKeysResponse> {
+    authorize(&req, &data, &["settings"])?;
+    let transaction = transaction_name(&query);
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+    let deleted = controller::delete_transaction(&mut *datastore, transaction)?;
+    Ok(ChangedKeysResponse(deleted))
+}
 
+/// Save settings changes from the given transaction to the live data store.  Returns the list of
+/// changed keys.
+fn commit_transaction(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<ChangedKeysResponse> {
+    authorize(&req, &data, &["settings"])?;
+    let transaction = transaction_name(&query);
+    let changes = commit_and_notify(transaction, &data)?;
     Ok(ChangedKeysResponse(changes))
 }
 
-/// Save settings changes to the main data store and kick off appliers.
-fn apply_settings(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
+/// Starts settings appliers for any changes that have been committed to the data store.  This
+/// updates config files, runs restart commands, etc.
+fn apply_changes(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<HttpResponse> {
     if let Some(keys_str) = query.get("keys") {
         let keys = comma_separated("keys", keys_str)?;
+        authorize(&req, &data, &keys.iter().collect::<Vec<_>>())?;
         controller::apply_changes(Some(&keys))?;
     } else {
+        authorize(&req, &data, &["settings"])?;
         controller::apply_changes(None as Option<&HashSet<&str>>)?;
     }
 
     Ok(HttpResponse::NoContent().json(()))
 }
 
-/// Save settings changes to the main data store and kick off appliers.
-fn commit_and_apply_settings(data: web::Data<SharedDataStore>) -> Result<ChangedKeysResponse> {
+/// Usually you want to apply settings changes you've committed, so this is a convenience method
+/// to perform both a commit and an apply.  Commits the given transaction.
+///
+/// With dry-run=true, nothing is committed or applied; instead, returns the changes the
+/// transaction would make to each affected configuration file, and the restart commands that
+/// would be run.
+fn commit_transaction_and_apply(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Box<dyn Future<Item = HttpResponse, Error = error::Error>> {
+    let dry_run = authorize(&req, &data, &["settings"]).and_then(|_| flag(&query, "dry-run"));
+    let transaction = transaction_name(&query).to_string();
+    let reveal = auth::reveals_secrets(req.extensions().get::<ConnectionCredentials>());
+    match dry_run {
+        Ok(true) => Box::new(preview_transaction(transaction, reveal, data)),
+        Ok(false) => Box::new(future::result(
+            commit_and_apply(&transaction, &data).map(|changes| HttpResponse::Ok().json(changes)),
+        )),
+        Err(e) => Box::new(future::err(e)),
+    }
+}
+
+/// Commits the given transaction and starts settings appliers for its changes.  Returns the
+/// changed keys.
+fn commit_and_apply(transaction: &str, data: &SharedDataStore) -> Result<HashSet<Key>> {
+    let changes = commit_and_notify(transaction, data)?;
+    controller::apply_changes(Some(&changes))?;
+    Ok(changes)
+}
+
+/// Commits the given transaction and tells settings watchers about its changes.  Returns the
+/// changed keys; it's an error if the transaction had none.
+fn commit_and_notify(transaction: &str, data: &SharedDataStore) -> Result<HashSet<Key>> {
     let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
 
-    let changes = controller::commit(&mut *datastore)?;
+    let mut history = data.history.write().ok().context(error::HistoryLock)?;
+
+    let changes = controller::commit_transaction(&mut *datastore, &mut *history, transaction)?;
 
     if changes.is_empty() {
         return error::CommitWithNoPending.fail();
     }
+    data.watcher.notify(&changes)?;
 
-    controller::apply_changes(Some(&changes))?;
+    Ok(changes)
+}
+
+/// Runs the config applier in dry-run mode for the given transaction, and returns its report.
+/// Unless `reveal` is set, the diffs are masked, since rendered files can hold secret settings.
+fn preview_transaction(
+    transaction: String,
+    reveal: bool,
+    data: web::Data<SharedDataStore>,
+) -> impl Future<Item = HttpResponse, Error = error::Error> {
+    // The config applier calls back into the API, so we can't hold the lock while it runs.
+    let keys = data
+        .ds
+        .read()
+        .ok()
+        .context(error::DataStoreLock)
+        .and_then(|datastore| controller::get_transaction_keys(&*datastore, &transaction));
+
+    future::result(keys).and_then(move |keys| {
+        if keys.is_empty() {
+            return future::Either::A(future::err(error::Error::CommitWithNoPending));
+        }
+        // Waiting for the config applier blocks, so do it off of the server's worker threads.
+        future::Either::B(
+            web::block(move || controller::preview_changes(&keys, &transaction))
+                .map(move |mut report| {
+                    if !reveal {
+                        controller::mask_preview(&mut report);
+                    }
+                    HttpResponse::Ok().json(report)
+                })
+                .map_err(blocking_error),
+        )
+    })
+}
+
+/// Returns a snapshot of the whole data store: live and pending data, and metadata.  The snapshot
+/// is JSON, or TOML with format=toml.  The values of secret settings are masked unless the client
+/// is root.
+fn export_datastore(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<HttpResponse> {
+    authorize(&req, &data, &["datastore.export"])?;
+    let format = snapshot_format(&query)?;
+    let version = Version::from_file(DATASTORE_VERSION_FILE).context(error::DataStoreVersion)?;
+
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let mut snapshot =
+        snapshot::export(&*datastore, version).context(error::DataStore { op: "export" })?;
+    if !auth::reveals_secrets(req.extensions().get::<ConnectionCredentials>()) {
+        controller::mask_snapshot(&mut snapshot);
+    }
+
+    match format {
+        SnapshotFormat::Json => Ok(HttpResponse::Ok().json(snapshot)),
+        SnapshotFormat::Toml => {
+            // Going through toml::Value puts tables after plain values, as TOML requires.
+            let body = toml::Value::try_from(&snapshot)
+                .and_then(|value| toml::to_string(&value))
+                .context(error::SnapshotSerializeToml)?;
+            Ok(HttpResponse::Ok()
+                .content_type("application/toml")
+                .body(body))
+        }
+    }
+}
+
+/// Writes a snapshot from /datastore/export to the data store.  The snapshot must come from the
+/// data store version this host runs.  Live settings are staged and committed like any other
+/// change, so they're recorded in history, seen by watchers, and applied.  Snapshots can hold
+/// secret settings, so only root may import them.  Returns the settings that changed.
+fn import_datastore(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<ChangedKeysResponse> {
+    authorize(&req, &data, &["datastore.import"])?;
+    require_root(&req)?;
+    let snapshot: Snapshot = match snapshot_format(&query)? {
+        SnapshotFormat::Json => serde_json::from_slice(&body).context(error::SnapshotParseJson)?,
+        SnapshotFormat::Toml => toml::from_slice(&body).context(error::SnapshotParseToml)?,
+    };
+
+    let version = Version::from_file(DATASTORE_VERSION_FILE).context(error::DataStoreVersion)?;
+    ensure!(
+        snapshot.version == version,
+        error::SnapshotVersion {
+            given: snapshot.version,
+            expected: version,
+        }
+    );
+
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+    snapshot::import(&mut *datastore, &snapshot, IMPORT_TRANSACTION)
+        .context(error::DataStore { op: "import" })?;
+
+    let mut history = data.history.write().ok().context(error::HistoryLock)?;
+    let changes =
+        controller::commit_transaction(&mut *datastore, &mut *history, IMPORT_TRANSACTION)?;
+    if !changes.is_empty() {
+        data.watcher.notify(&changes)?;
+        controller::apply_changes(Some(&changes))?;
+    }
 
     Ok(ChangedKeysResponse(changes))
 }
 
-/// Get the affected services for a list of data keys
+/// Get the release information of the running OS, and its active and next-boot partition sets
+fn get_os_info(req: HttpRequest, data: web::Data<SharedDataStore>) -> Result<OsInfoResponse> {
+    authorize(&req, &data, &["os"])?;
+    os::get_os_info().map(OsInfoResponse)
+}
+
+/// Reboots the host, unless an update command is in flight.
+fn reboot(
+    req: HttpRequest,
+    data: web::Data<SharedDataStore>,
+) -> impl Future<Item = HttpResponse, Error = error::Error> {
+    future::result(authorize(&req, &data, &["actions.reboot"])).and_then(|_| {
+        web::block(updates::reboot)
+            .map(|_| HttpResponse::Accepted().finish())
+            .map_err(blocking_error)
+    })
+}
+
+/// Queries the update repository and refreshes the list of available updates.
+fn refresh_updates(
+    req: HttpRequest,
+    data: web::Data<SharedDataStore>,
+) -> impl Future<Item = HttpResponse, Error = error::Error> {
+    update_action(req, data, "actions.refresh-updates", UpdateCommand::Refresh)
+}
+
+/// Downloads the chosen update and writes it to the inactive partition set.
+fn prepare_update(
+    req: HttpRequest,
+    data: web::Data<SharedDataStore>,
+) -> impl Future<Item = HttpResponse, Error = error::Error> {
+    update_action(req, data, "actions.prepare-update", UpdateCommand::Prepare)
+}
+
+/// Marks the partition set with the prepared update to boot next.
+fn activate_update(
+    req: HttpRequest,
+    data: web::Data<SharedDataStore>,
+) -> impl Future<Item = HttpResponse, Error = error::Error> {
+    update_action(req, data, "actions.activate-update", UpdateCommand::Activate)
+}
+
+/// Marks the running partition set to boot next again.
+fn deactivate_update(
+    req: HttpRequest,
+    data: web::Data<SharedDataStore>,
+) -> impl Future<Item = HttpResponse, Error = error::Error> {
+    update_action(req, data, "actions.deactivate-update", UpdateCommand::Deactivate)
+}
+
+/// Starts the given update command in the background.  Responds with 202 Accepted once it has
+/// started; callers can follow its progress at /updates/status.
+fn update_action(
+    req: HttpRequest,
+    data: web::Data<SharedDataStore>,
+    action: &'static str,
+    command: UpdateCommand,
+) -> impl Future<Item = HttpResponse, Error = error::Error> {
+    future::result(authorize(&req, &data, &[action])).and_then(move |_| {
+        // thar-be-updates may call back into the API, so wait for it off of the server's worker
+        // threads.
+        web::block(move || updates::dispatch_update_command(command))
+            .map(|_| HttpResponse::Accepted().finish())
+            .map_err(blocking_error)
+    })
+}
+
+/// Get the status of updates, as last written by thar-be-updates
+fn get_update_status(
+    req: HttpRequest,
+    data: web::Data<SharedDataStore>,
+) -> Result<UpdateStatusResponse> {
+    authorize(&req, &data, &["updates.status"])?;
+    updates::get_update_status().map(UpdateStatusResponse)
+}
+
+/// Get the affected services for a list of data keys, live or in the transaction given in the 'tx'
+/// query parameter
 fn get_affected_services(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
 ) -> Result<MetadataResponse> {
     if let Some(keys_str) = query.get("keys") {
         let data_keys = comma_separated("keys", keys_str)?;
+        authorize(&req, &data, &data_keys.iter().collect::<Vec<_>>())?;
         let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
-        let resp =
-            controller::get_metadata_for_data_keys(&*datastore, "affected-services", &data_keys)?;
+        let resp = controller::get_metadata_for_data_keys(
+            &*datastore,
+            "affected-services",
+            &data_keys,
+            &metadata_committed(&query),
+        )?;
 
         Ok(MetadataResponse(resp))
     } else {
@@ -188,15 +672,48 @@ fn get_affected_services(
     }
 }
 
-/// Get all settings that have setting-generator metadata
-fn get_setting_generators(data: web::Data<SharedDataStore>) -> Result<MetadataResponse> {
+/// Get all settings that have setting-generator metadata, live or in the transaction given in the
+/// 'tx' query parameter
+fn get_setting_generators(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<MetadataResponse> {
+    authorize(&req, &data, &["settings"])?;
     let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
-    let resp = controller::get_metadata_for_all_data_keys(&*datastore, "setting-generator")?;
+    let resp = controller::get_metadata_for_all_data_keys(
+        &*datastore,
+        "setting-generator",
+        &metadata_committed(&query),
+    )?;
+    Ok(MetadataResponse(resp))
+}
+
+/// Get the templates for a list of data keys, or if 'keys' isn't specified, for all data keys that
+/// have templates; live, or in the transaction given in the 'tx' query parameter
+fn get_templates(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<MetadataResponse> {
+    let committed = metadata_committed(&query);
+    let resp = if let Some(keys_str) = query.get("keys") {
+        let data_keys = comma_separated("keys", keys_str)?;
+        authorize(&req, &data, &data_keys.iter().collect::<Vec<_>>())?;
+        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+        controller::get_metadata_for_data_keys(&*datastore, "templates", &data_keys, &committed)
+    } else {
+        authorize(&req, &data, &["settings"])?;
+        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+        controller::get_metadata_for_all_data_keys(&*datastore, "templates", &committed)
+    }?;
+
     Ok(MetadataResponse(resp))
 }
 
 /// Get all services, or if 'names' is specified, services with those names
 fn get_services(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
 ) -> Result<ServicesResponse> {
@@ -204,8 +721,11 @@ fn get_services(
 
     let resp = if let Some(names_str) = query.get("names") {
         let names = comma_separated("names", names_str)?;
-        controller::get_services_names(&*datastore, &names, Committed::Live)
+        let keys: Vec<String> = names.iter().map(|name| format!("services.{}", name)).collect();
+        authorize(&req, &data, &keys)?;
+        controller::get_services_names(&*datastore, &names, &Committed::Live)
     } else {
+        authorize(&req, &data, &["services"])?;
         controller::get_services(&*datastore)
     }?;
 
@@ -214,6 +734,7 @@ fn get_services(
 
 /// Get all configuration files, or if 'names' is specified, configuration files with those names
 fn get_configuration_files(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
 ) -> Result<ConfigurationFilesResponse> {
@@ -221,8 +742,14 @@ fn get_configuration_files(
 
     let resp = if let Some(names_str) = query.get("names") {
         let names = comma_separated("names", names_str)?;
-        controller::get_configuration_files_names(&*datastore, &names, Committed::Live)
+        let keys: Vec<String> = names
+            .iter()
+            .map(|name| format!("configuration-files.{}", name))
+            .collect();
+        authorize(&req, &data, &keys)?;
+        controller::get_configuration_files_names(&*datastore, &names, &Committed::Live)
     } else {
+        authorize(&req, &data, &["configuration-files"])?;
         controller::get_configuration_files(&*datastore)
     }?;
 
@@ -233,6 +760,164 @@ fn get_configuration_files(
 
 // Helpers for handler methods called by the router
 
+/// Deserializes Settings from a request body.  We do this ourselves, rather than letting actix do
+/// it, so that we can tell the client which field failed and why.
+fn deserialize_settings(body: &[u8]) -> Result<Settings> {
+    let deserializer = &mut serde_json::Deserializer::from_slice(body);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        // The path is relative to Settings; make it a full key like the ones we return elsewhere.
+        let relative_path = e.path().to_string();
+        let path = if relative_path == "." {
+            "settings".to_string()
+        } else {
+            format!("settings.{}", relative_path)
+        };
+        let source = e.into_inner();
+        let expected = expected_type(&source.to_string());
+        error::Error::InvalidSettings {
+            path,
+            expected,
+            source,
+        }
+    })
+}
+
+/// Pulls the expected type out of a serde error message, e.g. "a string" from "invalid type:
+/// integer `1`, expected a string at line 1 column 20".
+fn expected_type(message: &str) -> Option<String> {
+    let expected = message.splitn(2, ", expected ").nth(1)?;
+    let expected = match expected.rfind(" at line ") {
+        Some(i) => &expected[..i],
+        None => expected,
+    };
+    Some(expected.to_string())
+}
+
+/// Returns the given settings as a response, masking the values of secret settings unless the
+/// client that made the request may see them.
+fn settings_response(req: &HttpRequest, settings: &Settings) -> Result<SettingsResponse> {
+    if auth::reveals_secrets(req.extensions().get::<ConnectionCredentials>()) {
+        let value = serde_json::to_value(settings).context(error::ResponseSerialization)?;
+        Ok(SettingsResponse(value))
+    } else {
+        controller::mask_settings(settings).map(SettingsResponse)
+    }
+}
+
+/// Fails unless the client that made the request is root, for requests that can write secret
+/// settings wholesale.
+fn require_root(req: &HttpRequest) -> Result<()> {
+    ensure!(
+        auth::reveals_secrets(req.extensions().get::<ConnectionCredentials>()),
+        error::Forbidden {
+            method: req.method().as_str(),
+            path: req.path(),
+        }
+    );
+    Ok(())
+}
+
+/// Checks the authorization policy to see whether the client that made the request may use the
+/// request's method on the given keys.
+fn authorize<S: AsRef<str>>(req: &HttpRequest, data: &SharedDataStore, keys: &[S]) -> Result<()> {
+    let extensions = req.extensions();
+    auth::authorize(
+        data.policy.as_ref(),
+        extensions.get::<ConnectionCredentials>(),
+        req.method().as_str(),
+        req.path(),
+        keys,
+    )
+}
+
+/// Returns the settings prefix given in the 'prefix' query parameter and the revision given in
+/// the optional 'since' query parameter, for watching settings.
+fn watch_parameters(query: &web::Query<HashMap<String, String>>) -> Result<(String, Option<u64>)> {
+    let prefix_str = query
+        .get("prefix")
+        .context(error::MissingInput { input: "prefix" })?;
+    if prefix_str.is_empty() {
+        return error::EmptyInput { input: "prefix" }.fail();
+    }
+    // Note: the prefix should not include "settings."
+    let prefix = "settings.".to_string() + prefix_str;
+
+    let since = match query.get("since") {
+        Some(since_str) => Some(since_str.parse().context(error::InvalidWatchParameter {
+            name: "since",
+            input: since_str.as_str(),
+        })?),
+        None => None,
+    };
+
+    Ok((prefix, since))
+}
+
+/// Returns the watch timeout given in seconds in the optional 'timeout' query parameter.
+fn watch_timeout(query: &web::Query<HashMap<String, String>>) -> Result<Duration> {
+    match query.get("timeout") {
+        Some(timeout_str) => {
+            let secs = timeout_str.parse().context(error::InvalidWatchParameter {
+                name: "timeout",
+                input: timeout_str.as_str(),
+            })?;
+            Ok(Duration::from_secs(secs).min(MAX_WATCH_TIMEOUT))
+        }
+        None => Ok(DEFAULT_WATCH_TIMEOUT),
+    }
+}
+
+/// Unwraps our error from one returned by a blocking call run with web::block.
+fn blocking_error(e: BlockingError<error::Error>) -> error::Error {
+    match e {
+        BlockingError::Error(e) => e,
+        BlockingError::Canceled => error::Error::Canceled,
+    }
+}
+
+/// Returns whether the given boolean query parameter is set to true; it's false if not given.
+fn flag(query: &web::Query<HashMap<String, String>>, name: &str) -> Result<bool> {
+    match query.get(name) {
+        Some(input) => input.parse().context(error::InvalidFlag { name, input }),
+        None => Ok(false),
+    }
+}
+
+/// The document formats we can use for data store snapshots.
+enum SnapshotFormat {
+    Json,
+    Toml,
+}
+
+/// Returns the snapshot format given in the 'format' query parameter, or JSON if none was given.
+fn snapshot_format(query: &web::Query<HashMap<String, String>>) -> Result<SnapshotFormat> {
+    match query.get("format").map(String::as_str) {
+        None | Some("json") => Ok(SnapshotFormat::Json),
+        Some("toml") => Ok(SnapshotFormat::Toml),
+        Some(format) => error::InvalidSnapshotFormat { format }.fail(),
+    }
+}
+
+/// Returns the transaction name given in the 'tx' query parameter, or "default" if none was
+/// given, so simple clients don't need to know about transactions.
+fn transaction_name(query: &web::Query<HashMap<String, String>>) -> &str {
+    if let Some(name_str) = query.get("tx") {
+        name_str
+    } else {
+        "default"
+    }
+}
+
+/// Returns the pending transaction given in the 'tx' query parameter of a metadata request, or
+/// live if none was given.  Unlike settings requests, there's no default transaction here, since
+/// most callers want the metadata that's in effect.
+fn metadata_committed(query: &web::Query<HashMap<String, String>>) -> Committed {
+    match query.get("tx") {
+        Some(tx) => Committed::Pending { tx: tx.clone() },
+        None => Committed::Live,
+    }
+}
+
 fn comma_separated<'a>(key_name: &'static str, input: &'a str) -> Result<HashSet<&'a str>> {
     if input.is_empty() {
         return error::EmptyInput { input: key_name }.fail();
@@ -240,47 +925,181 @@ fn comma_separated<'a>(key_name: &'static str, input: &'a str) -> Result<HashSet
     Ok(input.split(',').collect())
 }
 
+/// The JSON body returned with every error response.  `code` is a short, stable name for the
+/// type of error, and `message` describes it for humans.  If a request body couldn't be
+/// deserialized, `path` is the dotted key of the field that failed, and `expected` describes the
+/// type it should have been, if known.
+#[derive(Debug, Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected: Option<&'a str>,
+}
+
 // Can also override `render_response` if we want to change headers, content type, etc.
 impl ResponseError for error::Error {
-    /// Maps our error types to the HTTP error code they should return.
+    /// Maps our error types to the HTTP error code they should return, with a JSON body
+    /// describing the error.
     fn error_response(&self) -> HttpResponse {
         use error::Error::*;
-        match self {
+        let (mut response, code) = match self {
             // 400 Bad Request
-            MissingInput { .. } => HttpResponse::BadRequest(),
-            EmptyInput { .. } => HttpResponse::BadRequest(),
-            NewKey { .. } => HttpResponse::BadRequest(),
+            InvalidSettings { .. } => (HttpResponse::BadRequest(), "invalid-settings"),
+            MissingInput { .. } => (HttpResponse::BadRequest(), "missing-input"),
+            EmptyInput { .. } => (HttpResponse::BadRequest(), "empty-input"),
+            NewKey { .. } => (HttpResponse::BadRequest(), "invalid-key"),
+            InvalidCommitId { .. } => (HttpResponse::BadRequest(), "invalid-commit-id"),
+            InvalidWatchParameter { .. } => (HttpResponse::BadRequest(), "invalid-watch-parameter"),
+            InvalidFlag { .. } => (HttpResponse::BadRequest(), "invalid-flag"),
+            InvalidSnapshotFormat { .. } => (HttpResponse::BadRequest(), "invalid-snapshot-format"),
+            SnapshotParseJson { .. } => (HttpResponse::BadRequest(), "snapshot-parse"),
+            SnapshotParseToml { .. } => (HttpResponse::BadRequest(), "snapshot-parse"),
+            DataStore {
+                source: datastore::Error::InvalidTransaction { .. },
+                ..
+            } => (HttpResponse::BadRequest(), "invalid-transaction"),
+            DataStore {
+                source: datastore::Error::InvalidKey { .. },
+                ..
+            }
+            | DataStore {
+                source: datastore::Error::KeyTooLong { .. },
+                ..
+            }
+            | DataStore {
+                source: datastore::Error::KeyPathTooLong { .. },
+                ..
+            } => (HttpResponse::BadRequest(), "invalid-key"),
+
+            // 403 Forbidden
+            Forbidden { .. } => (HttpResponse::Forbidden(), "forbidden"),
 
             // 404 Not Found
-            MissingData { .. } => HttpResponse::NotFound(),
-            ListKeys { .. } => HttpResponse::NotFound(),
+            MissingData { .. } => (HttpResponse::NotFound(), "missing-data"),
+            ListKeys { .. } => (HttpResponse::NotFound(), "missing-keys"),
+            HistoryMissing { .. } => (HttpResponse::NotFound(), "history-missing"),
+            UpdateDoesNotExist => (HttpResponse::NotFound(), "update-does-not-exist"),
+            NoStagedImage => (HttpResponse::NotFound(), "no-staged-image"),
+            UpdateStatusRead {
+                source: thar_be_updates::error::Error::NoStatusFile { .. },
+            } => (HttpResponse::NotFound(), "update-status-missing"),
+
+            // 409 Conflict
+            DisallowCommand { .. } => (HttpResponse::Conflict(), "disallowed-command"),
+            SnapshotVersion { .. } => (HttpResponse::Conflict(), "snapshot-version"),
+            RollbackUnset { .. } => (HttpResponse::Conflict(), "rollback-unset"),
+            DataStore {
+                source: datastore::Error::SnapshotTransaction { .. },
+                ..
+            } => (HttpResponse::Conflict(), "snapshot-transaction"),
+
+            // 410 Gone
+            WatchMissedChanges { .. } => (HttpResponse::Gone(), "watch-missed-changes"),
 
             // 422 Unprocessable Entity
-            CommitWithNoPending => HttpResponse::UnprocessableEntity(),
+            CommitWithNoPending => (HttpResponse::UnprocessableEntity(), "commit-with-no-pending"),
+
+            // 423 Locked
+            UpdateLockHeld => (HttpResponse::build(StatusCode::LOCKED), "update-lock-held"),
+
+            // 503 Service Unavailable
+            TooManyWatchers { .. } => (HttpResponse::ServiceUnavailable(), "too-many-watchers"),
 
             // 500 Internal Server Error
-            DataStoreLock => HttpResponse::InternalServerError(),
-            ResponseSerialization { .. } => HttpResponse::InternalServerError(),
-            BindSocket { .. } => HttpResponse::InternalServerError(),
-            ServerStart { .. } => HttpResponse::InternalServerError(),
-            ListedKeyNotPresent { .. } => HttpResponse::InternalServerError(),
-            DataStore { .. } => HttpResponse::InternalServerError(),
-            Deserialization { .. } => HttpResponse::InternalServerError(),
-            DataStoreSerialization { .. } => HttpResponse::InternalServerError(),
-            CommandSerialization { .. } => HttpResponse::InternalServerError(),
-            InvalidMetadata { .. } => HttpResponse::InternalServerError(),
-            ConfigApplierStart { .. } => HttpResponse::InternalServerError(),
-            ConfigApplierStdin {} => HttpResponse::InternalServerError(),
-            ConfigApplierWrite { .. } => HttpResponse::InternalServerError(),
-            SystemdNotify { .. } => HttpResponse::InternalServerError(),
-            SystemdNotifyStatus {} => HttpResponse::InternalServerError(),
-        }
-        .finish()
+            DataStoreLock => (HttpResponse::InternalServerError(), "data-store-lock"),
+            ResponseSerialization { .. } => {
+                (HttpResponse::InternalServerError(), "response-serialization")
+            }
+            BindSocket { .. } => (HttpResponse::InternalServerError(), "bind-socket"),
+            ServerStart { .. } => (HttpResponse::InternalServerError(), "server-start"),
+            ListedKeyNotPresent { .. } => {
+                (HttpResponse::InternalServerError(), "listed-key-not-present")
+            }
+            DataStore { .. } => (HttpResponse::InternalServerError(), "data-store"),
+            Deserialization { .. } => (HttpResponse::InternalServerError(), "deserialization"),
+            DataStoreSerialization { .. } => {
+                (HttpResponse::InternalServerError(), "data-store-serialization")
+            }
+            CommandSerialization { .. } => {
+                (HttpResponse::InternalServerError(), "command-serialization")
+            }
+            InvalidMetadata { .. } => (HttpResponse::InternalServerError(), "invalid-metadata"),
+            ConfigApplierStart { .. } => {
+                (HttpResponse::InternalServerError(), "config-applier-start")
+            }
+            ConfigApplierStdin {} => (HttpResponse::InternalServerError(), "config-applier-stdin"),
+            ConfigApplierWrite { .. } => {
+                (HttpResponse::InternalServerError(), "config-applier-write")
+            }
+            ConfigApplierWait { .. } => {
+                (HttpResponse::InternalServerError(), "config-applier-wait")
+            }
+            ConfigApplierFailed { .. } => {
+                (HttpResponse::InternalServerError(), "config-applier-failed")
+            }
+            ConfigApplierOutput { .. } => {
+                (HttpResponse::InternalServerError(), "config-applier-output")
+            }
+            Canceled => (HttpResponse::InternalServerError(), "canceled"),
+            SystemdNotify { .. } => (HttpResponse::InternalServerError(), "systemd-notify"),
+            SystemdNotifyStatus {} => {
+                (HttpResponse::InternalServerError(), "systemd-notify-status")
+            }
+            HistoryLock => (HttpResponse::InternalServerError(), "history-lock"),
+            HistoryRead { .. } => (HttpResponse::InternalServerError(), "history-read"),
+            HistoryParse { .. } => (HttpResponse::InternalServerError(), "history-parse"),
+            HistorySerialize { .. } => (HttpResponse::InternalServerError(), "history-serialize"),
+            HistoryWrite { .. } => (HttpResponse::InternalServerError(), "history-write"),
+            HistoryPersist { .. } => (HttpResponse::InternalServerError(), "history-persist"),
+            HistoryValue { .. } => (HttpResponse::InternalServerError(), "history-value"),
+            WatchLock => (HttpResponse::InternalServerError(), "watch-lock"),
+            WatchSerialization { .. } => {
+                (HttpResponse::InternalServerError(), "watch-serialization")
+            }
+            PolicyRead { .. } => (HttpResponse::InternalServerError(), "policy-read"),
+            PolicyParse { .. } => (HttpResponse::InternalServerError(), "policy-parse"),
+            PolicyInvalid { .. } => (HttpResponse::InternalServerError(), "policy-invalid"),
+            ReleaseVersion { .. } => (HttpResponse::InternalServerError(), "release-version"),
+            PartitionTableRead { .. } => {
+                (HttpResponse::InternalServerError(), "partition-table-read")
+            }
+            UpdateDispatcherStart { .. } => {
+                (HttpResponse::InternalServerError(), "update-dispatcher-start")
+            }
+            UpdateLock { .. } => (HttpResponse::InternalServerError(), "update-lock"),
+            UpdateCommandFailed { .. } => {
+                (HttpResponse::InternalServerError(), "update-command-failed")
+            }
+            UpdateStatusRead { .. } => (HttpResponse::InternalServerError(), "update-status-read"),
+            RebootStart { .. } => (HttpResponse::InternalServerError(), "reboot-start"),
+            RebootFailed { .. } => (HttpResponse::InternalServerError(), "reboot-failed"),
+            SnapshotSerializeToml { .. } => {
+                (HttpResponse::InternalServerError(), "snapshot-serialize")
+            }
+            DataStoreVersion { .. } => (HttpResponse::InternalServerError(), "data-store-version"),
+        };
+
+        let (path, expected) = match self {
+            InvalidSettings { path, expected, .. } => (Some(path.as_str()), expected.as_deref()),
+            _ => (None, None),
+        };
+        response.json(ErrorBody {
+            code,
+            message: self.to_string(),
+            path,
+            expected,
+        })
     }
 }
 
 struct SharedDataStore {
-    ds: sync::RwLock<FilesystemDataStore>,
+    ds: sync::RwLock<AnyDataStore>,
+    history: sync::RwLock<History>,
+    watcher: Watcher,
+    policy: Option<Policy>,
 }
 
 /// Helper macro for implementing the actix-web Responder trait for a type.
@@ -304,8 +1123,10 @@ macro_rules! impl_responder_for {
     )
 }
 
-// This lets us respond from our handler methods with a Settings (or Result<Settings>)
-impl_responder_for!(Settings, self, self);
+/// This lets us respond from our handler methods with Settings, which may have had secret values
+/// masked
+struct SettingsResponse(Value);
+impl_responder_for!(SettingsResponse, self, self.0);
 
 /// This lets us respond from our handler methods with a HashMap (or Result<HashMap>) for metadata
 struct MetadataResponse(HashMap<String, Value>);
@@ -321,4 +1142,77 @@ struct ConfigurationFilesResponse(ConfigurationFiles);
 impl_responder_for!(ConfigurationFilesResponse, self, self.0);
 
 struct ChangedKeysResponse(HashSet<Key>);
-impl_responder_for!(ChangedKeysResponse, self, self.0);
\ No newline at end of file
+impl_responder_for!(ChangedKeysResponse, self, self.0);
+
+/// This lets us respond from our handler methods with the settings history
+struct HistoryResponse(Vec<HistoryEntry>);
+impl_responder_for!(HistoryResponse, self, self.0);
+
+/// This lets us respond from our handler methods with the result of a settings watch
+struct WatchResponse(WatchChanges);
+impl_responder_for!(WatchResponse, self, self.0);
+
+/// This lets us respond from our handler methods with OS information
+struct OsInfoResponse(OsInfo);
+impl_responder_for!(OsInfoResponse, self, self.0);
+
+/// This lets us respond from our handler methods with the update status
+struct UpdateStatusResponse(UpdateStatus);
+impl_responder_for!(UpdateStatusResponse, self, self.0);
+
+/// This lets us respond from our handler methods with a list of transaction names
+struct TransactionListResponse(HashSet<String>);
+impl_responder_for!(TransactionListResponse, self, self.0);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deserialize_settings_ok() {
+        let settings = deserialize_settings(br#"{"hostname": "abc"}"#).unwrap();
+        assert_eq!(settings.hostname.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn deserialize_settings_invalid_value() {
+        match deserialize_settings(br#"{"timezone": "Mars/Olympus_Mons"}"#).unwrap_err() {
+            error::Error::InvalidSettings { path, .. } => assert_eq!(path, "settings.timezone"),
+            e => panic!("Unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn deserialize_settings_wrong_type() {
+        match deserialize_settings(br#"{"kubernetes": {"cluster-name": 1}}"#).unwrap_err() {
+            error::Error::InvalidSettings { path, expected, .. } => {
+                assert_eq!(path, "settings.kubernetes.cluster-name");
+                assert_eq!(expected, Some("a string".to_string()));
+            }
+            e => panic!("Unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn deserialize_settings_unknown_field() {
+        match deserialize_settings(br#"{"kubernetes": {"no-such-field": "x"}}"#).unwrap_err() {
+            error::Error::InvalidSettings { path, .. } => {
+                assert!(path.starts_with("settings.kubernetes"), "path was {}", path)
+            }
+            e => panic!("Unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn expected_type_parsing() {
+        assert_eq!(
+            expected_type("invalid type: integer `1`, expected a string at line 1 column 20"),
+            Some("a string".to_string())
+        );
+        assert_eq!(
+            expected_type("unknown field `x`, expected one of `a`, `b`"),
+            Some("one of `a`, `b`".to_string())
+        );
+        assert_eq!(expected_type("Invalid base64 input"), None);
+    }
+}
@@ -0,0 +1,56 @@
+//! The os module gathers information about the running OS: its release, and the partition sets it
+//! booted from and will boot next.
+
+use gilmanos_release::GilmanosRelease;
+use serde::Serialize;
+use signpost::{PartitionSet, State};
+use snafu::ResultExt;
+use std::path::PathBuf;
+
+use crate::server::error::{self, Result};
+
+/// The partitions that make up a partition set.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Partitions {
+    boot: PathBuf,
+    root: PathBuf,
+    hash: PathBuf,
+}
+
+impl From<&PartitionSet> for Partitions {
+    fn from(set: &PartitionSet) -> Self {
+        Self {
+            boot: set.boot.clone(),
+            root: set.root.clone(),
+            hash: set.hash.clone(),
+        }
+    }
+}
+
+/// Release information for the running OS, and its partition sets.  The next partition set is
+/// None if no partition set is marked to boot.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct OsInfo {
+    arch: String,
+    version_id: String,
+    variant_id: String,
+    build_id: String,
+    active_partition: Partitions,
+    next_partition: Option<Partitions>,
+}
+
+/// Reads the OS release information and the partition table of the OS disk.
+pub(crate) fn get_os_info() -> Result<OsInfo> {
+    let release = GilmanosRelease::new().context(error::ReleaseVersion)?;
+    let state = State::load().context(error::PartitionTableRead)?;
+
+    Ok(OsInfo {
+        arch: release.arch,
+        version_id: release.version_id.to_string(),
+        variant_id: release.variant_id,
+        build_id: release.build_id,
+        active_partition: state.active_set().into(),
+        next_partition: state.next_set().map(Partitions::from),
+    })
+}
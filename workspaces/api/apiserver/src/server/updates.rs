@@ -0,0 +1,96 @@
+//! The updates module runs host actions for the /actions endpoints: it starts update commands
+//! with thar-be-updates, reads the update status thar-be-updates keeps, and reboots the host.
+//!
+//! thar-be-updates holds UPDATE_LOCKFILE while a command is in flight.  It returns once a command
+//! has started, leaving long-running work in the background, and exits with a TbuErrorStatus code
+//! if it can't run the command, so we can tell the caller why.  The status is read without the
+//! lock, since callers poll it while commands are in flight.
+
+use fs2::FileExt;
+use num_traits::FromPrimitive;
+use snafu::{ensure, ResultExt};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, Stdio};
+use thar_be_updates::error::TbuErrorStatus;
+use thar_be_updates::status::{UpdateCommand, UpdateStatus, UPDATE_LOCKFILE};
+
+use crate::server::error::{self, Result};
+
+/// Starts the given update command with thar-be-updates.
+pub(crate) fn dispatch_update_command(command: UpdateCommand) -> Result<()> {
+    let arg = match command {
+        UpdateCommand::Refresh => "refresh",
+        UpdateCommand::Prepare => "prepare",
+        UpdateCommand::Activate => "activate",
+        UpdateCommand::Deactivate => "deactivate",
+    };
+
+    // thar-be-updates leaves a child running for long commands; if we captured its output, we'd
+    // wait for that child too.
+    debug!("Launching thar-be-updates to {}", arg);
+    let status = Command::new("/usr/bin/thar-be-updates")
+        .arg(arg)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .context(error::UpdateDispatcherStart)?;
+    if status.success() {
+        return Ok(());
+    }
+
+    let code = status.code().or_else(|| status.signal()).unwrap_or(1);
+    match TbuErrorStatus::from_i32(code) {
+        Some(TbuErrorStatus::UpdateLockHeld) => error::UpdateLockHeld.fail(),
+        Some(TbuErrorStatus::DisallowCommand) => error::DisallowCommand { command: arg }.fail(),
+        Some(TbuErrorStatus::UpdateDoesNotExist) => error::UpdateDoesNotExist.fail(),
+        Some(TbuErrorStatus::NoStagedImage) => error::NoStagedImage.fail(),
+        _ => error::UpdateCommandFailed { command: arg, code }.fail(),
+    }
+}
+
+/// Returns the update status last written by thar-be-updates.
+pub(crate) fn get_update_status() -> Result<UpdateStatus> {
+    thar_be_updates::status::get_update_status().context(error::UpdateStatusRead)
+}
+
+/// Reboots the host, unless an update command is in flight.
+pub(crate) fn reboot() -> Result<()> {
+    // Rebooting in the middle of an update command could leave a partition set half-written.
+    let lockfile = open_lockfile()?;
+    lock_result(lockfile.try_lock_shared())?;
+
+    info!("Rebooting");
+    let output = Command::new("/sbin/shutdown")
+        .args(&["-r", "now"])
+        .output()
+        .context(error::RebootStart)?;
+    ensure!(
+        output.status.success(),
+        error::RebootFailed {
+            stderr: String::from_utf8_lossy(&output.stderr),
+        }
+    );
+    Ok(())
+}
+
+/// Opens the update lock file, creating it if needed.  We don't truncate it; thar-be-updates
+/// owns its contents.
+fn open_lockfile() -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(UPDATE_LOCKFILE)
+        .context(error::UpdateLock)
+}
+
+/// Maps the result of trying to take the update lock to our errors; failing because the lock is
+/// held means an update command is in flight.
+fn lock_result(result: io::Result<()>) -> Result<()> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => error::UpdateLockHeld.fail(),
+        Err(e) => Err(e).context(error::UpdateLock),
+    }
+}
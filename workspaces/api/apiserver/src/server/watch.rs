@@ -0,0 +1,254 @@
+//! The watch module lets requests wait for commits that change settings they care about.
+//!
+//! Each commit that changes keys bumps a revision number, and we keep the changed keys for the
+//! most recent revisions so a client that passes back the last revision it saw doesn't miss any
+//! changes between requests.  Revisions only live in memory, so they start over when the server
+//! restarts; clients are told to start over when they give a revision we don't know.
+//!
+//! Waiting blocks a thread from the server's blocking pool, which is shared with update actions,
+//! dry runs, and imports, so only MAX_WATCHERS requests can watch at once.
+
+use serde::Serialize;
+use snafu::{ensure, OptionExt};
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::datastore::Key;
+use crate::server::error::{self, Result};
+
+/// The number of recent revisions whose changed keys we keep.
+const MAX_REVISIONS: usize = 100;
+
+/// The number of requests that can watch at once.  The blocking pool has five threads per CPU,
+/// so this leaves at least one free for other work on a single-CPU host.
+const MAX_WATCHERS: usize = 4;
+
+/// The response to a watch: the latest revision, and the keys matching the watch that changed
+/// since the requested revision.  If the watch timed out, changed_keys is empty.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct WatchChanges {
+    pub(crate) revision: u64,
+    pub(crate) changed_keys: HashSet<Key>,
+}
+
+#[derive(Debug, Default)]
+struct WatchState {
+    revision: u64,
+    // Changed keys for the most recent revisions, oldest first; the last is for `revision`.
+    changes: VecDeque<HashSet<Key>>,
+}
+
+impl WatchState {
+    /// Returns the keys starting with the given prefix that changed after the given revision.
+    fn changes_since(&self, since: u64, prefix: &str) -> HashSet<Key> {
+        let count = (self.revision - since) as usize;
+        self.changes
+            .iter()
+            .skip(self.changes.len() - count)
+            .flatten()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Watcher {
+    state: Mutex<WatchState>,
+    changed: Condvar,
+    // The number of outstanding WatchSlots.
+    watchers: Arc<AtomicUsize>,
+}
+
+/// A reservation for one of the MAX_WATCHERS watches; it's released when dropped.
+#[derive(Debug)]
+pub(crate) struct WatchSlot {
+    watchers: Arc<AtomicUsize>,
+}
+
+impl Drop for WatchSlot {
+    fn drop(&mut self) {
+        self.watchers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Watcher {
+    pub(crate) fn new() -> Self {
+        Default::default()
+    }
+
+    /// Reserves a slot for a request that will watch for changes, or fails if MAX_WATCHERS
+    /// requests are already watching.  Hold the slot for as long as the request is watching.
+    pub(crate) fn reserve(&self) -> Result<WatchSlot> {
+        let previous = self.watchers.fetch_add(1, Ordering::SeqCst);
+        // Build the slot first so it releases our increment if we're over the limit.
+        let slot = WatchSlot {
+            watchers: self.watchers.clone(),
+        };
+        ensure!(
+            previous < MAX_WATCHERS,
+            error::TooManyWatchers { max: MAX_WATCHERS }
+        );
+        Ok(slot)
+    }
+
+    /// Returns the latest revision.
+    pub(crate) fn revision(&self) -> Result<u64> {
+        let state = self.state.lock().ok().context(error::WatchLock)?;
+        Ok(state.revision)
+    }
+
+    /// Records a commit that changed the given keys, and wakes anyone waiting for changes.
+    /// Returns the new revision.
+    pub(crate) fn notify(&self, changed_keys: &HashSet<Key>) -> Result<u64> {
+        let mut state = self.state.lock().ok().context(error::WatchLock)?;
+        state.revision += 1;
+        state.changes.push_back(changed_keys.clone());
+        if state.changes.len() > MAX_REVISIONS {
+            state.changes.pop_front();
+        }
+        let revision = state.revision;
+        drop(state);
+
+        self.changed.notify_all();
+        Ok(revision)
+    }
+
+    /// Waits until keys starting with the given prefix have changed after the given revision, or
+    /// after the latest revision if none is given.  Returns immediately if they already have.
+    /// If the timeout passes first, returns the latest revision with no changed keys.
+    pub(crate) fn wait(
+        &self,
+        prefix: &str,
+        since: Option<u64>,
+        timeout: Duration,
+    ) -> Result<WatchChanges> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().ok().context(error::WatchLock)?;
+
+        // Make sure we still know about every revision the caller hasn't seen.
+        let since = since.unwrap_or(state.revision);
+        let oldest = state.revision - state.changes.len() as u64;
+        ensure!(
+            since <= state.revision && since >= oldest,
+            error::WatchMissedChanges {
+                since,
+                revision: state.revision,
+            }
+        );
+
+        loop {
+            let changed_keys = state.changes_since(since, prefix);
+            let now = Instant::now();
+            if !changed_keys.is_empty() || now >= deadline {
+                return Ok(WatchChanges {
+                    revision: state.revision,
+                    changed_keys,
+                });
+            }
+
+            state = self
+                .changed
+                .wait_timeout(state, deadline - now)
+                .ok()
+                .context(error::WatchLock)?
+                .0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::datastore::KeyType;
+    use maplit::hashset;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn key(name: &str) -> Key {
+        Key::new(KeyType::Data, name).unwrap()
+    }
+
+    #[test]
+    fn changes_since_revision() {
+        let watcher = Watcher::new();
+        watcher.notify(&hashset!(key("settings.a.x"))).unwrap();
+        watcher.notify(&hashset!(key("settings.b.x"))).unwrap();
+        watcher.notify(&hashset!(key("settings.a.y"))).unwrap();
+
+        // Changes after the requested revision are returned right away
+        let changes = watcher
+            .wait("settings.a", Some(1), Duration::from_secs(0))
+            .unwrap();
+        assert_eq!(
+            changes,
+            WatchChanges {
+                revision: 3,
+                changed_keys: hashset!(key("settings.a.y")),
+            }
+        );
+
+        // Nothing matching changed after revision 1 under this prefix
+        let changes = watcher
+            .wait("settings.c", Some(1), Duration::from_secs(0))
+            .unwrap();
+        assert!(changes.changed_keys.is_empty());
+        assert_eq!(changes.revision, 3);
+    }
+
+    #[test]
+    fn wakes_on_change() {
+        let watcher = Arc::new(Watcher::new());
+        let since = watcher.revision().unwrap();
+        let waiter = {
+            let watcher = watcher.clone();
+            thread::spawn(move || {
+                watcher
+                    .wait("settings.a", Some(since), Duration::from_secs(30))
+                    .unwrap()
+            })
+        };
+
+        // Only the matching change should be returned, whether or not the waiter had started
+        // waiting before the first change
+        watcher.notify(&hashset!(key("settings.b"))).unwrap();
+        watcher.notify(&hashset!(key("settings.a"))).unwrap();
+
+        let changes = waiter.join().unwrap();
+        assert_eq!(changes.changed_keys, hashset!(key("settings.a")));
+    }
+
+    #[test]
+    fn limited_watchers() {
+        let watcher = Watcher::new();
+        let slots: Vec<_> = (0..MAX_WATCHERS).map(|_| watcher.reserve().unwrap()).collect();
+        watcher.reserve().unwrap_err();
+
+        // Dropping a slot, including the one from a failed reservation, frees it up
+        drop(slots);
+        let _slot = watcher.reserve().unwrap();
+        assert_eq!(watcher.watchers.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn missed_changes() {
+        let watcher = Watcher::new();
+        for _ in 0..MAX_REVISIONS + 1 {
+            watcher.notify(&hashset!(key("settings.a"))).unwrap();
+        }
+
+        // Revision 0's successor has been dropped, and revisions from the future are unknown
+        watcher
+            .wait("settings.", Some(0), Duration::from_secs(0))
+            .unwrap_err();
+        watcher
+            .wait("settings.", Some(1000), Duration::from_secs(0))
+            .unwrap_err();
+        watcher
+            .wait("settings.", Some(1), Duration::from_secs(0))
+            .unwrap();
+    }
+}
@@ -0,0 +1,321 @@
+/*!
+# Background
+corndog is a simple kernel settings applier.
+thar-be-settings calls it as a restart-command when kernel settings change, so changes take effect on commit without a reboot.
+thar-be-settings also runs every restart-command at boot, which is how the settings are applied to a freshly booted kernel.
+
+It's given the group of settings to apply:
+* `corndog sysctl` writes each value in `settings.kernel.sysctl` to the matching file under `/proc/sys`.
+A sysctl removed from the settings keeps its current value until reboot.
+* `corndog modules` unloads each module in `settings.kernel.modules` that has `allowed` set to false, and loads each module that has `autoload` set to true.
+The modprobe configuration rendered by thar-be-settings keeps modules that aren't allowed from being loaded again.
+
+If a setting can't be applied, corndog logs the error and moves on to the rest, then exits with an error at the end.
+*/
+use snafu::{ensure, ResultExt};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use apiserver::model;
+use apiserver::modeled_types::{KmodKey, SysctlKey};
+
+#[macro_use]
+extern crate log;
+
+// FIXME Get from configuration in the future
+const DEFAULT_API_SOCKET: &str = "/run/api.sock";
+const API_SETTINGS_URI: &str = "/settings";
+
+const SYSCTL_DIR: &str = "/proc/sys";
+const SYSFS_MODULE_DIR: &str = "/sys/module";
+const MODPROBE_BIN: &str = "/usr/bin/modprobe";
+
+mod error {
+    use http::StatusCode;
+    use snafu::Snafu;
+    use std::path::PathBuf;
+    use std::process::{Command, Output};
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Logger setup error: {}", source))]
+        Logger { source: log::SetLoggerError },
+
+        #[snafu(display("Error sending {} to {}: {}", method, uri, source))]
+        APIRequest {
+            method: String,
+            uri: String,
+            source: apiclient::Error,
+        },
+
+        #[snafu(display("Error {} when sending {} to {}: {}", code, method, uri, response_body))]
+        APIResponse {
+            method: String,
+            uri: String,
+            code: StatusCode,
+            response_body: String,
+        },
+
+        #[snafu(display(
+            "Error deserializing response as JSON from {} to {}: {}",
+            method,
+            uri,
+            source
+        ))]
+        ResponseJson {
+            method: &'static str,
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to write sysctl '{}' to {}: {}", name, path.display(), source))]
+        SysctlWrite {
+            name: String,
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to execute '{:?}': {}", command, source))]
+        ExecutionFailure {
+            command: Command,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("modprobe failed - stderr: {}",
+                        std::str::from_utf8(&output.stderr).unwrap_or_else(|_| "<invalid UTF-8>")))]
+        ModprobeFailure { output: Output },
+
+        #[snafu(display("Failed to apply {} of the {} settings", count, group))]
+        ApplyFailures { count: usize, group: &'static str },
+    }
+}
+
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// Query the API for the kernel settings.  Returns None if none are set.
+fn get_kernel_settings<P>(socket_path: P) -> Result<Option<model::KernelSettings>>
+where
+    P: AsRef<Path>,
+{
+    debug!("Querying the API for kernel settings");
+
+    let uri = format!("{}?prefix=kernel", API_SETTINGS_URI);
+    let (code, response_body) =
+        apiclient::raw_request(socket_path, &uri, "GET", None).context(error::APIRequest {
+            method: "GET",
+            uri: uri.to_string(),
+        })?;
+    ensure!(
+        code.is_success(),
+        error::APIResponse {
+            method: "GET",
+            uri,
+            code,
+            response_body,
+        }
+    );
+
+    let settings: model::Settings =
+        serde_json::from_str(&response_body).context(error::ResponseJson { method: "GET", uri })?;
+    Ok(settings.kernel)
+}
+
+/// Returns the path under /proc/sys for the given sysctl.  Dots separate the components of the
+/// name, and slashes stand in for dots within a component, so "net.ipv4.conf.eth0/1.rp_filter"
+/// is "/proc/sys/net/ipv4/conf/eth0.1/rp_filter".
+fn sysctl_path(name: &SysctlKey) -> PathBuf {
+    let mut path = PathBuf::from(SYSCTL_DIR);
+    path.extend(name.split('.').map(|component| component.replace('/', ".")));
+    path
+}
+
+/// Writes each sysctl value to /proc/sys, and returns the number that failed.
+fn apply_sysctls(sysctls: &HashMap<SysctlKey, String>) -> usize {
+    let mut failures = 0;
+    for (name, value) in sysctls {
+        let path = sysctl_path(name);
+        info!("Setting sysctl {} to '{}'", name, value);
+        if let Err(e) = fs::write(&path, value).context(error::SysctlWrite {
+            name: name.as_ref(),
+            path: &path,
+        }) {
+            error!("{}", e);
+            failures += 1;
+        }
+    }
+    failures
+}
+
+/// Unloads modules that aren't allowed and loads those that should be, and returns the number
+/// of modules we failed to act on.
+fn apply_modules(modules: &HashMap<KmodKey, model::KmodSetting>) -> usize {
+    let mut failures = 0;
+    for (name, setting) in modules {
+        let result = if setting.allowed == Some(false) {
+            // modprobe fails to remove a module that isn't loaded, so only try if it is.
+            if Path::new(SYSFS_MODULE_DIR).join(name.as_ref()).exists() {
+                info!("Unloading kernel module {}", name);
+                modprobe(&["--remove", name.as_ref()])
+            } else {
+                Ok(())
+            }
+        } else if setting.autoload == Some(true) {
+            info!("Loading kernel module {}", name);
+            modprobe(&[name.as_ref()])
+        } else {
+            Ok(())
+        };
+
+        if let Err(e) = result {
+            error!("Kernel module {}: {}", name, e);
+            failures += 1;
+        }
+    }
+    failures
+}
+
+/// Wrapper around process::Command that runs modprobe and does error handling.
+fn modprobe(args: &[&str]) -> Result<()> {
+    let mut command = Command::new(MODPROBE_BIN);
+    command.args(args);
+    let output = command
+        .output()
+        .context(error::ExecutionFailure { command })?;
+
+    ensure!(output.status.success(), error::ModprobeFailure { output });
+    Ok(())
+}
+
+/// The groups of kernel settings we know how to apply
+enum Group {
+    Sysctl,
+    Modules,
+}
+
+/// Store the args we receive on the command line
+struct Args {
+    group: Group,
+    socket_path: String,
+    verbosity: usize,
+}
+
+/// Print a usage message in the event a bad arg is passed
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {} (sysctl | modules)
+            [ --socket-path PATH ]
+            [ --verbose --verbose ... ]
+
+    sysctl: apply settings.kernel.sysctl
+    modules: apply settings.kernel.modules",
+        program_name
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parse the args to the program and return an Args struct
+fn parse_args(args: env::Args) -> Args {
+    let mut group = None;
+    let mut socket_path = None;
+    let mut verbosity = 2;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "-v" | "--verbose" => verbosity += 1,
+
+            "--socket-path" => {
+                socket_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
+                )
+            }
+
+            "sysctl" => group = Some(Group::Sysctl),
+            "modules" => group = Some(Group::Modules),
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        group: group.unwrap_or_else(|| usage_msg("Must give 'sysctl' or 'modules'")),
+        socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.to_string()),
+        verbosity,
+    }
+}
+
+fn main() -> Result<()> {
+    // Parse and store the args passed to the program
+    let args = parse_args(env::args());
+
+    // TODO Fix this later when we decide our logging story
+    // Start the logger
+    stderrlog::new()
+        .module(module_path!())
+        .timestamp(stderrlog::Timestamp::Millisecond)
+        .verbosity(args.verbosity)
+        .color(stderrlog::ColorChoice::Never)
+        .init()
+        .context(error::Logger)?;
+
+    info!("corndog started");
+
+    let kernel = get_kernel_settings(&args.socket_path)?;
+
+    match args.group {
+        Group::Sysctl => {
+            let sysctls = kernel.and_then(|k| k.sysctl).unwrap_or_default();
+            let count = apply_sysctls(&sysctls);
+            ensure!(
+                count == 0,
+                error::ApplyFailures {
+                    count,
+                    group: "sysctl"
+                }
+            );
+        }
+
+        Group::Modules => {
+            let modules = kernel.and_then(|k| k.modules).unwrap_or_default();
+            let count = apply_modules(&modules);
+            ensure!(
+                count == 0,
+                error::ApplyFailures {
+                    count,
+                    group: "kernel module"
+                }
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn sysctl_paths() {
+        let name = SysctlKey::try_from("vm.max_map_count").unwrap();
+        assert_eq!(sysctl_path(&name), Path::new("/proc/sys/vm/max_map_count"));
+
+        let name = SysctlKey::try_from("net.ipv4.conf.eth0/1.rp_filter").unwrap();
+        assert_eq!(
+            sysctl_path(&name),
+            Path::new("/proc/sys/net/ipv4/conf/eth0.1/rp_filter")
+        );
+    }
+}
@@ -0,0 +1,151 @@
+/*!
+# Background
+This library provides FriendlyVersion, the type of the `settings.updates.version-lock` setting.
+The API server uses it to validate the setting, and thar-be-updates uses it to choose an update,
+so it lives here where both can share it.
+
+A FriendlyVersion is either "latest" or a semver version, like "1.2.0", which may start with a
+"v" like "v1.2.0".
+*/
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use snafu::ResultExt;
+use std::borrow::Borrow;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Deref;
+
+pub mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Invalid version '{}', expected 'latest' or a semver: {}", input, source))]
+        InvalidVersion {
+            input: String,
+            source: semver::SemVerError,
+        },
+    }
+}
+
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// FriendlyVersion can only be created from "latest" or a semver version, like "1.2.0", which may
+/// start with a "v" like "v1.2.0".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct FriendlyVersion {
+    inner: String,
+}
+
+impl FriendlyVersion {
+    pub const LATEST: &'static str = "latest";
+
+    /// Returns whether this is "latest" rather than a specific version.
+    pub fn is_latest(&self) -> bool {
+        self.inner == Self::LATEST
+    }
+}
+
+impl TryFrom<&str> for FriendlyVersion {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self> {
+        if input != Self::LATEST {
+            semver::Version::parse(input.trim_start_matches('v'))
+                .context(error::InvalidVersion { input })?;
+        }
+        Ok(FriendlyVersion {
+            inner: input.to_string(),
+        })
+    }
+}
+
+/// Converts to a semver version, which fails for "latest".
+impl TryFrom<FriendlyVersion> for semver::Version {
+    type Error = semver::SemVerError;
+
+    fn try_from(input: FriendlyVersion) -> std::result::Result<Self, Self::Error> {
+        semver::Version::parse(input.inner.trim_start_matches('v'))
+    }
+}
+
+impl From<FriendlyVersion> for String {
+    fn from(input: FriendlyVersion) -> Self {
+        input.inner
+    }
+}
+
+impl<'de> Deserialize<'de> for FriendlyVersion {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let original = String::deserialize(deserializer)?;
+        Self::try_from(original.as_str()).map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for FriendlyVersion {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.inner)
+    }
+}
+
+impl Deref for FriendlyVersion {
+    type Target = str;
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl Borrow<String> for FriendlyVersion {
+    fn borrow(&self) -> &String {
+        &self.inner
+    }
+}
+
+impl Borrow<str> for FriendlyVersion {
+    fn borrow(&self) -> &str {
+        &self.inner
+    }
+}
+
+impl AsRef<str> for FriendlyVersion {
+    fn as_ref(&self) -> &str {
+        &self.inner
+    }
+}
+
+impl fmt::Display for FriendlyVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.inner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FriendlyVersion;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn friendly_version() {
+        for ok in &["latest", "1.2.0", "v1.2.0", "0.3.1-rc1"] {
+            FriendlyVersion::try_from(*ok).unwrap();
+        }
+        for bad in &["", "Latest", "1.2", "v", "newest"] {
+            FriendlyVersion::try_from(*bad).unwrap_err();
+        }
+
+        let latest = FriendlyVersion::try_from("latest").unwrap();
+        assert!(latest.is_latest());
+        semver::Version::try_from(latest).unwrap_err();
+        let version = FriendlyVersion::try_from("v1.2.0").unwrap();
+        assert!(!version.is_latest());
+        assert_eq!(semver::Version::try_from(version).unwrap(), semver::Version::new(1, 2, 0));
+    }
+}
@@ -0,0 +1,438 @@
+/*!
+# Background
+host-containers is the settings applier for host containers.
+thar-be-settings calls it as a restart-command when `settings.host-containers` changes, and at boot.
+
+Each host container named in `settings.host-containers` runs as an instance of the `host-containers@` systemd unit, like `host-containers@admin.service`.
+For each one, host-containers writes:
+* `/etc/host-containers/NAME.env`, the unit's environment file, with the image source and whether the container is superpowered.
+* `/etc/host-containers/NAME/environment`, the `KEY=VALUE` lines host-ctr adds to the container's environment.
+* `/etc/host-containers/NAME/mounts`, the extra bind mounts host-ctr gives the container.
+* `/local/host-containers/NAME/user-data`, the decoded user data, in the container's persistent storage.
+
+It then starts and enables the unit if the container is enabled, restarting it if any of those files changed, or stops and disables the unit if it isn't.
+
+If a container can't be handled, host-containers logs the error and moves on to the rest, then exits with an error at the end.
+*/
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+use std::process::{self, Command};
+
+use apiserver::model;
+use apiserver::modeled_types::HostContainerName;
+
+#[macro_use]
+extern crate log;
+
+// FIXME Get from configuration in the future
+const DEFAULT_API_SOCKET: &str = "/run/api.sock";
+const API_SETTINGS_URI: &str = "/settings";
+
+const CONFIG_DIR: &str = "/etc/host-containers";
+const PERSISTENT_STORAGE_DIR: &str = "/local/host-containers";
+const SYSTEMCTL_BIN: &str = "/bin/systemctl";
+
+mod error {
+    use http::StatusCode;
+    use snafu::Snafu;
+    use std::path::PathBuf;
+    use std::process::{Command, Output};
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Logger setup error: {}", source))]
+        Logger { source: log::SetLoggerError },
+
+        #[snafu(display("Error sending {} to {}: {}", method, uri, source))]
+        APIRequest {
+            method: String,
+            uri: String,
+            source: apiclient::Error,
+        },
+
+        #[snafu(display("Error {} when sending {} to {}: {}", code, method, uri, response_body))]
+        APIResponse {
+            method: String,
+            uri: String,
+            code: StatusCode,
+            response_body: String,
+        },
+
+        #[snafu(display(
+            "Error deserializing response as JSON from {} to {}: {}",
+            method,
+            uri,
+            source
+        ))]
+        ResponseJson {
+            method: &'static str,
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Host container is enabled but has no source"))]
+        MissingSource,
+
+        #[snafu(display("Invalid base64 in user data: {}", source))]
+        UserData { source: base64::DecodeError },
+
+        #[snafu(display("Failed to create directory {}: {}", path.display(), source))]
+        CreateDir {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to write {}: {}", path.display(), source))]
+        WriteFile {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to remove {}: {}", path.display(), source))]
+        RemoveFile {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to execute '{:?}': {}", command, source))]
+        ExecutionFailure {
+            command: Command,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Systemd command failed - stderr: {}",
+                        std::str::from_utf8(&output.stderr).unwrap_or_else(|_| "<invalid UTF-8>")))]
+        SystemdCommandFailure { output: Output },
+
+        #[snafu(display("Failed to apply settings for {} host containers", count))]
+        ApplyFailures { count: usize },
+    }
+}
+
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// Query the API for the host container settings.
+fn get_host_containers<P>(
+    socket_path: P,
+) -> Result<HashMap<HostContainerName, model::HostContainer>>
+where
+    P: AsRef<Path>,
+{
+    debug!("Querying the API for host container settings");
+
+    let uri = format!("{}?prefix=host-containers", API_SETTINGS_URI);
+    let (code, response_body) = apiclient::raw_request(socket_path, &uri, "GET", None)
+        .context(error::APIRequest {
+            method: "GET",
+            uri: uri.to_string(),
+        })?;
+    ensure!(
+        code.is_success(),
+        error::APIResponse {
+            method: "GET",
+            uri,
+            code,
+            response_body,
+        }
+    );
+
+    let settings: model::Settings =
+        serde_json::from_str(&response_body).context(error::ResponseJson { method: "GET", uri })?;
+    Ok(settings.host_containers.unwrap_or_default())
+}
+
+/// Returns the contents of the systemd environment file for the container's unit.
+fn unit_env(container: &model::HostContainer) -> Result<String> {
+    let source = container.source.as_ref().context(error::MissingSource)?;
+    let superpowered = container.superpowered.unwrap_or(false);
+    Ok(format!(
+        "CTR_SOURCE={}\nCTR_SUPERPOWERED={}\n",
+        source.as_ref(),
+        superpowered
+    ))
+}
+
+/// Returns the KEY=VALUE lines for the container's environment, sorted so the file only changes
+/// when the settings do.
+fn container_env(container: &model::HostContainer) -> String {
+    let mut lines: Vec<String> = container
+        .environment
+        .iter()
+        .flatten()
+        .map(|(name, value)| format!("{}={}\n", name.as_ref(), value.as_ref()))
+        .collect();
+    lines.sort();
+    lines.concat()
+}
+
+/// Returns the container's extra mounts, one per line.
+fn container_mounts(container: &model::HostContainer) -> String {
+    container
+        .mounts
+        .iter()
+        .flatten()
+        .map(|mount| format!("{}\n", mount.as_ref()))
+        .collect()
+}
+
+/// Creates the directory, and any missing parents, with the given mode.
+fn create_dir(path: &Path, mode: u32) -> Result<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(mode)
+        .create(path)
+        .context(error::CreateDir { path })
+}
+
+/// Writes the file with the given mode, unless it already has the given contents.  Returns
+/// whether the file changed.
+fn write_if_changed(path: &Path, contents: &[u8], mode: u32) -> Result<bool> {
+    if fs::read(path).ok().as_ref().map(Vec::as_slice) == Some(contents) {
+        return Ok(false);
+    }
+    debug!("Writing {}", path.display());
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)
+        .and_then(|mut f| f.write_all(contents))
+        .context(error::WriteFile { path })?;
+    Ok(true)
+}
+
+/// Removes the file if it exists.  Returns whether the file changed.
+fn remove_if_exists(path: &Path) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    debug!("Removing {}", path.display());
+    fs::remove_file(path).context(error::RemoveFile { path })?;
+    Ok(true)
+}
+
+/// Writes the files for the container that host-ctr and its unit read at startup, and returns
+/// whether any of them changed.
+fn write_container_files(
+    name: &HostContainerName,
+    container: &model::HostContainer,
+    config_dir: &Path,
+    storage_dir: &Path,
+) -> Result<bool> {
+    let mut changed = false;
+
+    create_dir(config_dir, 0o755)?;
+    let env_path = config_dir.join(format!("{}.env", name.as_ref()));
+    changed |= write_if_changed(&env_path, unit_env(container)?.as_bytes(), 0o644)?;
+
+    // The container's environment can hold secrets, so only root can read it.
+    let ctr_config_dir = config_dir.join(name.as_ref());
+    create_dir(&ctr_config_dir, 0o755)?;
+    let ctr_env_path = ctr_config_dir.join("environment");
+    changed |= write_if_changed(&ctr_env_path, container_env(container).as_bytes(), 0o600)?;
+    let mounts_path = ctr_config_dir.join("mounts");
+    changed |= write_if_changed(&mounts_path, container_mounts(container).as_bytes(), 0o644)?;
+
+    // User data goes in the container's persistent storage, which host-ctr mounts for it.
+    let ctr_storage_dir = storage_dir.join(name.as_ref());
+    create_dir(&ctr_storage_dir, 0o700)?;
+    let user_data_path = ctr_storage_dir.join("user-data");
+    changed |= match &container.user_data {
+        Some(user_data) => {
+            let decoded = base64::decode(user_data.as_ref()).context(error::UserData)?;
+            write_if_changed(&user_data_path, &decoded, 0o600)?
+        }
+        None => remove_if_exists(&user_data_path)?,
+    };
+
+    Ok(changed)
+}
+
+/// Brings the container's unit in line with its settings.
+fn apply_container(name: &HostContainerName, container: &model::HostContainer) -> Result<()> {
+    let unit = format!("host-containers@{}.service", name.as_ref());
+
+    if container.enabled != Some(true) {
+        info!("Stopping and disabling {}", unit);
+        systemctl(&["disable", "--now", &unit])?;
+        return Ok(());
+    }
+
+    let changed = write_container_files(
+        name,
+        container,
+        Path::new(CONFIG_DIR),
+        Path::new(PERSISTENT_STORAGE_DIR),
+    )?;
+
+    systemctl(&["enable", &unit])?;
+    if changed {
+        info!("Settings changed, restarting {}", unit);
+        systemctl(&["restart", "--no-block", &unit])
+    } else {
+        info!("Starting {}", unit);
+        systemctl(&["start", "--no-block", &unit])
+    }
+}
+
+/// Wrapper around process::Command that runs systemctl and does error handling.
+fn systemctl(args: &[&str]) -> Result<()> {
+    let mut command = Command::new(SYSTEMCTL_BIN);
+    command.args(args);
+    let output = command
+        .output()
+        .context(error::ExecutionFailure { command })?;
+
+    ensure!(
+        output.status.success(),
+        error::SystemdCommandFailure { output }
+    );
+    Ok(())
+}
+
+/// Store the args we receive on the command line
+struct Args {
+    socket_path: String,
+    verbosity: usize,
+}
+
+/// Print a usage message in the event a bad arg is passed
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --socket-path PATH ]
+            [ --verbose --verbose ... ]",
+        program_name
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parse the args to the program and return an Args struct
+fn parse_args(args: env::Args) -> Args {
+    let mut socket_path = None;
+    let mut verbosity = 2;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "-v" | "--verbose" => verbosity += 1,
+
+            "--socket-path" => {
+                socket_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
+                )
+            }
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.to_string()),
+        verbosity,
+    }
+}
+
+fn main() -> Result<()> {
+    // Parse and store the args passed to the program
+    let args = parse_args(env::args());
+
+    // TODO Fix this later when we decide our logging story
+    // Start the logger
+    stderrlog::new()
+        .module(module_path!())
+        .timestamp(stderrlog::Timestamp::Millisecond)
+        .verbosity(args.verbosity)
+        .color(stderrlog::ColorChoice::Never)
+        .init()
+        .context(error::Logger)?;
+
+    info!("host-containers started");
+
+    let containers = get_host_containers(&args.socket_path)?;
+
+    let mut count = 0;
+    for (name, container) in &containers {
+        if let Err(e) = apply_container(name, container) {
+            error!("Host container {}: {}", name, e);
+            count += 1;
+        }
+    }
+    ensure!(count == 0, error::ApplyFailures { count });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryFrom;
+    use tempfile::TempDir;
+
+    fn container() -> model::HostContainer {
+        serde_json::from_str(
+            r#"{
+                "source": "example.com/admin:v0.1",
+                "enabled": true,
+                "superpowered": true,
+                "user-data": "aGVsbG8=",
+                "environment": {"B": "2 two", "A": "1"},
+                "mounts": ["/var/log:/host/var/log:ro", "/opt:/opt"]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn file_contents() {
+        let container = container();
+        assert_eq!(
+            unit_env(&container).unwrap(),
+            "CTR_SOURCE=example.com/admin:v0.1\nCTR_SUPERPOWERED=true\n"
+        );
+        assert_eq!(container_env(&container), "A=1\nB=2 two\n");
+        assert_eq!(container_mounts(&container), "/var/log:/host/var/log:ro\n/opt:/opt\n");
+    }
+
+    #[test]
+    fn empty_container() {
+        let container: model::HostContainer = serde_json::from_str("{}").unwrap();
+        unit_env(&container).unwrap_err();
+        assert_eq!(container_env(&container), "");
+        assert_eq!(container_mounts(&container), "");
+    }
+
+    #[test]
+    fn container_files() {
+        let root = TempDir::new().unwrap();
+        let (config_dir, storage_dir) = (root.path().join("etc"), root.path().join("local"));
+        let name = HostContainerName::try_from("admin").unwrap();
+        let mut container = container();
+
+        assert!(write_container_files(&name, &container, &config_dir, &storage_dir).unwrap());
+        assert!(!write_container_files(&name, &container, &config_dir, &storage_dir).unwrap());
+        let user_data_path = storage_dir.join("admin/user-data");
+        assert_eq!(fs::read(&user_data_path).unwrap(), b"hello");
+        assert!(config_dir.join("admin.env").exists());
+        assert!(config_dir.join("admin/environment").exists());
+        assert!(config_dir.join("admin/mounts").exists());
+
+        container.user_data = None;
+        assert!(write_container_files(&name, &container, &config_dir, &storage_dir).unwrap());
+        assert!(!user_data_path.exists());
+    }
+}
@@ -4,11 +4,12 @@ use std::env;
 use std::process;
 
 
-use crate::{MigrationType, Result};
+use crate::{Backend, MigrationType, Result};
 
 /// stores user-supplied arguments
 pub struct Args {
     pub datastore_path: String,
+    pub datastore_backend: Backend,
     pub migration_type: MigrationType,
 }
 
@@ -17,8 +18,11 @@ fn usage() -> ! {
     eprintln!(
         r"Usage: {}
             --datastore-path PATH
-            ( --forward | --backward )",
-        program_name
+            [ --datastore-backend filesystem|sqlite ]
+            ( --forward | --backward )
+    Datastore backend defaults to {}",
+        program_name,
+        Backend::default()
     );
     process::exit(2);
 }
@@ -31,6 +35,7 @@ fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
 pub(crate) fn parse_args(args: env::args) -> Result<Args> {
     let mut migration_type = None;
     let mut datastore_path = None;
+    let mut datastore_backend = None;
 
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
@@ -42,6 +47,14 @@ pub(crate) fn parse_args(args: env::args) -> Result<Args> {
                 )
             }
 
+            "--datastore-backend" => {
+                let backend = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("did not give argument to --datastore-backend"));
+                datastore_backend =
+                    Some(backend.parse().unwrap_or_else(|e| usage_msg(format!("{}", e))))
+            }
+
             "--forward" => migration_type = Some(MigrationType::Forward),
             "--backward" => migration_type = Some(MigrationType::Backward),
 
@@ -51,6 +64,7 @@ pub(crate) fn parse_args(args: env::args) -> Result<Args> {
     
     Ok(Args {
         datastore_path: datastore_path.unwarp_or_else(|| usage()),
+        datastore_backend: datastore_backend.unwrap_or_default(),
         migration_type: migration_type.unwarp_or_else(|| usage()),
     })
 }
\ No newline at end of file
@@ -11,11 +11,13 @@ use apiserver::datastore::{deserialize_scalar, serialize_scalar, Committed, Data
 /// Retrieves data from the specified data store in a consistent format for easy modification.
 pub(crate) fn get_input_data<D: DataStore>(
     datastore: &D,
-    committed: Committed,
+    committed: &Committed,
 ) -> Result<MigrationData> {
     let raw_data = datastore
         .get_prefix("", committed)
-        .context(error::GetData { committed })?;
+        .context(error::GetData {
+            committed: committed.clone(),
+        })?;
 
     // Deserialize values to Value so there's a consistent input type.  (We can't specify item
     // types because we'd have to know the model structure.)
@@ -25,20 +27,17 @@ pub(crate) fn get_input_data<D: DataStore>(
         data.insert(data_key, value);
     }
 
-    // Metadata isn't committed, it goes live immediately, so we only populate the metadata
-    // output for Committed::Live.
+    // Metadata is staged and committed along with data, so we populate the metadata output from
+    // the same live or pending state.
     let mut metadata = HashMap::new();
-    if let Committed::Live = committed {
-        let raw_metadata = datastore
-            .get_metadata_prefix("", &None as &Option<&str>)
-            .context(error::GetMetadata)?;
-        for (data_key, meta_map) in raw_metadata.into_iter() {
-            let data_entry = metadata.entry(data_key).or_insert_with(HashMap::new);
-            for (metadata_key, value) in meta_map.into_iter() {
-                let value =
-                    deserialize_scalar(&value).context(error::Deserialize { input: value })?;
-                data_entry.insert(metadata_key, value);
-            }
+    let raw_metadata = datastore
+        .get_metadata_prefix("", &None as &Option<&str>, committed)
+        .context(error::GetMetadata)?;
+    for (data_key, meta_map) in raw_metadata.into_iter() {
+        let data_entry = metadata.entry(data_key).or_insert_with(HashMap::new);
+        for (metadata_key, value) in meta_map.into_iter() {
+            let value = deserialize_scalar(&value).context(error::Deserialize { input: value })?;
+            data_entry.insert(metadata_key, value);
         }
     }
 
@@ -51,7 +50,7 @@ pub(crate) fn get_input_data<D: DataStore>(
 pub(crate) fn set_output_data<D: DataStore>(
     datastore: &mut D,
     input: &MigrationData,
-    committed: Committed,
+    committed: &Committed,
 ) -> Result<()> {
     // Prepare serialized data
     let mut data = HashMap::new();
@@ -72,7 +71,7 @@ pub(crate) fn set_output_data<D: DataStore>(
         for (metadata_key, raw_value) in meta_map.into_iter() {
             let value = serialize_scalar(&raw_value).context(error::Serialize)?;
             datastore
-                .set_metadata(&metadata_key, &data_key, value)
+                .set_metadata(&metadata_key, &data_key, value, committed)
                 .context(error::DataStoreWrite)?;
         }
     }
@@ -6,12 +6,22 @@ use apiserver::datastore;
 #[derive(Debug, Snafu)]
 #[snafu(visibility = "pub")]
 pub enum Error {
+    #[snafu(display("Unable to open {} data store at '{}': {}", backend, path, source))]
+    OpenDataStore {
+        path: String,
+        backend: datastore::Backend,
+        source: datastore::Error,
+    },
+
     #[snafu(display("Unable to get {:?} data for migration: {}", committed, source))]
     GetData {
         committed: datastore::Committed,
         source: datastore::Error,
     },
 
+    #[snafu(display("Unable to list transactions in data store: {}", source))]
+    ListTransactions { source: datastore::Error },
+
     #[snafu(display("Unable to get metadata for migration: {}", source))]
     GetMetadata { source: datastore::Error },
 
@@ -4,6 +4,10 @@
 //!
 //! Note that you must still name your migration binary according to spec for it to be handled
 //! properly by the migration runner.
+//!
+//! To test a migration, enable the "memory-datastore" feature in your dev-dependencies, build a
+//! MemoryDataStore from a fixture with `MemoryDataStore::from_fixture_str`, and pass it to
+//! `run_migration`.
 
 // Note that migrations must be run serially; technically, this is because the data store isn't
 // locked, and also because migration authors are given an interface for ordering via migration
@@ -17,16 +21,23 @@ use std::collections::HashMap;
 use std::env;
 use std::fmt;
 
+use snafu::ResultExt;
+
 use apiserver::datastore::{Committed, Value};
-pub use apiserver::datastore::{DataStore, FilesystemDataStore, Key, KeyType};
+pub use apiserver::datastore::{
+    AnyDataStore, Backend, DataStore, FilesystemDataStore, Key, KeyType, SqliteDataStore,
+};
+#[cfg(feature = "memory-datastore")]
+pub use apiserver::datastore::MemoryDataStore;
 
 use args::parse_args;
 use datastore::{get_input_data, set_output_data};
 pub use error::Result;
 
-/// The data store implementation currently in use.  Used by the simpler `migrate` interface; can
-/// be overridden by using the `run_migration` interface.
-type DataStoreImplementation = FilesystemDataStore;
+/// The data store implementation used by the simpler `migrate` interface; the backend is chosen
+/// with the --datastore-backend argument.  Can be overridden by using the `run_migration`
+/// interface.
+type DataStoreImplementation = AnyDataStore;
 
 /// Migrations must implement this trait, and can then use the migrate method to let this module
 /// do the rest of the work.
@@ -86,8 +97,15 @@ pub fn run_migration<D: DataStore>(
     datastore: &mut D,
     migration_type: MigrationType,
 ) -> Result<()> {
-    for committed in &[Committed::Live, Committed::Pending] {
-        let input = get_input_data(datastore, *committed)?;
+    // We migrate live data and every pending transaction, so no settings are lost.
+    let mut committeds = vec![Committed::Live];
+    let transactions = datastore
+        .list_transactions()
+        .context(error::ListTransactions)?;
+    committeds.extend(transactions.into_iter().map(|tx| Committed::Pending { tx }));
+
+    for committed in committeds {
+        let input = get_input_data(datastore, &committed)?;
 
         let migrated = match migration_type {
             MigrationType::Forward => migration.forward(input),
@@ -96,7 +114,7 @@ pub fn run_migration<D: DataStore>(
 
         validate_migrated_data(&migrated)?;
 
-        set_output_data(datastore, &migrated, *committed)?;
+        set_output_data(datastore, &migrated, &committed)?;
     }
     Ok(())
 }
@@ -123,6 +141,12 @@ impl fmt::Display for MigrationType {
 /// migration type.
 pub fn migrate(migration: impl Migration) -> Result<()> {
     let args = parse_args(env::args())?;
-    let mut datastore = DataStoreImplementation::new(args.datastore_path);
+    let mut datastore =
+        DataStoreImplementation::open(args.datastore_backend, &args.datastore_path).context(
+            error::OpenDataStore {
+                path: &args.datastore_path,
+                backend: args.datastore_backend,
+            },
+        )?;
     run_migration(migration, &mut datastore, args.migration_type)
 }
@@ -39,6 +39,9 @@ mod error {
     #[derive(Debug, Snafu)]
     #[snafu(visibility = "pub(super)")]
     pub(super) enum MoondogError {
+        #[snafu(display("Unable to create HTTP client: {}", source))]
+        HttpClient { source: reqwest::Error },
+
         #[snafu(display("Error requesting '{}': {}", uri, source))]
         UserDataRequest { uri: String, source: reqwest::Error },
 
@@ -112,9 +115,18 @@ impl AwsUserDataProvider {
 
 impl UserDataProvider for AwsUserDataProvider {
     fn retrieve_user_data(&self) -> Result<RawUserData> {
+        // IMDS is link-local, so we never go through a proxy to reach it, even if one is set in
+        // our environment.
+        let client = reqwest::Client::builder()
+            .no_proxy()
+            .build()
+            .context(error::HttpClient)?;
+
         debug!("Requesting user data from IMDS");
-        let mut response =
-            reqwest::get(Self::USER_DATA_ENDPOINT).context(error::UserDataRequest {
+        let mut response = client
+            .get(Self::USER_DATA_ENDPOINT)
+            .send()
+            .context(error::UserDataRequest {
                 uri: Self::USER_DATA_ENDPOINT,
             })?;
         trace!("IMDS response: {:?}", &response);
@@ -0,0 +1,97 @@
+/*!
+# Introduction
+regiondog is called by sundog to generate the `settings.aws.region` setting.
+It reads the region from the instance identity document in IMDS.
+*/
+use snafu::{OptionExt, ResultExt};
+
+// Instance Meta Data Service
+const IMDS_URI: &str = "http://169.254.169.254/2018-09-24";
+const INSTANCE_IDENTITY_DOCUMENT_PATH: &str = "/dynamic/instance-identity/document";
+
+mod error {
+    use snafu::Snafu;
+
+    // Taken from sundog.
+    fn code(source: &reqwest::Error) -> String {
+        source
+            .status()
+            .as_ref()
+            .map(|i| i.as_str())
+            .unwrap_or("Unknown")
+            .to_string()
+    }
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum RegiondogError {
+        #[snafu(display("Unable to create HTTP client: {}", source))]
+        HttpClient { source: reqwest::Error },
+
+        #[snafu(display("Error '{}' to '{}': {}", code(&source), path, source))]
+        ImdsRequest {
+            path: String,
+            source: reqwest::Error,
+        },
+
+        #[snafu(display("Error '{}' from '{}': {}", code(&source), path, source))]
+        ImdsResponse {
+            path: String,
+            source: reqwest::Error,
+        },
+
+        #[snafu(display("Error getting text response from {}: {}", path, source))]
+        ImdsText {
+            path: String,
+            source: reqwest::Error,
+        },
+
+        #[snafu(display("Error deserializing response into JSON from {}: {}", path, source))]
+        ImdsJson {
+            path: String,
+            source: serde_json::error::Error,
+        },
+
+        #[snafu(display(
+            "Missing 'region' key in Instance Identity Document from IMDS: {}",
+            path
+        ))]
+        MissingRegion { path: String },
+    }
+}
+
+use error::RegiondogError;
+
+type Result<T> = std::result::Result<T, RegiondogError>;
+
+fn get_region() -> Result<String> {
+    // IMDS is link-local, so we never go through a proxy to reach it, even if one is set in our
+    // environment.
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .build()
+        .context(error::HttpClient)?;
+
+    let path = INSTANCE_IDENTITY_DOCUMENT_PATH;
+    let iid_text = client
+        .get(&format!("{}{}", IMDS_URI, path))
+        .send()
+        .context(error::ImdsRequest { path })?
+        .error_for_status()
+        .context(error::ImdsResponse { path })?
+        .text()
+        .context(error::ImdsText { path })?;
+    let iid_json: serde_json::Value =
+        serde_json::from_str(&iid_text).context(error::ImdsJson { path })?;
+    let region = iid_json["region"]
+        .as_str()
+        .context(error::MissingRegion { path })?;
+
+    Ok(region.to_string())
+}
+
+fn main() -> Result<()> {
+    let region = get_region()?;
+    println!("{}", region);
+    Ok(())
+}
@@ -12,8 +12,8 @@ use std::{collections::HashMap, env, process};
 use snafu::{ensure, ResultExt};
 
 const DEFAULT_API_SOCKET: &str = "/run/api.sock";
-const API_PENDING_URI: &str = "/settings/pending";
-const API_COMMIT_URI: &str = "/settings/commit";
+const API_PENDING_URI: &str = "/tx";
+const API_COMMIT_URI: &str = "/tx/commit";
 
 type Result<T> = std::result::Result<T, error::SettingsCommitterError>;
 
@@ -67,8 +67,11 @@ fn generate_defaults_toml() -> Result<()> {
         .into_iter()
         .filter_entry(|e| e.file_name().to_string_lossy().ends_with(".toml")); // looking for TOML config
 
-    // Merge the files into a single TOML value, in order.
+    // Merge the files into a single TOML value, in order.  merge_values replaces arrays rather than
+    // appending to them, which would leave only the last file's metadata, so we gather the
+    // metadata lists from every file and put them in the result at the end.
     let mut defaults = Value::Table(Map::new());
+    let mut metadata = Vec::new();
     for entry in walker {
         let entry = entry.context(error::ListFiles { dir: DEFAULTS_DIR })?;
 
@@ -79,9 +82,18 @@ fn generate_defaults_toml() -> Result<()> {
             op: "read",
             path: entry.path(),
         })?;
-        let value = toml::from_str(&data).context(error::TomlDeserialize { path: entry.path() })?;
+        let mut value: Value =
+            toml::from_str(&data).context(error::TomlDeserialize { path: entry.path() })?;
+        if let Some(Value::Array(entries)) = value.get_mut("metadata") {
+            metadata.append(entries);
+        }
         merge_values(&mut defaults, &value).context(error::TomlMerge)?;
     }
+    if !metadata.is_empty() {
+        // We made defaults a table above, and merging can't change its type.
+        let table = defaults.as_table_mut().unwrap();
+        table.insert("metadata".to_string(), Value::Array(metadata));
+    }
 
     // Serialize to disk for storewolf to read.
     let data = toml::to_string(&defaults).context(error::TomlSerialize)?;
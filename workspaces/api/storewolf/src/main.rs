@@ -24,6 +24,9 @@ extern crate log;
 
 // FIXME Get these from configuration in the future
 const DATASTORE_VERSION_FILE: &str = "/usr/share/thar/data-store-version";
+// Default settings are written to the API's default transaction, which settings-committer commits
+// on boot.
+const TRANSACTION: &str = "default";
 
 mod error {
     use std::io;
@@ -65,6 +68,9 @@ mod error {
         #[snafu(display("defaults.toml's metadata is not a TOML list of Metadata"))]
         DefaultsMetadataNotTable { source: toml::de::Error },
 
+        #[snafu(display("Unable to recover interrupted datastore commit: {}", source))]
+        RecoverCommit { source: datastore::Error },
+
         #[snafu(display("Error querying datstore for populated keys: {}", source))]
         QueryData { source: datastore::Error },
 
@@ -173,6 +179,9 @@ fn populate_default_datastore<P: AsRef<Path>>(base_path: P) -> Result<()> {
     // changes, so it can be used consistently by the rest of the OS.
     let datastore_path = base_path.as_ref().join("current");
     let mut datastore = FilesystemDataStore::new(&datastore_path);
+    // An interrupted commit can leave the live tree moved aside, so finish it before we check
+    // whether live exists.
+    datastore.recover().context(error::RecoverCommit)?;
     let mut existing_data = HashSet::new();
     let mut existing_metadata = HashMap::new();
 
@@ -182,11 +191,11 @@ fn populate_default_datastore<P: AsRef<Path>>(base_path: P) -> Result<()> {
     if live_path.exists() {
         debug!("Gathering existing data from the datastore");
         existing_metadata = datastore
-            .list_populated_metadata("", &None as &Option<&str>)
+            .list_populated_metadata("", &None as &Option<&str>, &datastore::Committed::Live)
             .context(error::QueryMetadata)?;
         ;
         existing_data = datastore
-            .list_populated_keys("", datastore::Committed::Live)
+            .list_populated_keys("", &datastore::Committed::Live)
             .context(error::QueryData)?;
     } else {
         info!("Creating datastore at: {}", &live_path.display());
@@ -243,8 +252,11 @@ fn populate_default_datastore<P: AsRef<Path>>(base_path: P) -> Result<()> {
             "Writing default settings to datastore: {:#?}",
             &settings_to_write
         );
+        let pending = datastore::Committed::Pending {
+            tx: TRANSACTION.to_string(),
+        };
         datastore
-            .set_keys(&settings_to_write, datastore::Committed::Pending)
+            .set_keys(&settings_to_write, &pending)
             .context(error::WriteKeys)?;
     }
 
@@ -302,7 +314,7 @@ fn populate_default_datastore<P: AsRef<Path>>(base_path: P) -> Result<()> {
         for metadata in metadata_to_write {
             let (md, key, val) = metadata;
             datastore
-                .set_metadata(&md, &key, val)
+                .set_metadata(&md, &key, val, &datastore::Committed::Live)
                 .context(error::WriteMetadata)?;
         }
     }
@@ -331,7 +343,7 @@ fn populate_default_datastore<P: AsRef<Path>>(base_path: P) -> Result<()> {
             &other_defaults_to_write
         );
         datastore
-            .set_keys(&other_defaults_to_write, datastore::Committed::Live)
+            .set_keys(&other_defaults_to_write, &datastore::Committed::Live)
             .context(error::WriteKeys)?;
     }
     Ok(())
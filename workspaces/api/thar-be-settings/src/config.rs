@@ -1,8 +1,12 @@
 use crate::{error, Result};
 use itertools::join;
+use serde::Serialize;
+use serde_json::Value;
+use similar::TextDiff;
 use snafu::ResultExt;
 use std::collections::HashSet;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 
 #[allow(clippy::implicit_hasher)]
@@ -25,6 +29,42 @@ where
     Ok(config_files)
 }
 
+/// Fetch the pending settings in the given transaction and merge them over the given live
+/// settings, so templates can be rendered as they would be after a commit.
+pub async fn merge_pending_settings<P>(
+    socket_path: P,
+    transaction: &str,
+    settings: model::Model,
+) -> Result<model::Model>
+where
+    P: AsRef<Path>,
+{
+    debug!("Querying API for pending settings in transaction '{}'", transaction);
+    let uri = "/tx";
+    let query = Some(("tx", transaction.to_string()));
+    let pending: Value = schnauzer::get_json(socket_path, uri, query)
+        .await
+        .context(error::GetJson { uri })?;
+    trace!("Pending settings: {:?}", &pending);
+
+    let mut merged = serde_json::to_value(settings).context(error::SettingsMerge)?;
+    merge_values(&mut merged["settings"], pending);
+    serde_json::from_value(merged).context(error::SettingsMerge)
+}
+
+/// Merge `overlay` into `base`.  Maps are merged key by key; any other value in `overlay`
+/// replaces the one in `base`.
+fn merge_values(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base_map), Value::Object(overlay_map)) => {
+            for (key, value) in overlay_map {
+                merge_values(base_map.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
 /// Given a map of Service objects, return a HashSet of
 /// affected configuration file names
 pub fn get_config_file_names(services: &model::Services) -> HashSet<String> {
@@ -81,6 +121,31 @@ pub fn write_config_files(rendered_config: Vec<RenderedConfigFile>) -> Result<()
     Ok(())
 }
 
+/// Compare the rendered configuration files to the ones on disk, returning a diff for each file
+/// that would change, sorted by path.
+pub fn diff_config_files(rendered_config: &[RenderedConfigFile]) -> Result<Vec<FileDiff>> {
+    let mut diffs = Vec::new();
+    for cfg in rendered_config {
+        debug!("Comparing {:?} to its rendered template", &cfg.path);
+        if let Some(diff) = cfg.diff_with_disk()? {
+            diffs.push(FileDiff {
+                path: cfg.path.clone(),
+                diff,
+            });
+        }
+    }
+    diffs.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(diffs)
+}
+
+/// FileDiff contains the path to a config file and a unified diff
+/// of the changes rendering would make to it.
+#[derive(Debug, Serialize)]
+pub struct FileDiff {
+    pub path: PathBuf,
+    pub diff: String,
+}
+
 /// RenderedConfigFile contains both the path to the config file
 /// and the rendered data to write.
 #[derive(Debug)]
@@ -111,12 +176,37 @@ impl RenderedConfigFile {
             pathtype: "file",
         })
     }
+
+    /// Returns a unified diff from the file on disk to the rendered template, or None if they
+    /// match.  A file that doesn't exist yet is treated as empty.
+    fn diff_with_disk(&self) -> Result<Option<String>> {
+        let current = match fs::read_to_string(&self.path) {
+            Ok(current) => current,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context(error::ConfigRead { path: &self.path }),
+        };
+
+        if current == self.rendered {
+            return Ok(None);
+        }
+        Ok(Some(unified_diff(&self.path, &current, &self.rendered)))
+    }
+}
+
+/// Returns a unified diff between two versions of the file at the given path.
+fn unified_diff(path: &Path, old: &str, new: &str) -> String {
+    let path = path.display().to_string();
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(&path, &path)
+        .to_string()
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use maplit::{hashmap, hashset};
+    use serde_json::json;
     use std::convert::TryInto;
 
     #[test]
@@ -136,4 +226,36 @@ mod test {
 
         assert_eq!(get_config_file_names(&input_map), expected_output)
     }
+
+    #[test]
+    fn test_merge_values() {
+        let mut live = json!({
+            "hostname": "live",
+            "ntp": {"time-servers": ["a", "b"]},
+            "updates": {"seed": 1, "metadata-base-url": "x"},
+        });
+        let pending = json!({
+            "ntp": {"time-servers": ["c"]},
+            "updates": {"seed": 2},
+        });
+
+        merge_values(&mut live, pending);
+        assert_eq!(
+            live,
+            json!({
+                "hostname": "live",
+                "ntp": {"time-servers": ["c"]},
+                "updates": {"seed": 2, "metadata-base-url": "x"},
+            })
+        );
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let diff = unified_diff(Path::new("/etc/test"), "a\nb\nc\n", "a\nB\nc\n");
+        assert_eq!(
+            diff,
+            "--- /etc/test\n+++ /etc/test\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+        );
+    }
 }
\ No newline at end of file
@@ -27,6 +27,12 @@ pub enum Error {
         source: io::Error,
     },
 
+    #[snafu(display("Failed to read configuration file at {}: {}", path.display(), source))]
+    ConfigRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to merge pending settings over live settings: {}", source))]
+    SettingsMerge { source: serde_json::Error },
+
     #[snafu(display("Failed to run restart command - '{}': {}", command, source))]
     CommandExecutionFailure { command: String, source: io::Error },
 
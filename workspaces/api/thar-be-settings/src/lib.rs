@@ -9,6 +9,11 @@ Configuration file data from the API includes paths to template files for each c
 It then renders the templates and rewrites the affected configuration files.
 Service data from the API includes any commands needed to restart services affected by configuration file changes, which are run here.
 In the standalone ("all keys") mode, it queries the API for all services and configuration files, then renders and rewrites all configuration files and restarts all services.
+
+In either mode, it can instead do a dry run.
+The pending settings in a transaction are merged over the live settings before rendering, and nothing is written or restarted.
+Instead, it prints a JSON report with a unified diff for each configuration file that would change, and the restart commands that would be run.
+The API server uses this to preview a commit.
 */
 
 #![deny(rust_2018_idioms)]
@@ -16,6 +21,7 @@ In the standalone ("all keys") mode, it queries the API for all services and con
 #[macro_use]
 extern crate log;
 
+use serde::Serialize;
 use snafu::ResultExt;
 use std::collections::HashSet;
 use std::io::{self, Read};
@@ -27,6 +33,15 @@ pub mod service;
 pub use error::Error;
 type Result<T> = std::result::Result<T, Error>;
 
+/// DryRunReport describes what applying settings would do: the changes it would make to each
+/// configuration file, and the restart commands it would run.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DryRunReport {
+    pub files: Vec<config::FileDiff>,
+    pub restart_commands: Vec<String>,
+}
+
 /// Read stdin and parse into JSON
 pub fn get_changed_settings() -> Result<HashSet<String>> {
     let mut input = String::new();
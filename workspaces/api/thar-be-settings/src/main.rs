@@ -1,17 +1,20 @@
 #[macro_use]
 extern crate log;
 
-use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
+use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger, WriteLogger};
 use snafu::ResultExt;
 use std::collections::HashSet;
 use std::env;
+use std::io;
 use std::process;
 use std::str::FromStr;
 
-use thar_be_settings::{config, get_changed_settings, service};
+use thar_be_settings::config::RenderedConfigFile;
+use thar_be_settings::{config, get_changed_settings, service, DryRunReport};
 
 // FIXME Get from configuration in the future
 const DEFAULT_API_SOCKET: &str = "/run/api.sock";
+const DEFAULT_TRANSACTION: &str = "default";
 
 mod error {
     use snafu::Snafu;
@@ -29,6 +32,9 @@ mod error {
             path: PathBuf,
             source: handlebars::TemplateFileError,
         },
+
+        #[snafu(display("Failed to serialize dry run report: {}", source))]
+        ReportSerialize { source: serde_json::Error },
     }
 }
 
@@ -46,6 +52,8 @@ struct Args {
     log_level: LevelFilter,
     mode: RunMode,
     socket_path: String,
+    dry_run: bool,
+    transaction: Option<String>,
 }
 
 /// Print a usage message in the event a bad arg is passed
@@ -54,14 +62,20 @@ fn usage() -> ! {
     eprintln!(
         r"Usage: {}
             [ --all ]
+            [ --dry-run [ --tx NAME ] ]
             [ --socket-path PATH ]
             [ --log-level trace|debug|info|warn|error ]
     If --all is given, all configuration files will be written and all
     services will have their restart-commands run.  Otherwise, settings keys
     will be read from stdin; only files related to those keys will be written,
     and only services related to those keys will be restarted.
-    Socket path defaults to {}",
-        program_name, DEFAULT_API_SOCKET,
+    If --dry-run is given, nothing is written or restarted.  Pending settings
+    from transaction NAME are merged over live settings, and a JSON report of
+    the changes to each configuration file and the restart-commands that would
+    be run is printed to stdout.  Logs go to stderr.
+    Socket path defaults to {}
+    Transaction name defaults to '{}'",
+        program_name, DEFAULT_API_SOCKET, DEFAULT_TRANSACTION,
     );
     process::exit(2);
 }
@@ -77,12 +91,23 @@ fn parse_args(args: env::Args) -> Args {
     let mut log_level = None;
     let mut mode = RunMode::SpecificKeys;
     let mut socket_path = None;
+    let mut dry_run = false;
+    let mut transaction = None;
 
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
         match arg.as_ref() {
             "--all" => mode = RunMode::All,
 
+            "--dry-run" => dry_run = true,
+
+            "--tx" => {
+                transaction = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --tx")),
+                )
+            }
+
             "--log-level" => {
                 let log_level_str = iter
                     .next()
@@ -103,19 +128,26 @@ fn parse_args(args: env::Args) -> Args {
         }
     }
 
+    if transaction.is_some() && !dry_run {
+        usage_msg("--tx can only be used with --dry-run");
+    }
+
     Args {
         mode,
         log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
         socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.to_string()),
+        dry_run,
+        transaction,
     }
 }
 
-/// Render and write config files to disk.  If `files_limit` is Some, only
-/// write those files, otherwise write all known files.
-async fn write_config_files(
+/// Render config files.  If `files_limit` is Some, only render those files,
+/// otherwise render all known files.  In a dry run, pending settings are
+/// merged over live settings first.
+async fn render_config_files(
     args: &Args,
     files_limit: Option<HashSet<String>>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<Vec<RenderedConfigFile>, Box<dyn std::error::Error>> {
     // Create a vec of ConfigFile structs from the list of changed services
     info!("Requesting configuration file data for affected services");
     let config_files = config::get_affected_config_files(&args.socket_path, files_limit).await?;
@@ -139,7 +171,13 @@ async fn write_config_files(
 
     // Get all settings values for config file templates
     debug!("Requesting settings values");
-    let settings = schnauzer::get_settings(&args.socket_path).await?;
+    let mut settings = schnauzer::get_settings(&args.socket_path).await?;
+    if args.dry_run {
+        let transaction = args.transaction.as_deref().unwrap_or(DEFAULT_TRANSACTION);
+        debug!("Merging pending settings from transaction '{}'", transaction);
+        settings =
+            config::merge_pending_settings(&args.socket_path, transaction, settings).await?;
+    }
 
     // Ensure all files render properly
     info!("Rendering config files...");
@@ -149,24 +187,26 @@ async fn write_config_files(
     };
     let rendered = config::render_config_files(&template_registry, config_files, settings, strict)?;
 
-    // If all the config renders properly, write it to disk
-    info!("Writing config files to disk...");
-    config::write_config_files(rendered)?;
-
-    Ok(())
+    Ok(rendered)
 }
 
 async fn run() -> Result<(), Box<dyn std::error::Error>> {
     // Parse and store the args passed to the program
     let args = parse_args(env::args());
 
-    // SimpleLogger will send errors to stderr and anything less to stdout.
-    SimpleLogger::init(args.log_level, LogConfig::default())
-        .context(error::Logger)?;
+    // SimpleLogger will send errors to stderr and anything less to stdout.  In a dry run, stdout
+    // is for the report, so everything goes to stderr.
+    if args.dry_run {
+        WriteLogger::init(args.log_level, LogConfig::default(), io::stderr())
+            .context(error::Logger)?;
+    } else {
+        SimpleLogger::init(args.log_level, LogConfig::default())
+            .context(error::Logger)?;
+    }
 
     info!("thar-be-settings started");
 
-    match args.mode {
+    let (services, files_limit) = match args.mode {
         RunMode::SpecificKeys => {
             // Get the settings that changed via stdin
             info!("Parsing stdin for updated settings");
@@ -181,31 +221,48 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
                 service::get_affected_services(&args.socket_path, Some(changed_settings)).await?;
             trace!("Found services: {:?}", services);
             if services.is_empty() {
-                info!("No services are affected, exiting...");
-                process::exit(0)
+                info!("No services are affected");
             }
 
             // Create a HashSet of configuration file names
             let config_file_names = config::get_config_file_names(&services);
-
-            if !config_file_names.is_empty() {
-                write_config_files(&args, Some(config_file_names)).await?;
-            }
-
-            // Now go bounce the affected services
-            info!("Restarting affected services...");
-            service::restart_services(services)?;
+            (services, Some(config_file_names))
         }
         RunMode::All => {
-            write_config_files(&args, None).await?;
-
-            info!("Restarting all services...");
+            info!("Requesting all services");
             let services = service::get_affected_services(&args.socket_path, None).await?;
             trace!("Found services: {:?}", services);
-            service::restart_services(services)?;
+            (services, None)
         }
+    };
+
+    // Skip rendering if the affected services have no configuration files
+    let no_files = files_limit.as_ref().map_or(false, |names| names.is_empty());
+    let rendered = if no_files {
+        Vec::new()
+    } else {
+        render_config_files(&args, files_limit).await?
+    };
+
+    if args.dry_run {
+        info!("Comparing config files to disk...");
+        let report = DryRunReport {
+            files: config::diff_config_files(&rendered)?,
+            restart_commands: service::restart_commands(&services),
+        };
+        let report = serde_json::to_string(&report).context(error::ReportSerialize)?;
+        println!("{}", report);
+        return Ok(());
     }
 
+    // If all the config renders properly, write it to disk
+    info!("Writing config files to disk...");
+    config::write_config_files(rendered)?;
+
+    // Now go bounce the affected services
+    info!("Restarting affected services...");
+    service::restart_services(services)?;
+
     Ok(())
 }
 
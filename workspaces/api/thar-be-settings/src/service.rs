@@ -112,6 +112,15 @@ pub fn restart_services(services: model::Services) -> Result<()> {
     Ok(())
 }
 
+/// Return the restart commands of each Service in a Services object, in the order they'd be run
+/// by `restart_services()`, without running them.
+pub fn restart_commands(services: &model::Services) -> Vec<String> {
+    services
+        .values()
+        .flat_map(|service| service.restart_commands.iter().cloned())
+        .collect()
+}
+
 /// This trait is primarily meant to extend the Service model.  It uses the metadata
 /// inside the Service struct to restart the service.
 trait ServiceRestart {
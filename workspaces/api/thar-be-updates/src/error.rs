@@ -9,6 +9,12 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug, Snafu)]
 #[snafu(visibility = "pub")]
 pub enum Error {
+    #[snafu(display("Failed to create update status directory '{}': {}", path.display(), source))]
+    CreateStatusDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
     #[snafu(display("Failed to create tempfile for writing status: {}", source))]
     CreateTempfile { source: std::io::Error },
 
@@ -101,6 +107,9 @@ pub enum Error {
     #[snafu(display("Failed to start updog: {}", source))]
     Updog { source: std::io::Error },
 
+    #[snafu(display("Failed to list available updates with updog"))]
+    RefreshUpdates,
+
     #[snafu(display("Failed to prepare the update with updog"))]
     PrepareUpdate,
 
@@ -119,6 +128,9 @@ pub enum Error {
         source: serde_json::Error,
     },
 
+    #[snafu(display("Setting '{}' is missing or invalid", setting))]
+    MissingSetting { setting: String },
+
     #[snafu(display("Failed to parse version string '{}' into semver version", version))]
     SemVer {
         version: String,
@@ -1,314 +1,355 @@
-use crate::error;
-use crate::error::Result;
-use gilmanos_release::GilmanosRelease;
-use chrono::{DateTime, Utc};
-use model::modeled_types::FriendlyVersion;
-use serde::{Deserialize, Serialize};
-use signpost::State;
-use snafu::{OptionExt, ResultExt};
-use std::convert::TryInto;
-use std::fs::File;
-use std::os::unix::process::ExitStatusExt;
-use std::process::Output;
-use tokio::runtime::Runtime;
-
-pub const UPDATE_LOCKFILE: &str = "/run/lock/thar-be-updates.lock";
-pub const UPDATE_STATUS_FILE: &str = "/run/cache/thar-be-updates/status.json";
-
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub enum UpdateState {
-    Idle,
-    Available,
-    Staged,
-    Ready,
-}
+/*!
+# Background
+
+thar-be-updates runs update commands for the API server and keeps track of the host's update
+status: the updates that are available, the one chosen according to the
+`settings.updates.version-lock` and wave settings, and the images in each partition set.
+
+It takes one of these commands:
+* `refresh` lists the available updates with updog and chooses one.
+* `prepare` writes the chosen update to the inactive partition set.
+* `activate` marks the inactive partition set to boot next.
+* `deactivate` marks the active partition set to boot next again, undoing `activate`.
+
+Each command is only allowed in some update states; for example, an update has to be prepared
+before it can be activated.
+
+thar-be-updates holds an exclusive lock on `/run/lock/thar-be-updates.lock` while a command runs,
+so only one runs at a time.  Commands can take a while, so once a command is allowed, it's run in
+a forked child that keeps the lock, and thar-be-updates returns.  The child records the result in
+the status file, `/run/cache/thar-be-updates/status.json`, which the API server reads to answer
+update queries.
+
+If a command can't be started, thar-be-updates exits with a specific code so the caller can tell
+why; see `TbuErrorStatus`.
+*/
+
+#[macro_use]
+extern crate log;
+
+use fs2::FileExt;
+use nix::unistd::{fork, ForkResult};
+use num_traits::ToPrimitive;
+use simplelog::{Config as LogConfig, LevelFilter, TermLogger, TerminalMode};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::process::{self, Command};
+use std::str::FromStr;
+use tempfile::NamedTempFile;
+use thar_be_updates::error::{self, Error, Result, TbuErrorStatus};
+use thar_be_updates::status::{
+    get_update_status, UpdateCommand, UpdateState, UpdateStatus, UPDATE_LOCKFILE,
+    UPDATE_STATUS_FILE,
+};
+
+// FIXME Get from configuration in the future
+const DEFAULT_API_SOCKET: &str = "/run/api.sock";
+const UPDOG_BIN: &str = "/usr/bin/updog";
+const SIGNPOST_BIN: &str = "/usr/bin/signpost";
 
-/// UpdateImage represents a Gilmanos update image
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct UpdateImage {
-    arch: String,
-    version: semver::Version,
-    variant: String,
+/// Store the args we receive on the command line
+struct Args {
+    command: UpdateCommand,
+    log_level: LevelFilter,
+    socket_path: String,
 }
 
-impl UpdateImage {
-    pub fn version(&self) -> &semver::Version {
-        &self.version
-    }
+/// Print a usage message in the event a bad arg is passed
+fn usage() -> ! {
+    let program_name = std::env::args()
+        .next()
+        .unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ refresh | prepare | activate | deactivate ]
+            [ --socket-path PATH ]
+            [ --log-level trace|debug|info|warn|error ]
+
+    Socket path defaults to {}",
+        program_name, DEFAULT_API_SOCKET,
+    );
+    process::exit(2);
 }
 
-/// StagedImage represents a Gilmanos image that is written to a partition set
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct StagedImage {
-    image: UpdateImage,
-    /// Indicates whether this image is marked for next boot
-    next_to_boot: bool,
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
 }
 
-impl StagedImage {
-    pub(crate) fn set_next_to_boot(&mut self, next_to_boot: bool) {
-        self.next_to_boot = next_to_boot
+/// Parse the args to the program and return an Args struct
+fn parse_args(args: std::env::Args) -> Args {
+    let mut command = None;
+    let mut log_level = None;
+    let mut socket_path = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--log-level" => {
+                let log_level_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --log-level"));
+                log_level = Some(LevelFilter::from_str(&log_level_str).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid log level '{}'", log_level_str))
+                }));
+            }
+
+            "--socket-path" => {
+                socket_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
+                )
+            }
+
+            // Commands are named like the update actions in the API, e.g. "refresh"
+            other => match serde_plain::from_str::<UpdateCommand>(other) {
+                Ok(parsed) if command.is_none() => command = Some(parsed),
+                _ => usage(),
+            },
+        }
+    }
+
+    Args {
+        command: command.unwrap_or_else(|| usage_msg("Must specify a command")),
+        log_level: log_level.unwrap_or(LevelFilter::Info),
+        socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.to_string()),
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub enum CommandStatus {
-    Success,
-    Failed,
-    Unknown,
+/// Checks whether the command can run given the current update status, so we can tell the
+/// caller before we start it in the background.
+fn check_command(command: &UpdateCommand, status: &UpdateStatus) -> Result<()> {
+    let state = status.update_state();
+    let allowed = match command {
+        // The list of updates can always be refreshed.
+        UpdateCommand::Refresh => true,
+        // An update can be prepared again, e.g. if a newer one was chosen since.
+        UpdateCommand::Prepare => matches!(state, UpdateState::Available | UpdateState::Staged),
+        UpdateCommand::Activate => matches!(state, UpdateState::Staged),
+        UpdateCommand::Deactivate => matches!(state, UpdateState::Ready),
+    };
+    ensure!(
+        allowed,
+        error::DisallowCommand {
+            command: command.clone(),
+            state: state.clone(),
+        }
+    );
+
+    match command {
+        UpdateCommand::Prepare => {
+            status.chosen_update().context(error::UpdateDoesNotExist)?;
+        }
+        UpdateCommand::Activate => {
+            status
+                .staging_partition()
+                .context(error::StagingPartition)?;
+        }
+        _ => {}
+    }
+    Ok(())
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-#[serde(rename_all = "kebab-case")]
-pub enum UpdateCommand {
-    Refresh,
-    Prepare,
-    Activate,
-    Deactivate,
+/// Lists the available updates with updog and chooses one.  The state only changes if we weren't
+/// already working on an update.
+fn refresh(status: &mut UpdateStatus, socket_path: &str) -> Result<()> {
+    let output = Command::new(UPDOG_BIN)
+        .args(&["whats", "--all", "--json"])
+        .output()
+        .context(error::Updog)?;
+    status.set_recent_command_info(UpdateCommand::Refresh, &output);
+    ensure!(output.status.success(), error::RefreshUpdates);
+
+    let updates: Vec<update_metadata::Update> =
+        serde_json::from_slice(&output.stdout).context(error::UpdateInfo)?;
+    let available = status.update_available_updates(socket_path, updates)?;
+    // Don't lose track of an update we've already prepared or activated.
+    if matches!(
+        status.update_state(),
+        UpdateState::Idle | UpdateState::Available
+    ) {
+        status.set_update_state(if available {
+            UpdateState::Available
+        } else {
+            UpdateState::Idle
+        });
+    }
+    Ok(())
 }
 
-/// CommandResult represents the result of an issued command
-#[derive(Debug, Clone, Deserialize, Serialize)]
-struct CommandResult {
-    cmd_type: UpdateCommand,
-    cmd_status: CommandStatus,
-    timestamp: DateTime<Utc>,
-    exit_status: Option<i32>,
-    stderr: Option<String>,
+/// Writes the chosen update to the inactive partition set with updog.
+fn prepare(status: &mut UpdateStatus) -> Result<()> {
+    let chosen_update = status
+        .chosen_update()
+        .context(error::UpdateDoesNotExist)?
+        .clone();
+    // We already checked the update's wave when choosing it, so updog doesn't have to.
+    let output = Command::new(UPDOG_BIN)
+        .args(&["update-image", "--image"])
+        .arg(chosen_update.version().to_string())
+        .arg("--now")
+        .output()
+        .context(error::Updog)?;
+    status.set_recent_command_info(UpdateCommand::Prepare, &output);
+    ensure!(output.status.success(), error::PrepareUpdate);
+
+    status.set_staging_partition_image_info(chosen_update);
+    status.set_update_state(UpdateState::Staged);
+    Ok(())
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct UpdateStatus {
-    update_state: UpdateState,
-    available_updates: Vec<semver::Version>,
-    chosen_update: Option<UpdateImage>,
-    active_partition: Option<StagedImage>,
-    staging_partition: Option<StagedImage>,
-    most_recent_command: Option<CommandResult>,
+/// Marks the inactive partition set, where the update was prepared, to boot next.
+fn activate(status: &mut UpdateStatus) -> Result<()> {
+    let output = Command::new(UPDOG_BIN)
+        .arg("update-apply")
+        .output()
+        .context(error::Updog)?;
+    status.set_recent_command_info(UpdateCommand::Activate, &output);
+    ensure!(output.status.success(), error::ActivateUpdate);
+
+    status.mark_staging_partition_next_to_boot()?;
+    status.set_update_state(UpdateState::Ready);
+    Ok(())
 }
 
-impl Default for UpdateStatus {
-    fn default() -> Self {
-        Self::new()
-    }
+/// Marks the active partition set to boot next again, undoing activate.
+fn deactivate(status: &mut UpdateStatus) -> Result<()> {
+    let output = Command::new(SIGNPOST_BIN)
+        .arg("rollback-to-inactive")
+        .output()
+        .context(error::Signpost)?;
+    status.set_recent_command_info(UpdateCommand::Deactivate, &output);
+    ensure!(output.status.success(), error::DeactivateUpdate);
+
+    status.unmark_staging_partition_next_to_boot()?;
+    status.set_update_state(UpdateState::Staged);
+    Ok(())
 }
 
-/// Loads and returns the update status from disk.
-/// This takes the update lock file as an parameter to signal to caller that the update
-/// lock needs to be obtained before calling this.
-pub fn get_update_status(_lockfile: &File) -> Result<UpdateStatus> {
-    let status_file = File::open(UPDATE_STATUS_FILE).context(error::NoStatusFile {
-        path: UPDATE_STATUS_FILE,
+/// Writes the update status to disk.  We write a temporary file and rename it into place, so
+/// readers, who don't take the update lock, never see a partial status.
+fn write_update_status(status: &UpdateStatus) -> Result<()> {
+    let status_path = Path::new(UPDATE_STATUS_FILE);
+    // The status file is always in a directory, so it has a parent.
+    let status_dir = status_path.parent().unwrap_or_else(|| Path::new("/"));
+    fs::create_dir_all(status_dir).context(error::CreateStatusDir { path: status_dir })?;
+
+    let tempfile = NamedTempFile::new_in(status_dir).context(error::CreateTempfile)?;
+    serde_json::to_writer_pretty(&tempfile, status).context(error::StatusWrite {
+        path: tempfile.path(),
     })?;
-    Ok(
-        serde_json::from_reader(status_file).context(error::StatusParse {
-            path: UPDATE_STATUS_FILE,
-        })?,
-    )
+    tempfile
+        .into_temp_path()
+        .persist(status_path)
+        .context(error::CreateStatusFile { path: status_path })?;
+    Ok(())
 }
 
-/// Retrieves settings from the API.
-///
-/// NOTE: this function creates its own tokio runtime to make the async apiclient call.  It should
-/// not be called if you're running another tokio runtime.  The program structure requires forking
-/// to handle long-running update actions, and the tokio runtime uses threading, which generally
-/// isn't safe over forks; instead, we create and drop one here for the short period we need it.
-fn get_settings(socket_path: &str) -> Result<serde_json::Value> {
-    let uri = "/settings";
-    let method = "GET";
-
-    let mut rt = Runtime::new().context(error::Runtime)?;
-    let try_response_body =
-        rt.block_on(async { apiclient::raw_request(&socket_path, uri, method, None).await });
-    let (_code, response_body) = try_response_body.context(error::APIRequest { method, uri })?;
-
-    serde_json::from_str(&response_body).context(error::ResponseJson { uri })
+/// Runs the command, which was already checked, and records the result in the status file,
+/// whether or not it succeeded.
+fn run_command(command: &UpdateCommand, mut status: UpdateStatus, socket_path: &str) -> Result<()> {
+    let result = match command {
+        UpdateCommand::Refresh => refresh(&mut status, socket_path),
+        UpdateCommand::Prepare => prepare(&mut status),
+        UpdateCommand::Activate => activate(&mut status),
+        UpdateCommand::Deactivate => deactivate(&mut status),
+    };
+    write_update_status(&status)?;
+    result
 }
 
-// This is how the UpdateStatus is stored on disk
-impl UpdateStatus {
-    /// Initializes the update status
-    pub fn new() -> Self {
-        Self {
-            update_state: UpdateState::Idle,
-            available_updates: vec![],
-            chosen_update: None,
-            active_partition: None,
-            staging_partition: None,
-            most_recent_command: None,
-        }
-    }
-
-    pub fn update_state(&self) -> &UpdateState {
-        &self.update_state
-    }
+fn run() -> Result<()> {
+    let args = parse_args(std::env::args());
+    TermLogger::init(args.log_level, LogConfig::default(), TerminalMode::Mixed)
+        .context(error::Logger)?;
 
-    pub fn set_update_state(&mut self, state: UpdateState) {
-        self.update_state = state;
-    }
+    // Only one command runs at a time.  We don't truncate the lock file; it only exists to be
+    // locked.
+    let lockfile = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(UPDATE_LOCKFILE)
+        .context(error::UpdateLockFile {
+            path: UPDATE_LOCKFILE,
+        })?;
+    lockfile
+        .try_lock_exclusive()
+        .context(error::UpdateLockHeld {
+            path: UPDATE_LOCKFILE,
+        })?;
 
-    pub fn chosen_update(&self) -> Option<&UpdateImage> {
-        match &self.chosen_update {
-            Some(update) => Some(&update),
-            None => None,
-        }
-    }
+    let mut status = if Path::new(UPDATE_STATUS_FILE).exists() {
+        get_update_status()?
+    } else {
+        UpdateStatus::new()
+    };
+    status.update_active_partition_info()?;
+    check_command(&args.command, &status)?;
 
-    pub fn staging_partition(&self) -> Option<&StagedImage> {
-        match &self.staging_partition {
-            Some(partition_info) => Some(&partition_info),
-            None => None,
+    // The child inherits the lock file, so it holds the lock until it exits, after we return.  We
+    // haven't started any threads yet, so it's safe to fork; see status::get_settings.
+    match unsafe { fork() }.ok().context(error::Fork)? {
+        ForkResult::Parent { child } => {
+            debug!("Started {:?} in process {}", args.command, child);
+            Ok(())
         }
+        ForkResult::Child => run_command(&args.command, status, &args.socket_path),
     }
+}
 
-    /// Updates the active partition set information
-    pub fn update_active_partition_info(&mut self) -> Result<()> {
-        // Get current OS release info to determine active partition image information
-        let os_info = GilmanosRelease::new().context(error::ReleaseVersion)?;
-        let active_image = UpdateImage {
-            arch: os_info.arch,
-            version: os_info.version_id,
-            variant: os_info.variant_id,
-        };
-
-        // Get partition set information. We can infer the version of the image in the active
-        // partition set by checking the os release information
-        let gpt_state = State::load().context(error::PartitionTableRead)?;
-        let active_set = gpt_state.active();
-        let next_set = gpt_state.next().context(error::NoneSetToBoot)?;
-        self.active_partition = Some(StagedImage {
-            image: active_image,
-            next_to_boot: active_set == next_set,
-        });
-        Ok(())
-    }
+/// Maps errors to the exit codes the API server understands.
+fn exit_status(error: &Error) -> i32 {
+    let status = match error {
+        Error::UpdateLockHeld { .. } => TbuErrorStatus::UpdateLockHeld,
+        Error::DisallowCommand { .. } => TbuErrorStatus::DisallowCommand,
+        Error::UpdateDoesNotExist => TbuErrorStatus::UpdateDoesNotExist,
+        Error::StagingPartition => TbuErrorStatus::NoStagedImage,
+        _ => TbuErrorStatus::OtherError,
+    };
+    status.to_i32().unwrap_or(1)
+}
 
-    /// Sets the staging partition image information
-    pub fn set_staging_partition_image_info(&mut self, image: UpdateImage) {
-        self.staging_partition = Some(StagedImage {
-            image,
-            next_to_boot: false,
-        });
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(exit_status(&e));
     }
+}
 
-    /// Mark staging partition as next to boot
-    pub fn mark_staging_partition_next_to_boot(&mut self) -> Result<()> {
-        if let Some(staging_partition) = &mut self.staging_partition {
-            staging_partition.set_next_to_boot(true);
-        } else {
-            return error::StagingPartition {}.fail();
-        }
-        if let Some(active_partition) = &mut self.active_partition {
-            active_partition.set_next_to_boot(false);
-        } else {
-            return error::ActivePartition {}.fail();
-        }
-        Ok(())
-    }
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    /// Unmark staging partition as next to boot
-    pub fn unmark_staging_partition_next_to_boot(&mut self) -> Result<()> {
-        if let Some(staging_partition) = &mut self.staging_partition {
-            staging_partition.set_next_to_boot(false);
-        } else {
-            return error::StagingPartition {}.fail();
-        }
-        if let Some(active_partition) = &mut self.active_partition {
-            active_partition.set_next_to_boot(true);
-        } else {
-            return error::ActivePartition {}.fail();
+    #[test]
+    fn commands_follow_update_state() {
+        let mut status = UpdateStatus::new();
+        check_command(&UpdateCommand::Refresh, &status).unwrap();
+        for command in &[
+            UpdateCommand::Prepare,
+            UpdateCommand::Activate,
+            UpdateCommand::Deactivate,
+        ] {
+            let e = check_command(command, &status).unwrap_err();
+            assert_eq!(exit_status(&e), TbuErrorStatus::DisallowCommand as i32);
         }
-        Ok(())
-    }
 
-    /// Sets information regarding the latest command invocation
-    /// Derive success/failure status from exit status when possible.
-    pub fn set_recent_command_info(&mut self, cmd_type: UpdateCommand, cmd_output: &Output) {
-        let exit_status = match cmd_output.status.code() {
-            Some(code) => code,
-            None => cmd_output.status.signal().unwrap_or(1),
-        };
-        let command_result = CommandResult {
-            cmd_type,
-            cmd_status: if exit_status == 0 {
-                CommandStatus::Success
-            } else {
-                CommandStatus::Failed
-            },
-            timestamp: Utc::now(),
-            exit_status: Some(exit_status),
-            stderr: Some(String::from_utf8_lossy(&cmd_output.stderr).to_string()),
-        };
-        self.most_recent_command = Some(command_result);
-    }
+        // Preparing needs an update to have been chosen.
+        status.set_update_state(UpdateState::Available);
+        let e = check_command(&UpdateCommand::Prepare, &status).unwrap_err();
+        assert_eq!(exit_status(&e), TbuErrorStatus::UpdateDoesNotExist as i32);
 
-    /// Returns the update information of the 'latest' available update
-    pub fn get_latest_update(
-        updates: Vec<update_metadata::Update>,
-    ) -> Result<Option<update_metadata::Update>> {
-        let os_info = GilmanosRelease::new().context(error::ReleaseVersion)?;
-        for update in updates {
-            // If the current running version is greater than the max version ever published,
-            // or moves us to a valid version <= the maximum version, update.
-            // Updates are listed in descending order (in terms of versions) in the manifest,
-            // so the first picked out would be the latest update available.
-            if os_info.version_id < update.version || os_info.version_id > update.max_version {
-                return Ok(Some(update));
-            }
-        }
-        Ok(None)
-    }
-
-    /// Checks the list of updates to for an available update.
-    /// If the 'version-lock'ed version is available returns true. Otherwise returns false
-    pub fn update_available_updates(
-        &mut self,
-        socket_path: &str,
-        updates: Vec<update_metadata::Update>,
-    ) -> Result<bool> {
-        // Extract the version to store
-        self.available_updates = updates.iter().map(|u| u.version.to_owned()).collect();
-        // Check if the 'version-lock'ed update is available as the 'chosen' update
-        // Retrieve the 'version-lock' setting
-        let settings = get_settings(socket_path)?;
-        let locked_version: FriendlyVersion = serde_json::from_value(
-            settings["updates"]["version-lock"].to_owned(),
-        )
-        .context(error::GetSetting {
-            setting: "/settings/updates/version-lock",
-        })?;
+        // Activating needs an update to have been written to the inactive partition set.
+        status.set_update_state(UpdateState::Staged);
+        let e = check_command(&UpdateCommand::Activate, &status).unwrap_err();
+        assert_eq!(exit_status(&e), TbuErrorStatus::NoStagedImage as i32);
 
-        if String::from(locked_version.to_owned()) == "latest" {
-            // Set chosen_update to the latest version available
-            if let Some(latest_update) = UpdateStatus::get_latest_update(updates)? {
-                self.chosen_update = Some(UpdateImage {
-                    arch: latest_update.arch,
-                    version: latest_update.version,
-                    variant: latest_update.variant,
-                });
-                return Ok(true);
-            }
-        } else {
-            let chosen_version =
-                FriendlyVersion::try_into(locked_version.to_owned()).context(error::SemVer {
-                    version: locked_version,
-                })?;
-            let os_info = GilmanosRelease::new().context(error::ReleaseVersion)?;
-            if chosen_version != os_info.version_id {
-                for update in &updates {
-                    if update.version == chosen_version {
-                        self.chosen_update = Some(UpdateImage {
-                            arch: update.arch.clone(),
-                            version: chosen_version,
-                            variant: update.variant.clone(),
-                        });
-                        return Ok(true);
-                    }
-                }
-            }
-        }
-        // 'version-lock'ed update is unavailable.
-        self.chosen_update = None;
-        Ok(false)
+        status.set_update_state(UpdateState::Ready);
+        check_command(&UpdateCommand::Deactivate, &status).unwrap();
+        check_command(&UpdateCommand::Refresh, &status).unwrap();
     }
 }
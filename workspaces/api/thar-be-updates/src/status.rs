@@ -2,11 +2,11 @@ use crate::error;
 use crate::error::Result;
 use gilmanos_release::GilmanosRelease;
 use chrono::{DateTime, Utc};
-use model::modeled_types::FriendlyVersion;
+use friendly_version::FriendlyVersion;
 use serde::{Deserialize, Serialize};
 use signpost::State;
 use snafu::{OptionExt, ResultExt};
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::fs::File;
 use std::os::unix::process::ExitStatusExt;
 use std::process::Output;
@@ -94,9 +94,9 @@ impl Default for UpdateStatus {
 }
 
 /// Loads and returns the update status from disk.
-/// This takes the update lock file as an parameter to signal to caller that the update
-/// lock needs to be obtained before calling this.
-pub fn get_update_status(_lockfile: &File) -> Result<UpdateStatus> {
+/// This doesn't need the update lock, so callers can poll the status while a command is in
+/// flight; a status caught in the middle of being written fails to parse, and can be retried.
+pub fn get_update_status() -> Result<UpdateStatus> {
     let status_file = File::open(UPDATE_STATUS_FILE).context(error::NoStatusFile {
         path: UPDATE_STATUS_FILE,
     })?;
@@ -125,6 +125,35 @@ fn get_settings(socket_path: &str) -> Result<serde_json::Value> {
     serde_json::from_str(&response_body).context(error::ResponseJson { uri })
 }
 
+/// Returns the seed that decides our update wave, or None if settings.updates.ignore-waves is set.
+/// bork generates the seed as a number, but we also accept a string holding one.
+fn wave_seed(settings: &serde_json::Value) -> Result<Option<u32>> {
+    let updates = &settings["updates"];
+    if updates["ignore-waves"].as_bool().unwrap_or(false) {
+        return Ok(None);
+    }
+
+    let seed = match &updates["seed"] {
+        serde_json::Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+        serde_json::Value::String(s) => s.parse().ok(),
+        _ => None,
+    };
+    seed.map(Some).context(error::MissingSetting {
+        setting: "/settings/updates/seed",
+    })
+}
+
+/// Returns whether the update's wave has reached us, which is always true if we ignore waves.
+fn wave_ready(
+    update: &update_metadata::Update,
+    wave_seed: Option<u32>,
+    now: DateTime<Utc>,
+) -> bool {
+    wave_seed
+        .map(|seed| update.update_ready(seed, now))
+        .unwrap_or(true)
+}
+
 // This is how the UpdateStatus is stored on disk
 impl UpdateStatus {
     /// Initializes the update status
@@ -242,21 +271,14 @@ impl UpdateStatus {
         self.most_recent_command = Some(command_result);
     }
 
-    /// Returns the update information of the 'latest' available update
+    /// Returns the update information of the 'latest' available update.  Updates whose wave
+    /// hasn't reached us yet are skipped, unless `wave_seed` is None, meaning we ignore waves.
     pub fn get_latest_update(
         updates: Vec<update_metadata::Update>,
+        wave_seed: Option<u32>,
     ) -> Result<Option<update_metadata::Update>> {
         let os_info = GilmanosRelease::new().context(error::ReleaseVersion)?;
-        for update in updates {
-            // If the current running version is greater than the max version ever published,
-            // or moves us to a valid version <= the maximum version, update.
-            // Updates are listed in descending order (in terms of versions) in the manifest,
-            // so the first picked out would be the latest update available.
-            if os_info.version_id < update.version || os_info.version_id > update.max_version {
-                return Ok(Some(update));
-            }
-        }
-        Ok(None)
+        Ok(latest_update(updates, &os_info.version_id, wave_seed, Utc::now()))
     }
 
     /// Checks the list of updates to for an available update.
@@ -277,38 +299,149 @@ impl UpdateStatus {
         .context(error::GetSetting {
             setting: "/settings/updates/version-lock",
         })?;
+        let wave_seed = wave_seed(&settings)?;
 
-        if String::from(locked_version.to_owned()) == "latest" {
-            // Set chosen_update to the latest version available
-            if let Some(latest_update) = UpdateStatus::get_latest_update(updates)? {
-                self.chosen_update = Some(UpdateImage {
-                    arch: latest_update.arch,
-                    version: latest_update.version,
-                    variant: latest_update.variant,
-                });
-                return Ok(true);
-            }
-        } else {
-            let chosen_version =
-                FriendlyVersion::try_into(locked_version.to_owned()).context(error::SemVer {
-                    version: locked_version,
-                })?;
-            let os_info = GilmanosRelease::new().context(error::ReleaseVersion)?;
-            if chosen_version != os_info.version_id {
-                for update in &updates {
-                    if update.version == chosen_version {
-                        self.chosen_update = Some(UpdateImage {
-                            arch: update.arch.clone(),
-                            version: chosen_version,
-                            variant: update.variant.clone(),
-                        });
-                        return Ok(true);
-                    }
-                }
-            }
+        let os_info = GilmanosRelease::new().context(error::ReleaseVersion)?;
+        self.chosen_update = choose_update(
+            updates,
+            locked_version,
+            &os_info.version_id,
+            wave_seed,
+            Utc::now(),
+        )?;
+        Ok(self.chosen_update.is_some())
+    }
+}
+
+/// Returns the latest update that's ready for us, given the version we're running.
+fn latest_update(
+    updates: Vec<update_metadata::Update>,
+    running_version: &semver::Version,
+    wave_seed: Option<u32>,
+    now: DateTime<Utc>,
+) -> Option<update_metadata::Update> {
+    for update in updates {
+        if !wave_ready(&update, wave_seed, now) {
+            continue;
+        }
+        // If the current running version is greater than the max version ever published,
+        // or moves us to a valid version <= the maximum version, update.
+        // Updates are listed in descending order (in terms of versions) in the manifest,
+        // so the first picked out would be the latest update available.
+        if *running_version < update.version || *running_version > update.max_version {
+            return Some(update);
         }
-        // 'version-lock'ed update is unavailable.
-        self.chosen_update = None;
-        Ok(false)
     }
-}
\ No newline at end of file
+    None
+}
+
+/// Returns the update to choose given the 'version-lock' setting, or None if the locked version
+/// isn't available, isn't ready for us yet, or is the version we're running.
+fn choose_update(
+    updates: Vec<update_metadata::Update>,
+    locked_version: FriendlyVersion,
+    running_version: &semver::Version,
+    wave_seed: Option<u32>,
+    now: DateTime<Utc>,
+) -> Result<Option<UpdateImage>> {
+    if locked_version.is_latest() {
+        // Choose the latest version available
+        return Ok(
+            latest_update(updates, running_version, wave_seed, now).map(|update| UpdateImage {
+                arch: update.arch,
+                version: update.version,
+                variant: update.variant,
+            }),
+        );
+    }
+
+    let chosen_version =
+        FriendlyVersion::try_into(locked_version.to_owned()).context(error::SemVer {
+            version: locked_version,
+        })?;
+    if chosen_version == *running_version {
+        return Ok(None);
+    }
+    Ok(updates
+        .into_iter()
+        .find(|update| update.version == chosen_version && wave_ready(update, wave_seed, now))
+        .map(|update| UpdateImage {
+            arch: update.arch,
+            version: update.version,
+            variant: update.variant,
+        }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use serde_json::json;
+
+    /// Builds an update to the given version, whose only wave starts at the given time.
+    fn update(version: &str, wave_start: DateTime<Utc>) -> update_metadata::Update {
+        serde_json::from_value(json!({
+            "variant": "aws-k8s",
+            "arch": "x86_64",
+            "version": version,
+            "max_version": version,
+            "waves": {"2048": wave_start},
+            "images": {"boot": "boot", "root": "root", "hash": "hash"},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn ignore_waves_overrides_wave_check() {
+        let now = Utc::now();
+        let later = update("1.1.0", now + Duration::days(1));
+        assert!(!wave_ready(&later, Some(100), now));
+
+        let settings = json!({"updates": {"seed": 100, "ignore-waves": true}});
+        let seed = wave_seed(&settings).unwrap();
+        assert_eq!(seed, None);
+        assert!(wave_ready(&later, seed, now));
+    }
+
+    #[test]
+    fn seed_as_string_or_number() {
+        let number = json!({"updates": {"seed": 100}});
+        assert_eq!(wave_seed(&number).unwrap(), Some(100));
+        let string = json!({"updates": {"seed": "100"}});
+        assert_eq!(wave_seed(&string).unwrap(), Some(100));
+        let ignore_off = json!({"updates": {"seed": "100", "ignore-waves": false}});
+        assert_eq!(wave_seed(&ignore_off).unwrap(), Some(100));
+
+        for bad in &[json!({"updates": {}}), json!({"updates": {"seed": "abc"}})] {
+            wave_seed(bad).unwrap_err();
+        }
+        wave_seed(&json!({"updates": {"seed": -1}})).unwrap_err();
+    }
+
+    #[test]
+    fn locked_version_not_ready() {
+        let now = Utc::now();
+        let running = semver::Version::new(1, 0, 0);
+        let locked = FriendlyVersion::try_from("v1.1.0").unwrap();
+        let updates = || {
+            vec![
+                update("1.2.0", now - Duration::days(1)),
+                update("1.1.0", now + Duration::days(1)),
+            ]
+        };
+
+        // The locked version's wave hasn't reached us, so nothing is chosen, even though a
+        // newer version is ready.
+        let chosen = choose_update(updates(), locked.clone(), &running, Some(100), now);
+        assert!(chosen.unwrap().is_none());
+
+        // Ignoring waves, it's chosen right away.
+        let chosen = choose_update(updates(), locked, &running, None, now).unwrap();
+        assert_eq!(chosen.unwrap().version(), &semver::Version::new(1, 1, 0));
+
+        // "latest" skips to the newest version that's ready.
+        let latest = FriendlyVersion::try_from("latest").unwrap();
+        let chosen = choose_update(updates(), latest, &running, Some(100), now).unwrap();
+        assert_eq!(chosen.unwrap().version(), &semver::Version::new(1, 2, 0));
+    }
+}
@@ -1,10 +1,12 @@
 package main
 
 import (
+	"bufio"
 	"context"
 	"flag"
 	"os"
 	"os/signal"
+	"path/filepath"
 	"regexp"
 	"strings"
 	"syscall"
@@ -29,11 +31,14 @@ func main() {
 
 func _main() int {
 	targetCtr, source := "", ""
+	envFile, mountsFile := "", ""
 	superpowered := false
 
 	flag.StringVar(&targetCtr, "ctr-id", "", "The ID of the container to be started")
 	flag.StringVar(&source, "source", "", "The image to be pulled")
 	flag.BoolVar(&superpowered, "superpowered", false, "Specifies wheter to launch the contaainer in `superpowerd` mode or not")
+	flag.StringVar(&envFile, "env-file", "", "File of KEY=VALUE lines to add to the container's environment")
+	flag.StringVar(&mountsFile, "mounts-file", "", "File of SOURCE:DESTINATION[:ro|:rw] lines to bind mount into the container")
 	flag.Parse()
 
 	if targetCtr == "" || source == "" {
@@ -43,6 +48,23 @@ func _main() int {
 	
 	ctx := namespaces.NamespaceFromEnv(context.Background())
 
+	// Read the per-container settings written by the host-containers settings applier
+	env, err := readLines(envFile)
+	if err != nil {
+		log.G(ctx).WithError(err).WithField("file", envFile).Error("Failed to read container environment")
+		return 1
+	}
+	mountLines, err := readLines(mountsFile)
+	if err != nil {
+		log.G(ctx).WithError(err).WithField("file", mountsFile).Error("Failed to read container mounts")
+		return 1
+	}
+	mounts, err := parseMounts(mountLines)
+	if err != nil {
+		log.G(ctx).WithError(err).WithField("file", mountsFile).Error("Invalid container mounts")
+		return 1
+	}
+
 	// Set up channel on which to send signal notifications.
 	// We must use a buffered channel or risk missing the signal
 	// if we're not ready to receive when the signal is sent.
@@ -74,6 +96,9 @@ func _main() int {
 
 		oci.WithCgroup(cgroupPath),
 		withTharMounts(targetCtr),
+		withPersistentStorage(targetCtr),
+		oci.WithMounts(mounts),
+		oci.WithEnv(env),
 		withSuperpowered(superpowerd),
 	)
 
@@ -191,6 +216,76 @@ func withTharMounts(targetCtr string) oci.SpecOpts {
 	return oci.Compose()
 }
 
+// Persistent storage for each host container lives under /local, so it survives reboots and
+// updates.  The settings applier writes the container's user data there too.
+const persistentStorageDir = "/local/host-containers"
+
+// Where the container sees its persistent storage
+const persistentStorageMount = "/.thar/host-containers/current"
+
+// Mount the container's persistent storage
+func withPersistentStorage(targetCtr string) oci.SpecOpts {
+	return oci.WithMounts([]runtimespec.Mount{
+		{
+			Options:     []string{"rbind", "rw"},
+			Destination: persistentStorageMount,
+			Source:      filepath.Join(persistentStorageDir, targetCtr),
+		},
+	})
+}
+
+// Read the non-empty lines of the given file.  It's fine if the file isn't given or doesn't
+// exist; that means there's nothing to add.
+func readLines(path string) ([]string, error) {
+	if path == "" {
+		return nil, nil
+	}
+	f, err := os.Open(path)
+	if os.IsNotExist(err) {
+		return nil, nil
+	} else if err != nil {
+		return nil, err
+	}
+	defer f.Close()
+
+	var lines []string
+	scanner := bufio.NewScanner(f)
+	for scanner.Scan() {
+		if line := scanner.Text(); line != "" {
+			lines = append(lines, line)
+		}
+	}
+	return lines, scanner.Err()
+}
+
+// Parse bind mounts of the form SOURCE:DESTINATION, optionally followed by :ro or :rw.
+// Mounts are read-write unless given :ro.
+func parseMounts(lines []string) ([]runtimespec.Mount, error) {
+	var mounts []runtimespec.Mount
+	for _, line := range lines {
+		parts := strings.Split(line, ":")
+		if len(parts) != 2 && len(parts) != 3 {
+			return nil, errors.Errorf("Mount '%s' is not of the form SOURCE:DESTINATION[:ro|:rw]", line)
+		}
+		if !filepath.IsAbs(parts[0]) || !filepath.IsAbs(parts[1]) {
+			return nil, errors.Errorf("Mount '%s' must use absolute paths", line)
+		}
+		mode := "rw"
+		if len(parts) == 3 {
+			mode = parts[2]
+		}
+		if mode != "ro" && mode != "rw" {
+			return nil, errors.Errorf("Mount '%s' has invalid mode '%s'", line, mode)
+		}
+		mounts = append(mounts, runtimespec.Mount{
+			Options:     []string{"rbind", mode},
+			Destination: parts[1],
+			Source:      parts[0],
+		})
+	}
+	return mounts, nil
+}
+
 // Add additional container options depending on whether it's `superpowered` or not
 func withSuperpowered(superpowered bool) oci.SpecOpts {
 	if !superpowered {
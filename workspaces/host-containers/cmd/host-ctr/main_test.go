@@ -3,6 +3,8 @@ package main
 import (
 	"github.com/stretchr/testify/asssert"
 	"testing"
+
+	runtimespec "github.com/opencontainers/runtime-spec/specs-go"
 )
 
 func TestECRImageNameToRefValid(t *testing.T) {
@@ -46,3 +48,18 @@ func TestECRImageNameToRefInvalid(t *testing.T) {
 		})
 	}
 }
+
+func TestParseMounts(t *testing.T) {
+	mounts, err := parseMounts([]string{"/var/log:/host/var/log:ro", "/a:/b:rw", "/c:/d"})
+	assert.NoError(t, err)
+	assert.Equal(t, []runtimespec.Mount{
+		{Options: []string{"rbind", "ro"}, Destination: "/host/var/log", Source: "/var/log"},
+		{Options: []string{"rbind", "rw"}, Destination: "/b", Source: "/a"},
+		{Options: []string{"rbind", "rw"}, Destination: "/d", Source: "/c"},
+	}, mounts)
+
+	for _, line := range []string{"", "/a", "a:/b", "/a:b", "/a:/b:rx", "/a:/b:ro:x"} {
+		_, err := parseMounts([]string{line})
+		assert.Error(t, err, line)
+	}
+}
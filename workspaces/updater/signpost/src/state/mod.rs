@@ -155,6 +155,11 @@ impl State {
         &self.sets[self.inactive().idx()]
     }
 
+    /// Returns the partition set that will be booted next, if any is bootable.
+    pub fn next_set(&self) -> Option<&PartitionSet> {
+        self.next().map(|select| &self.sets[select.idx()])
+    }
+
     pub(crate) fn next(&self) -> Option<SetSelect> {
         let gptprio_a = self.gptprio(SetSelect::A);
         let gptprio_b = self.gptprio(SetSelect::B);